@@ -2,8 +2,9 @@
 
 extern crate test;
 
+use merk::chunks::{ChunkConfig, DecodeScratch};
 use merk::proofs::encode_into as encode_proof_into;
-use merk::restore::Restorer;
+use merk::restore::{ChunkVerifier, Restorer};
 use merk::test_utils::*;
 use merk::{Merk, Result};
 use rand::prelude::*;
@@ -38,6 +39,22 @@ fn get_1m_rocksdb(b: &mut Bencher) {
     });
 }
 
+#[bench]
+fn root_hash_1m_rocksdb(b: &mut Bencher) {
+    let initial_size = 1_000_000;
+    let batch_size = 2_000;
+
+    let path = thread::current().name().unwrap().to_owned();
+    let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+    for i in 0..(initial_size / batch_size) {
+        let batch = make_batch_rand(batch_size, i);
+        unsafe { merk.apply_unchecked(&batch, &[]).expect("apply failed") };
+    }
+
+    b.iter(|| test::black_box(merk.root_hash()));
+}
+
 #[bench]
 fn insert_1m_2k_seq_rocksdb_noprune(b: &mut Bencher) {
     let initial_size = 1_000_000;
@@ -176,6 +193,37 @@ fn prove_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
     });
 }
 
+#[bench]
+fn prove_into_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
+    let initial_size = 1_000_000;
+    let batch_size = 1_000;
+    let proof_size = 1;
+
+    let path = thread::current().name().unwrap().to_owned();
+    let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+    for i in 0..(initial_size / batch_size) {
+        let batch = make_batch_rand(batch_size, i);
+        unsafe { merk.apply_unchecked(&batch, &[]).expect("apply failed") };
+    }
+
+    let mut bytes = vec![];
+    let mut i = 0;
+    b.iter(|| {
+        let batch = make_batch_rand(proof_size, i);
+        let mut keys = Vec::with_capacity(batch.len());
+        for (key, _) in batch {
+            keys.push(key);
+        }
+        merk.prove_into(keys.as_slice(), &mut bytes)
+            .expect("prove failed");
+        i = (i + 1) % (initial_size / batch_size);
+
+        merk.commit(std::collections::LinkedList::new(), &[])
+            .unwrap();
+    });
+}
+
 #[bench]
 fn build_trunk_chunk_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
     let initial_size = 1_000_000;
@@ -237,8 +285,47 @@ fn chunkproducer_rand_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
     b.bytes = (total_bytes / i) as u64;
 }
 
+#[bench]
+fn chunkproducer_scratch_rand_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
+    let mut rng = rand::thread_rng();
+
+    let initial_size = 1_000_000;
+    let batch_size = 1_000;
+
+    let path = thread::current().name().unwrap().to_owned();
+    let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+    for i in 0..(initial_size / batch_size) {
+        let batch = make_batch_rand(batch_size, i);
+        unsafe { merk.apply_unchecked(&batch, &[]).expect("apply failed") };
+    }
+
+    let mut chunks = merk.chunks().unwrap();
+    let mut scratch = DecodeScratch::new();
+    let mut total_bytes = 0;
+    let mut i = 0;
+
+    b.iter(|| {
+        let index = rng.gen::<usize>() % chunks.len();
+        let chunk = chunks.chunk_with_scratch(index, &mut scratch).unwrap();
+        total_bytes += chunk.len();
+        i += 1;
+    });
+
+    b.bytes = (total_bytes / i) as u64;
+}
+
 #[bench]
 fn chunk_iter_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
+    chunk_iter_1m_1_rand(b, ChunkConfig::default());
+}
+
+#[bench]
+fn chunk_iter_no_readahead_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
+    chunk_iter_1m_1_rand(b, ChunkConfig { readahead_size: 0 });
+}
+
+fn chunk_iter_1m_1_rand(b: &mut Bencher, config: ChunkConfig) {
     let initial_size = 1_000_000;
     let batch_size = 1_000;
 
@@ -250,14 +337,14 @@ fn chunk_iter_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
         unsafe { merk.apply_unchecked(&batch, &[]).expect("apply failed") };
     }
 
-    let mut chunks = merk.chunks().unwrap().into_iter();
+    let mut chunks = merk.chunks_with_config(config).unwrap().into_iter();
     let mut total_bytes = 0;
     let mut i = 0;
 
     let mut next = || match chunks.next() {
         Some(chunk) => chunk,
         None => {
-            chunks = merk.chunks().unwrap().into_iter();
+            chunks = merk.chunks_with_config(config).unwrap().into_iter();
             chunks.next().unwrap()
         }
     };
@@ -321,6 +408,52 @@ fn restore_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
     b.bytes = (total_bytes / i) as u64;
 }
 
+fn leaf_chunks_1m_rand() -> (ChunkVerifier, Vec<(usize, Vec<u8>)>) {
+    let initial_size = 1_000_000;
+    let batch_size = 1_000;
+
+    let path = thread::current().name().unwrap().to_owned();
+    let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+    for i in 0..(initial_size / batch_size) {
+        let batch = make_batch_rand(batch_size, i);
+        unsafe { merk.apply_unchecked(&batch, &[]).expect("apply failed") };
+    }
+
+    let mut chunks: Vec<_> = merk
+        .chunks()
+        .unwrap()
+        .into_iter()
+        .map(|chunk| chunk.unwrap())
+        .enumerate()
+        .collect();
+    let verifier = ChunkVerifier::new(merk.root_hash(), &chunks[0].1).unwrap();
+    chunks.remove(0);
+
+    (verifier, chunks)
+}
+
+#[bench]
+fn verify_chunks_1m_rand_rocksdb_noprune(b: &mut Bencher) {
+    let (verifier, chunks) = leaf_chunks_1m_rand();
+    let total_bytes: usize = chunks.iter().map(|(_, chunk)| chunk.len()).sum();
+
+    b.iter(|| verifier.verify_all(&chunks).unwrap());
+
+    b.bytes = total_bytes as u64;
+}
+
+#[cfg(feature = "parallel")]
+#[bench]
+fn verify_chunks_parallel_1m_rand_rocksdb_noprune(b: &mut Bencher) {
+    let (verifier, chunks) = leaf_chunks_1m_rand();
+    let total_bytes: usize = chunks.iter().map(|(_, chunk)| chunk.len()).sum();
+
+    b.iter(|| verifier.verify_all_parallel(&chunks).unwrap());
+
+    b.bytes = total_bytes as u64;
+}
+
 #[bench]
 fn checkpoint_create_destroy_1m_1_rand_rocksdb_noprune(b: &mut Bencher) {
     let initial_size = 1_000_000;
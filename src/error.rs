@@ -1,3 +1,19 @@
+use std::fmt;
+
 pub use failure::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+/// The error returned by `Merk::prove_with_deadline` when its deadline passes
+/// before the proof is generated. It can be told apart from other errors with
+/// `err.downcast_ref::<ProofTimeout>()`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProofTimeout;
+
+impl fmt::Display for ProofTimeout {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Proof generation exceeded its deadline")
+    }
+}
+
+impl std::error::Error for ProofTimeout {}
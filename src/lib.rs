@@ -17,8 +17,15 @@ pub mod test_utils;
 /// The core tree data structure.
 pub mod tree;
 
-pub use crate::merk::{chunks, Merk, restore};
+pub use crate::merk::{
+    chunks, restore, ChangeKind, CommitReceipt, ImportProgress, LogConfig, Merk, NodeHooks,
+    ReadCache, ReadOnlyMerk, RootAttestation, ScopedMerk, ScrubConfig, Scrubber, Timings,
+    Transaction, WriteOpts,
+};
 
-pub use error::{Error, Result};
-pub use proofs::verify_query;
-pub use tree::{Batch, BatchEntry, Hash, Op, PanicSource, HASH_LENGTH};
+pub use error::{Error, ProofTimeout, Result};
+pub use proofs::{
+    verify, verify_against_any, verify_any_version, verify_key_projected, verify_nested,
+    verify_query, verify_query_limited,
+};
+pub use tree::{leaf_hash, Batch, BatchEntry, Hash, Op, PanicSource, HASH_LENGTH};
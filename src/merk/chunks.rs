@@ -2,17 +2,126 @@
 //! a Merk.
 
 use super::Merk;
-use crate::proofs::{chunk::get_next_chunk, Node, Op};
+use crate::proofs::{
+    chunk::{chunk_hash, get_next_chunk_into},
+    Node, Op,
+};
 
+use crate::tree::{Hash, HASH_LENGTH};
 use crate::Result;
+use byteorder::{BigEndian, ByteOrder};
 use ed::Encode;
 use failure::bail;
-use rocksdb::DBRawIterator;
+use rocksdb::{DBRawIterator, ReadOptions};
+
+pub use crate::proofs::chunk::DecodeScratch;
+
+/// The default RocksDB readahead size used when producing chunks, in bytes.
+pub const DEFAULT_READAHEAD_SIZE: usize = 2 * 1024 * 1024;
+
+/// Configures a `ChunkProducer`, as passed to `Merk::chunks_with_config`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ChunkConfig {
+    /// The number of bytes RocksDB reads ahead when iterating through the
+    /// tree's nodes. Chunks are produced by reading nodes sequentially, so
+    /// readahead reduces I/O stalls when iterating through chunks in order. A
+    /// value of 0 disables readahead.
+    pub readahead_size: usize,
+}
+
+impl Default for ChunkConfig {
+    fn default() -> Self {
+        ChunkConfig {
+            readahead_size: DEFAULT_READAHEAD_SIZE,
+        }
+    }
+}
+
+/// Describes the chunks produced for a tree, as returned by
+/// `ChunkProducer::manifest`. A serving node can persist the manifest along
+/// with a checkpoint of the store, then after restarting pass both to
+/// `ChunkProducer::from_manifest` to continue serving identical chunks.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Manifest {
+    root_hash: Hash,
+    chunk_count: usize,
+    chunk_boundaries: Vec<Vec<u8>>,
+}
+
+impl Manifest {
+    /// Returns the root hash of the tree the chunks were produced for.
+    pub fn root_hash(&self) -> Hash {
+        self.root_hash
+    }
+
+    /// Returns the total number of chunks.
+    pub fn chunk_count(&self) -> usize {
+        self.chunk_count
+    }
+
+    /// Returns the keys at which the leaf chunks are split.
+    pub fn chunk_boundaries(&self) -> &[Vec<u8>] {
+        self.chunk_boundaries.as_slice()
+    }
+
+    /// Encodes the manifest as bytes: the root hash, the chunk count as a
+    /// 4-byte big-endian integer, then each boundary key prefixed by its
+    /// 1-byte length.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HASH_LENGTH + 4);
+        bytes.extend_from_slice(&self.root_hash);
+        bytes.extend_from_slice(&(self.chunk_count as u32).to_be_bytes());
+        for key in self.chunk_boundaries.iter() {
+            bytes.push(key.len() as u8);
+            bytes.extend_from_slice(key);
+        }
+        bytes
+    }
+
+    /// Decodes a manifest encoded with `Manifest::encode`.
+    pub fn decode(bytes: &[u8]) -> Result<Manifest> {
+        if bytes.len() < HASH_LENGTH + 4 {
+            bail!("Manifest is too short");
+        }
+
+        let mut root_hash = [0; HASH_LENGTH];
+        root_hash.copy_from_slice(&bytes[..HASH_LENGTH]);
+        let chunk_count = BigEndian::read_u32(&bytes[HASH_LENGTH..HASH_LENGTH + 4]) as usize;
+
+        let mut chunk_boundaries = vec![];
+        let mut rest = &bytes[HASH_LENGTH + 4..];
+        while !rest.is_empty() {
+            let len = rest[0] as usize;
+            if rest.len() < len + 1 {
+                bail!("Manifest boundary key is truncated");
+            }
+            chunk_boundaries.push(rest[1..len + 1].to_vec());
+            rest = &rest[len + 1..];
+        }
+
+        let expected_count = if chunk_boundaries.is_empty() {
+            1
+        } else {
+            chunk_boundaries.len() + 2
+        };
+        if chunk_count != expected_count {
+            bail!("Manifest chunk count does not match its boundaries");
+        }
+
+        Ok(Manifest {
+            root_hash,
+            chunk_count,
+            chunk_boundaries,
+        })
+    }
+}
 
 /// A `ChunkProducer` allows the creation of chunk proofs, used for trustlessly
 /// replicating entire Merk trees. Chunks can be generated on the fly in a
 /// random order, or iterated in order for slightly better performance.
 pub struct ChunkProducer<'a> {
+    merk: &'a Merk,
     trunk: Vec<Op>,
     chunk_boundaries: Vec<Vec<u8>>,
     raw_iter: DBRawIterator<'a>,
@@ -23,6 +132,12 @@ impl<'a> ChunkProducer<'a> {
     /// Creates a new `ChunkProducer` for the given `Merk` instance. In the
     /// constructor, the first chunk (the "trunk") will be created.
     pub fn new(merk: &'a Merk) -> Result<Self> {
+        ChunkProducer::with_config(merk, ChunkConfig::default())
+    }
+
+    /// Creates a new `ChunkProducer` for the given `Merk` instance, using the
+    /// given configuration. See `ChunkProducer::new`.
+    pub fn with_config(merk: &'a Merk, config: ChunkConfig) -> Result<Self> {
         let (trunk, has_more) = merk.walk(|maybe_walker| match maybe_walker {
             Some(mut walker) => walker.create_trunk_proof(),
             None => Ok((vec![], false)),
@@ -40,10 +155,15 @@ impl<'a> ChunkProducer<'a> {
             vec![]
         };
 
-        let mut raw_iter = merk.raw_iter();
+        let mut read_opts = ReadOptions::default();
+        if config.readahead_size > 0 {
+            read_opts.set_readahead_size(config.readahead_size);
+        }
+        let mut raw_iter = merk.db.raw_iterator_opt(read_opts);
         raw_iter.seek_to_first();
 
         Ok(ChunkProducer {
+            merk,
             trunk,
             chunk_boundaries,
             raw_iter,
@@ -51,6 +171,36 @@ impl<'a> ChunkProducer<'a> {
         })
     }
 
+    /// Creates a `ChunkProducer` for `merk` which will produce the same chunks
+    /// as the producer `manifest` was taken from, e.g. after reopening the
+    /// checkpoint it was serving from. Errors if `merk` is not at the
+    /// manifest's root hash.
+    ///
+    /// Chunks are only identical for identical trees, so a producer should be
+    /// pinned to a checkpoint (see `Merk::checkpoint`) if it is going to be
+    /// resumed, rather than to a store which keeps being written to.
+    pub fn from_manifest(merk: &'a Merk, manifest: &Manifest) -> Result<Self> {
+        if merk.root_hash() != manifest.root_hash {
+            bail!("Store does not match the manifest's root hash");
+        }
+
+        let producer = ChunkProducer::new(merk)?;
+        if producer.chunk_boundaries != manifest.chunk_boundaries {
+            bail!("Store's chunk boundaries do not match the manifest");
+        }
+        Ok(producer)
+    }
+
+    /// Returns a `Manifest` describing the chunks of this producer, so that it
+    /// can be recreated with `from_manifest`.
+    pub fn manifest(&self) -> Manifest {
+        Manifest {
+            root_hash: self.merk.root_hash(),
+            chunk_count: self.len(),
+            chunk_boundaries: self.chunk_boundaries.clone(),
+        }
+    }
+
     /// Gets the chunk with the given index. Errors if the index is out of
     /// bounds - the number of chunks can be checked by calling
     /// `producer.len()`.
@@ -72,6 +222,30 @@ impl<'a> ChunkProducer<'a> {
         self.next_chunk()
     }
 
+    /// Gets the hash of the chunk with the given index, as computed by
+    /// `restore::verify_chunk_hash` on the receiving side. Errors if the index
+    /// is out of bounds.
+    pub fn chunk_hash(&mut self, index: usize) -> Result<Hash> {
+        Ok(chunk_hash(self.chunk(index)?.as_slice()))
+    }
+
+    /// Consumes the `ChunkProducer` and returns an iterator which yields the
+    /// trunk chunk followed by the leaf chunks in descending order (from the
+    /// last index down to 1), along with their indexes, e.g. to replicate the
+    /// highest keys first.
+    ///
+    /// The trunk is still yielded first since the other chunks can't be
+    /// verified without it. Each leaf chunk is produced by seeking the raw
+    /// iterator to the start of the chunk, so this is about as fast as random
+    /// access with `chunk`. The chunks should be passed to
+    /// `Restorer::process_chunk_at`.
+    pub fn into_rev_iter(self) -> RevChunkIter<'a> {
+        RevChunkIter {
+            next_index: Some(0),
+            producer: self,
+        }
+    }
+
     /// Returns the total number of chunks for the underlying Merk tree.
     pub fn len(&self) -> usize {
         let boundaries_len = self.chunk_boundaries.len();
@@ -82,13 +256,56 @@ impl<'a> ChunkProducer<'a> {
         }
     }
 
+    /// Gets the chunk with the given index like `chunk`, but builds it using
+    /// the buffers in `scratch` and returns the encoded chunk borrowed from
+    /// it. Reusing one `DecodeScratch` across many calls avoids allocating for
+    /// each node in each chunk.
+    ///
+    /// If `index` is the chunk after the one most recently produced, the raw
+    /// iterator is already in position and no seek is needed, so gets over
+    /// every index in order are as fast as `ChunkIter`.
+    pub fn chunk_with_scratch<'s>(
+        &mut self,
+        index: usize,
+        scratch: &'s mut DecodeScratch,
+    ) -> Result<&'s [u8]> {
+        if index >= self.len() {
+            bail!("Chunk index out-of-bounds");
+        }
+
+        if index != self.index {
+            self.index = index;
+
+            if index == 0 || index == 1 {
+                self.raw_iter.seek_to_first();
+            } else {
+                let preceding_key = self.chunk_boundaries.get(index - 2).unwrap();
+                self.raw_iter.seek(preceding_key);
+                self.raw_iter.next();
+            }
+        }
+
+        self.next_chunk_into(scratch)?;
+        Ok(scratch.bytes.as_slice())
+    }
+
     /// Gets the next chunk based on the `ChunkProducer`'s internal index state.
     /// This is mostly useful for letting `ChunkIter` yield the chunks in order,
     /// optimizing throughput compared to random access.
     fn next_chunk(&mut self) -> Result<Vec<u8>> {
+        let mut scratch = DecodeScratch::new();
+        self.next_chunk_into(&mut scratch)?;
+        Ok(scratch.bytes)
+    }
+
+    /// Gets the next chunk like `next_chunk`, writing the encoded chunk into
+    /// `scratch.bytes`.
+    fn next_chunk_into(&mut self, scratch: &mut DecodeScratch) -> Result<()> {
         if self.index == 0 {
             self.index += 1;
-            return self.trunk.encode();
+            scratch.clear();
+            self.trunk.encode_into(&mut scratch.bytes)?;
+            return Ok(());
         }
 
         if self.index >= self.len() {
@@ -100,8 +317,20 @@ impl<'a> ChunkProducer<'a> {
 
         self.index += 1;
 
-        let chunk = get_next_chunk(&mut self.raw_iter, end_key_slice)?;
-        chunk.encode()
+        get_next_chunk_into(&mut self.raw_iter, end_key_slice, scratch)?;
+
+        // nodes for values stored outside of the tree have empty values
+        for index in scratch.external.iter() {
+            if let Op::Push(Node::KV(key, value)) = &mut scratch.ops[*index] {
+                match self.merk.fetch_external_value(key)? {
+                    None => bail!("Missing external value for node {:?}", key),
+                    Some(external_value) => *value = external_value,
+                }
+            }
+        }
+
+        scratch.ops.encode_into(&mut scratch.bytes)?;
+        Ok(())
     }
 }
 
@@ -135,12 +364,192 @@ impl<'a> Iterator for ChunkIter<'a> {
     }
 }
 
+/// A `RevChunkIter` yields the trunk chunk, then the leaf chunks for the
+/// underlying `Merk` instance in descending order. See
+/// `ChunkProducer::into_rev_iter`.
+pub struct RevChunkIter<'a> {
+    producer: ChunkProducer<'a>,
+    next_index: Option<usize>,
+}
+
+impl<'a> Iterator for RevChunkIter<'a> {
+    type Item = Result<(usize, Vec<u8>)>;
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let remaining = match self.next_index {
+            None => 0,
+            Some(0) => self.producer.len(),
+            Some(index) => index,
+        };
+        (remaining, Some(remaining))
+    }
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let index = self.next_index?;
+        self.next_index = match index {
+            0 if self.producer.len() > 1 => Some(self.producer.len() - 1),
+            0 | 1 => None,
+            _ => Some(index - 1),
+        };
+
+        Some(self.producer.chunk(index).map(|chunk| (index, chunk)))
+    }
+}
+
+impl<'a> ChunkIter<'a> {
+    /// Coalesces consecutive leaf chunks into merged chunks of at most
+    /// `target_size` bytes, so that medium-sized trees can be transferred in
+    /// fewer, larger chunks. A leaf chunk which is larger than the target on
+    /// its own is yielded as a merged chunk of just that chunk. The trunk
+    /// chunk is still yielded on its own as the first item.
+    ///
+    /// Each merged chunk is a sequence of leaf chunks, each prefixed with its
+    /// length as a big-endian `u32`. Merged chunks should be passed to
+    /// `Restorer::process_merged_chunk`.
+    pub fn merged(self, target_size: usize) -> MergedChunkIter<'a> {
+        MergedChunkIter {
+            inner: self,
+            target_size,
+            pending: None,
+        }
+    }
+}
+
+/// A `MergedChunkIter` iterates through the trunk chunk followed by merged
+/// leaf chunks. See `ChunkIter::merged`.
+pub struct MergedChunkIter<'a> {
+    inner: ChunkIter<'a>,
+    target_size: usize,
+    /// A chunk which would have pushed the previous merged chunk past the
+    /// target size, to start the next one.
+    pending: Option<Vec<u8>>,
+}
+
+impl<'a> MergedChunkIter<'a> {
+    /// Returns the number of chunks before merging, which is the
+    /// `stated_length` expected by `Restorer`.
+    pub fn unmerged_len(&self) -> usize {
+        self.inner.0.len()
+    }
+}
+
+impl<'a> Iterator for MergedChunkIter<'a> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.inner.0.index == 0 {
+            return self.inner.next();
+        }
+
+        let mut merged = vec![];
+        loop {
+            let chunk = match self.pending.take().map(Ok).or_else(|| self.inner.next()) {
+                None => break,
+                Some(Err(err)) => return Some(Err(err)),
+                Some(Ok(chunk)) => chunk,
+            };
+            if !merged.is_empty() && merged.len() + 4 + chunk.len() > self.target_size {
+                self.pending = Some(chunk);
+                break;
+            }
+
+            let mut length = [0; 4];
+            BigEndian::write_u32(&mut length, chunk.len() as u32);
+            merged.extend_from_slice(&length);
+            merged.extend_from_slice(chunk.as_slice());
+        }
+
+        if merged.is_empty() {
+            None
+        } else {
+            Some(Ok(merged))
+        }
+    }
+}
+
+/// Splits a merged chunk (as created by `MergedChunkIter`) back into its
+/// individual leaf chunks.
+pub(crate) fn split_merged_chunk(bytes: &[u8]) -> Result<Vec<&[u8]>> {
+    let mut chunks = vec![];
+    let mut offset = 0;
+    while offset < bytes.len() {
+        if bytes.len() - offset < 4 {
+            bail!("Unexpected end of merged chunk");
+        }
+        let length = BigEndian::read_u32(&bytes[offset..]) as usize;
+        offset += 4;
+
+        if bytes.len() - offset < length {
+            bail!("Unexpected end of merged chunk");
+        }
+        chunks.push(&bytes[offset..offset + length]);
+        offset += length;
+    }
+
+    Ok(chunks)
+}
+
 impl Merk {
     /// Creates a `ChunkProducer` which can return chunk proofs for replicating
     /// the entire Merk tree.
     pub fn chunks(&self) -> Result<ChunkProducer> {
         ChunkProducer::new(self)
     }
+
+    /// Creates a `ChunkProducer` like `chunks`, using the given configuration
+    /// (e.g. to tune the readahead size for sequential chunk iteration).
+    pub fn chunks_with_config(&self, config: ChunkConfig) -> Result<ChunkProducer> {
+        ChunkProducer::with_config(self, config)
+    }
+
+    /// Splits the keyspace into up to `n` contiguous ranges holding roughly
+    /// equal numbers of keys, e.g. for scanning the tree with `n` parallel
+    /// workers. Each range is returned as an inclusive start key and an
+    /// exclusive end key, where `None` is unbounded, so together the ranges
+    /// cover every key exactly once. The split is deterministic for a given
+    /// tree.
+    ///
+    /// The ranges are split at the chunk boundaries (the keys of the trunk),
+    /// which divide the tree into subtrees of similar height but not
+    /// necessarily of equal size, so the ranges are only approximately even.
+    /// Trees too small to have leaf chunks are split by scanning their keys.
+    /// Fewer than `n` ranges are returned if there are not enough boundaries
+    /// to split at. Errors if `n` is 0.
+    pub fn split_ranges(&self, n: usize) -> Result<Vec<(Option<Vec<u8>>, Option<Vec<u8>>)>> {
+        if n == 0 {
+            bail!("Cannot split into 0 ranges");
+        }
+
+        let mut candidates = self.chunks()?.chunk_boundaries;
+        if candidates.is_empty() {
+            let mut iter = self.raw_iter();
+            iter.seek_to_first();
+            while iter.valid() {
+                candidates.push(iter.key().unwrap().to_vec());
+                iter.next();
+            }
+            iter.status()?;
+            // the first key can't start a range without leaving an empty one
+            // before it
+            if !candidates.is_empty() {
+                candidates.remove(0);
+            }
+        }
+
+        // pick `count - 1` evenly spaced split keys from the candidates
+        let buckets = candidates.len() + 1;
+        let count = n.min(buckets);
+        let mut ranges = Vec::with_capacity(count);
+        let mut start = None;
+        for i in 1..count {
+            let split = candidates[i * buckets / count - 1].clone();
+            ranges.push((start, Some(split.clone())));
+            start = Some(split);
+        }
+        ranges.push((start, None));
+
+        Ok(ranges)
+    }
 }
 
 #[cfg(test)]
@@ -154,6 +563,42 @@ mod tests {
         test_utils::*,
     };
 
+    #[test]
+    fn split_ranges() {
+        use std::ops::Bound;
+
+        let mut merk = TempMerk::new().unwrap();
+        assert_eq!(merk.split_ranges(4).unwrap(), vec![(None, None)]);
+        assert!(merk.split_ranges(0).is_err());
+
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        assert_eq!(merk.split_ranges(20).unwrap().len(), 10);
+        let ranges = merk.split_ranges(2).unwrap();
+        assert_eq!(
+            ranges,
+            vec![(None, Some(seq_key(5))), (Some(seq_key(5)), None)]
+        );
+
+        merk.apply(&make_batch_seq(10..10_000), &[]).unwrap();
+        let ranges = merk.split_ranges(4).unwrap();
+        assert_eq!(ranges.len(), 4);
+        assert_eq!(ranges[0].0, None);
+        assert_eq!(ranges[3].1, None);
+        let mut total = 0;
+        for (i, (start, end)) in ranges.iter().enumerate() {
+            if i > 0 {
+                assert_eq!(*start, ranges[i - 1].1);
+            }
+            let start = start.clone().map_or(Bound::Unbounded, Bound::Included);
+            let end = end.clone().map_or(Bound::Unbounded, Bound::Excluded);
+            let count = merk.count_range_iter((start, end)).unwrap();
+            assert!(count > 1_500 && count < 3_500);
+            total += count;
+        }
+        assert_eq!(total, 10_000);
+        assert_eq!(merk.split_ranges(4).unwrap(), ranges);
+    }
+
     #[test]
     fn len_small() {
         let mut merk = TempMerk::new().unwrap();
@@ -244,6 +689,39 @@ mod tests {
         std::fs::remove_dir_all(&path).unwrap();
     }
 
+    #[test]
+    fn resume_from_manifest() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(make_batch_seq(1..10_000).as_slice(), &[])
+            .unwrap();
+
+        let path: std::path::PathBuf = "resume_from_manifest_checkpoint.db".into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+        let checkpoint = merk.checkpoint(&path).unwrap();
+        let producer = checkpoint.chunks().unwrap();
+        let manifest = producer.manifest();
+        let bytes = manifest.encode();
+        let chunks: Vec<_> = producer.into_iter().map(Result::unwrap).collect();
+        assert_eq!(manifest.chunk_count(), chunks.len());
+        assert!(manifest.chunk_count() > 1);
+        drop(checkpoint);
+
+        merk.apply(make_batch_seq(10_000..10_100).as_slice(), &[])
+            .unwrap();
+        let manifest = Manifest::decode(&bytes).unwrap();
+        assert!(ChunkProducer::from_manifest(&merk, &manifest).is_err());
+
+        let checkpoint = Merk::open(&path).unwrap();
+        let producer = ChunkProducer::from_manifest(&checkpoint, &manifest).unwrap();
+        let resumed: Vec<_> = producer.into_iter().map(Result::unwrap).collect();
+        assert_eq!(resumed, chunks);
+
+        assert!(Manifest::decode(&bytes[..bytes.len() - 1]).is_err());
+        checkpoint.destroy().unwrap();
+    }
+
     #[test]
     fn random_access_chunks() {
         let mut merk = TempMerk::new().unwrap();
@@ -263,4 +741,125 @@ mod tests {
             assert_eq!(producer.chunk(index).unwrap(), chunks[index]);
         }
     }
+
+    #[test]
+    fn chunks_with_scratch() {
+        let mut merk = TempMerk::new().unwrap();
+        let batch = make_batch_seq(1..111);
+        merk.apply(batch.as_slice(), &[]).unwrap();
+
+        let chunks = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect::<Vec<_>>();
+
+        let mut producer = merk.chunks().unwrap();
+        let mut scratch = DecodeScratch::new();
+        for (index, chunk) in chunks.iter().enumerate() {
+            assert_eq!(
+                producer.chunk_with_scratch(index, &mut scratch).unwrap(),
+                &chunk[..]
+            );
+        }
+        for index in (0..chunks.len()).rev() {
+            assert_eq!(
+                producer.chunk_with_scratch(index, &mut scratch).unwrap(),
+                &chunks[index][..]
+            );
+        }
+        assert!(producer
+            .chunk_with_scratch(chunks.len(), &mut scratch)
+            .is_err());
+    }
+
+    #[test]
+    fn rev_chunks() {
+        let mut merk = TempMerk::new().unwrap();
+        let batch = make_batch_seq(1..10_000);
+        merk.apply(batch.as_slice(), &[]).unwrap();
+
+        let chunks: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        let rev_iter = merk.chunks().unwrap().into_rev_iter();
+        assert_eq!(rev_iter.size_hint().0, chunks.len());
+        let rev: Vec<_> = rev_iter.map(Result::unwrap).collect();
+        assert_eq!(rev.len(), chunks.len());
+        assert_eq!(rev[0], (0, chunks[0].clone()));
+        for (i, (index, chunk)) in rev[1..].iter().enumerate() {
+            assert_eq!(*index, chunks.len() - 1 - i);
+            assert_eq!(chunk, &chunks[*index]);
+        }
+
+        // a tree with only a trunk yields just the trunk
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(1..10), &[]).unwrap();
+        let rev: Vec<_> = merk.chunks().unwrap().into_rev_iter().collect();
+        assert_eq!(rev.len(), 1);
+    }
+
+    #[test]
+    fn chunks_without_readahead() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(1..10_000), &[]).unwrap();
+
+        let config = ChunkConfig { readahead_size: 0 };
+        let chunks = merk.chunks_with_config(config).unwrap().into_iter();
+        let default_chunks = merk.chunks().unwrap().into_iter();
+        for (chunk, default_chunk) in chunks.zip(default_chunks) {
+            assert_eq!(chunk.unwrap(), default_chunk.unwrap());
+        }
+    }
+
+    #[test]
+    fn merged_chunks() {
+        let mut merk = TempMerk::new().unwrap();
+        let batch = make_batch_seq(1..10_000);
+        merk.apply(batch.as_slice(), &[]).unwrap();
+
+        let chunks: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        let merged_iter = merk.chunks().unwrap().into_iter().merged(64 * 1024);
+        assert_eq!(merged_iter.unmerged_len(), chunks.len());
+        let merged: Vec<_> = merged_iter.map(Result::unwrap).collect();
+        assert!(merged.len() < chunks.len());
+        assert_eq!(merged[0], chunks[0]);
+
+        let split: Vec<_> = merged[1..]
+            .iter()
+            .flat_map(|chunk| split_merged_chunk(chunk).unwrap())
+            .map(|chunk| chunk.to_vec())
+            .collect();
+        assert_eq!(split.as_slice(), &chunks[1..]);
+        for chunk in merged[1..].iter() {
+            let parts = split_merged_chunk(chunk).unwrap().len();
+            assert!(chunk.len() <= 64 * 1024 || parts == 1);
+        }
+
+        assert!(split_merged_chunk(&merged[1][..merged[1].len() - 1]).is_err());
+
+        // chunks larger than the target are yielded on their own
+        let merged: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .merged(1)
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(merged.len(), chunks.len());
+        for (merged, chunk) in merged[1..].iter().zip(chunks[1..].iter()) {
+            assert_eq!(split_merged_chunk(merged).unwrap(), vec![chunk.as_slice()]);
+        }
+    }
 }
@@ -0,0 +1,128 @@
+//! Provides `Merk::prove_with_deadline`, which bounds the time spent
+//! generating a proof.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use failure::bail;
+
+use super::{Merk, MerkSource};
+use crate::error::ProofTimeout;
+use crate::proofs::{encode_into, Query};
+use crate::tree::{Fetch, Link, RefWalker, Tree};
+use crate::Result;
+
+/// The number of node fetches between checks of the deadline.
+const DEADLINE_CHECK_INTERVAL: usize = 16;
+
+/// A `Fetch` source which fails with `ProofTimeout` once its deadline has
+/// passed, checking the clock every `DEADLINE_CHECK_INTERVAL` fetches.
+#[derive(Clone)]
+struct DeadlineSource<'a> {
+    source: MerkSource<'a>,
+    deadline: Instant,
+    fetches: &'a AtomicUsize,
+}
+
+impl<'a> Fetch for DeadlineSource<'a> {
+    fn fetch(&self, link: &Link) -> Result<Tree> {
+        let fetches = self.fetches.fetch_add(1, Ordering::Relaxed);
+        if fetches % DEADLINE_CHECK_INTERVAL == 0 && Instant::now() >= self.deadline {
+            return Err(ProofTimeout.into());
+        }
+        self.source.fetch(link)
+    }
+}
+
+impl Merk {
+    /// Creates a Merkle proof for `query` like `prove_query`, but aborts with
+    /// a `ProofTimeout` error if `deadline` passes before the proof is
+    /// generated.
+    ///
+    /// Nearly all of the time spent on a large proof goes to reading pruned
+    /// nodes from disk, so the deadline is checked before starting and then
+    /// every few node reads, rather than for every node visited. A proof
+    /// which only visits nodes already in memory can overrun the deadline by
+    /// the time taken to walk them. Nodes loaded before a timeout stay in
+    /// memory, so retrying the query is faster.
+    pub fn prove_with_deadline(&self, query: &Query, deadline: Instant) -> Result<Vec<u8>> {
+        if Instant::now() >= deadline {
+            return Err(ProofTimeout.into());
+        }
+
+        let fetches = AtomicUsize::new(1);
+        let source = DeadlineSource {
+            source: self.source(),
+            deadline,
+            fetches: &fetches,
+        };
+
+        self.use_tree_mut(|maybe_tree| {
+            let tree = match maybe_tree {
+                None => bail!("Cannot create proof for empty tree"),
+                Some(tree) => tree,
+            };
+
+            let mut ref_walker = RefWalker::new(tree, source.clone());
+            let (proof, _) = ref_walker.create_query_proof(query.items())?;
+
+            let mut bytes = Vec::with_capacity(128);
+            encode_into(proof.iter(), &mut bytes);
+            Ok(bytes)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::{Duration, Instant};
+
+    use super::DeadlineSource;
+    use crate::error::ProofTimeout;
+    use crate::proofs::{Query, QueryItem};
+    use crate::test_utils::*;
+    use crate::tree::{Fetch, Link, NULL_HASH};
+    use crate::Merk;
+
+    #[test]
+    fn prove_with_deadline() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..1000), &[]).unwrap();
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        let mut query = Query::new();
+        query.insert_item(QueryItem::Range(seq_key(100)..seq_key(900)));
+
+        let err = merk
+            .prove_with_deadline(&query, Instant::now())
+            .unwrap_err();
+        assert_eq!(err.downcast_ref::<ProofTimeout>(), Some(&ProofTimeout));
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let proof = merk.prove_with_deadline(&query, deadline).unwrap();
+        assert_eq!(proof, merk.prove_query(&query).unwrap());
+
+        // the deadline is checked every few fetches
+        let fetches = AtomicUsize::new(1);
+        let source = DeadlineSource {
+            source: merk.source(),
+            deadline: Instant::now(),
+            fetches: &fetches,
+        };
+        let link = Link::Reference {
+            key: seq_key(5),
+            hash: NULL_HASH,
+            child_heights: (0, 0),
+        };
+        for _ in 1..super::DEADLINE_CHECK_INTERVAL {
+            source.fetch(&link).unwrap();
+        }
+        let err = source.fetch(&link).unwrap_err();
+        assert!(err.downcast_ref::<ProofTimeout>().is_some());
+
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! Provides `Merk::apply_at_height` and `Merk::root_at`, which maintain a
+//! mapping from heights (e.g. block heights) to the root hash committed at
+//! each one, so light clients can cross-check a served root against a header.
+//! Heights are recorded in the same mapping as the versions retained by
+//! `Merk::retain_version`.
+
+use std::convert::TryInto;
+
+use failure::bail;
+
+use super::versions::version_key;
+use super::{check_batch, ApplyOpts, Merk, INTERNAL_CF_NAME};
+use crate::tree::{Batch, Hash, HASH_LENGTH};
+use crate::Result;
+
+/// A root hash recorded for a height, along with a signature over
+/// `RootAttestation::message`, as returned by `Merk::attest_root_at`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RootAttestation {
+    /// The height the root hash was committed at.
+    pub height: u64,
+    /// The root hash recorded for the height.
+    pub root_hash: Hash,
+    /// The signature produced by the signer passed to `attest_root_at`.
+    pub signature: Vec<u8>,
+}
+
+impl RootAttestation {
+    /// Returns the bytes which are signed for the given height and root hash:
+    /// `b"merk-root"`, followed by the height as 8 big-endian bytes, followed
+    /// by the root hash. Verifiers rebuild this message to check the
+    /// signature with their own signature scheme.
+    pub fn message(height: u64, root_hash: &Hash) -> Vec<u8> {
+        let mut message = b"merk-root".to_vec();
+        message.extend_from_slice(&height.to_be_bytes());
+        message.extend_from_slice(root_hash);
+        message
+    }
+}
+
+impl Merk {
+    /// Applies a batch of operations to the tree like `apply`, and records the
+    /// resulting root hash as the root at `height`, atomically with the
+    /// commit. Within a write group, the record is written when the group
+    /// ends.
+    ///
+    /// Errors if a root is already recorded for `height` (including by an
+    /// earlier apply in the current write group, or by `retain_version`).
+    /// Records take a few dozen bytes each, and can be pruned with
+    /// `release_version`.
+    ///
+    /// This will fail if the keys in `batch` are not sorted and unique.
+    pub fn apply_at_height(&mut self, height: u64, batch: &Batch, aux: &Batch) -> Result<()> {
+        check_batch(batch)?;
+        let pending = self
+            .write_group
+            .as_ref()
+            .map_or(false, |group| group.heights.contains(&height));
+        if pending || self.root_at(height)?.is_some() {
+            bail!("A root is already recorded for height {}", height);
+        }
+
+        let opts = ApplyOpts {
+            height: Some(height),
+            ..Default::default()
+        };
+        unsafe { self.apply_unchecked_opts(batch, aux, opts)? };
+        Ok(())
+    }
+
+    /// Returns the root hash committed at `height` by `apply_at_height` (or
+    /// retained as version `height` by `retain_version`), or `None` if no
+    /// root was recorded for it.
+    ///
+    /// Roots recorded within an active write group can't be read until the
+    /// group ends.
+    pub fn root_at(&self, height: u64) -> Result<Option<Hash>> {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let root_hash = match self.db.get_pinned_cf(internal_cf, version_key(height))? {
+            None => return Ok(None),
+            Some(root_hash) => root_hash,
+        };
+        if root_hash.len() != HASH_LENGTH {
+            bail!("Invalid version record");
+        }
+        Ok(Some(root_hash[..].try_into().unwrap()))
+    }
+
+    /// Returns the root hash committed at `height`, like `root_at`, signed by
+    /// `sign` (which is passed `RootAttestation::message` for the height and
+    /// root hash). Returns `None` without calling `sign` if no root was
+    /// recorded for `height`.
+    pub fn attest_root_at<F>(&self, height: u64, sign: F) -> Result<Option<RootAttestation>>
+    where
+        F: FnOnce(&[u8]) -> Result<Vec<u8>>,
+    {
+        let root_hash = match self.root_at(height)? {
+            None => return Ok(None),
+            Some(root_hash) => root_hash,
+        };
+        let signature = sign(&RootAttestation::message(height, &root_hash))?;
+        Ok(Some(RootAttestation {
+            height,
+            root_hash,
+            signature,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RootAttestation;
+    use crate::test_utils::*;
+    use crate::{Merk, Op};
+
+    #[test]
+    fn root_at() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.root_at(1).unwrap(), None);
+
+        merk.apply_at_height(1, &make_batch_seq(0..10), &[])
+            .unwrap();
+        let root_1 = merk.root_hash();
+        merk.apply(&make_batch_seq(10..20), &[]).unwrap();
+        merk.apply_at_height(3, &make_batch_seq(20..30), &[])
+            .unwrap();
+        let root_3 = merk.root_hash();
+
+        assert_eq!(merk.root_at(1).unwrap(), Some(root_1));
+        assert_eq!(merk.root_at(2).unwrap(), None);
+        assert_eq!(merk.root_at(3).unwrap(), Some(root_3));
+
+        // heights can't be recorded twice, and a failed commit records nothing
+        assert!(merk.apply_at_height(3, &[], &[]).is_err());
+        let batch = [(seq_key(100), Op::Delete)];
+        assert!(merk.apply_at_height(4, &batch, &[]).is_err());
+        assert_eq!(merk.root_at(4).unwrap(), None);
+
+        let attestation = merk
+            .attest_root_at(3, |message| Ok(message.iter().rev().cloned().collect()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(attestation.root_hash, root_3);
+        let mut message = RootAttestation::message(3, &root_3);
+        message.reverse();
+        assert_eq!(attestation.signature, message);
+        assert!(merk
+            .attest_root_at(2, |_| unreachable!())
+            .unwrap()
+            .is_none());
+        drop(merk);
+
+        let mut merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.root_at(1).unwrap(), Some(root_1));
+
+        // a height recorded twice within a write group is caught
+        merk.begin_write_group().unwrap();
+        merk.apply_at_height(5, &make_batch_seq(30..40), &[])
+            .unwrap();
+        assert!(merk.apply_at_height(5, &[], &[]).is_err());
+        merk.end_write_group().unwrap();
+        let root_5 = merk.root_hash();
+        assert_eq!(merk.root_at(5).unwrap(), Some(root_5));
+
+        // heights and versions share one mapping
+        assert!(merk.retain_version(3).is_err());
+        merk.retain_version(5).unwrap();
+        assert_eq!(merk.retained_roots().unwrap(), vec![(5, root_5)]);
+        merk.retain_version(6).unwrap();
+        assert_eq!(merk.root_at(6).unwrap(), Some(root_5));
+        assert!(merk.apply_at_height(6, &[], &[]).is_err());
+        merk.release_version(1).unwrap();
+        assert_eq!(merk.root_at(1).unwrap(), None);
+
+        merk.destroy().unwrap();
+        std::fs::remove_dir_all(format!("{}.versions", path)).unwrap();
+    }
+}
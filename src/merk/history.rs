@@ -0,0 +1,269 @@
+//! Provides history mode (`Merk::open_with_history`), in which each key keeps
+//! a bounded list of its prior values, committed to by the root hash.
+
+use std::path::Path;
+
+use failure::bail;
+use rocksdb::WriteBatch;
+
+use super::{Merk, INTERNAL_CF_NAME};
+use crate::proofs::{decode_history_value, encode_history_value};
+use crate::tree::{Batch, Op};
+use crate::Result;
+
+/// The key in the internal column family which marks a store as created in
+/// history mode.
+const HISTORY_MODE_KEY: &[u8] = b"history";
+
+/// The maximum length of a stored value (including its history), since value
+/// lengths are hashed as 2 bytes.
+pub(super) const MAX_VALUE_LEN: usize = 65535;
+
+/// Returns whether the store in `db` was created in history mode.
+pub(super) fn is_history_store(db: &rocksdb::DB) -> Result<bool> {
+    let internal_cf = db.cf_handle(INTERNAL_CF_NAME).unwrap();
+    Ok(db.get_pinned_cf(internal_cf, HISTORY_MODE_KEY)?.is_some())
+}
+
+impl Merk {
+    /// Opens a store with the specified file path and options in history
+    /// mode, where each put to an existing key keeps the key's previous value
+    /// in a history list of up to `max_history` values, evicting the oldest.
+    /// If no store exists at that path, one will be created.
+    ///
+    /// The history is stored in the tree along with the current value, so it
+    /// is committed to by the root hash and can be proven with
+    /// `prove_history`. `get` still returns only the current value, but the
+    /// values in proofs and chunks (and from `prove`) are the encoded value
+    /// and history, which can be decoded with
+    /// `merk::proofs::decode_history_value`. Deleting a key removes its
+    /// history along with it.
+    ///
+    /// A store created in history mode must always be opened in history mode:
+    /// the mode is recorded when the store is created, and opening it with
+    /// any other method errors. Likewise, a non-empty store which was not
+    /// created in history mode can't be opened with this method. History is
+    /// carried over by `apply` and its variants (including for the counter
+    /// values resolved from `apply_merge` deltas when they are folded in), and
+    /// by the previews `apply_batch_bytes` and `staged_root_hash`.
+    ///
+    /// Since stored values are limited to 65535 bytes, the oldest entries of a
+    /// key's history are evicted early when the value and its history would
+    /// not fit, so `max_history` should be chosen based on the typical value
+    /// size. Puts of values which don't fit even without any history error.
+    pub fn open_with_history<P>(
+        path: P,
+        db_opts: rocksdb::Options,
+        max_history: usize,
+    ) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+    {
+        Merk::open_cfs(path, db_opts, vec![], Some(max_history))
+    }
+
+    /// Checks the mode the store was created in against the mode it is being
+    /// opened in, recording history mode for new stores opened in it.
+    pub(super) fn check_history_mode(&mut self) -> Result<()> {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let marked = is_history_store(&self.db)?;
+        match (marked, self.history_len.is_some()) {
+            (true, false) => bail!("Store was created in history mode, use open_with_history"),
+            (false, true) => {
+                if self.use_tree(|tree| tree.is_some()) {
+                    bail!("Store was not created in history mode");
+                }
+                let mut batch = WriteBatch::default();
+                batch.put_cf(internal_cf, HISTORY_MODE_KEY, [1]);
+                self.write(batch)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Opens the store at `path` (e.g. a checkpoint of this store) in the same
+    /// mode as this one.
+    pub(super) fn open_in_same_mode<P: AsRef<Path>>(&self, path: P) -> Result<Merk> {
+        match self.history_len {
+            None => Merk::open(path),
+            Some(max_len) => Merk::open_with_history(path, Merk::default_db_opts(), max_len),
+        }
+    }
+
+    /// Gets the prior values of `key`, newest first, or `None` if the key is
+    /// not found. Outside of history mode, keys have no history so this
+    /// always returns an empty list for existing keys.
+    pub fn get_history(&self, key: &[u8]) -> Result<Option<Vec<Vec<u8>>>> {
+        if self.history_len.is_none() {
+            return Ok(self.get_stored(key)?.map(|_| vec![]));
+        }
+
+        self.get_stored(key)?
+            .map(|bytes| Ok(decode_history_value(&bytes)?.1))
+            .transpose()
+    }
+
+    /// Creates a Merkle proof of the current value and history of `key` (or
+    /// of its absence), to be verified with `merk::proofs::verify_history`.
+    pub fn prove_history(&self, key: &[u8]) -> Result<Vec<u8>> {
+        self.prove(&[key.to_vec()])
+    }
+
+    /// Maps the puts in `batch` to values which include each key's history,
+    /// pushing the key's current value (if any) onto the front of it and
+    /// evicting entries which don't fit.
+    pub(crate) fn fold_history(&self, batch: &Batch, max_len: usize) -> Result<Vec<(Vec<u8>, Op)>> {
+        let mut folded = Vec::with_capacity(batch.len());
+        for (key, op) in batch.iter() {
+            let op = match op {
+                Op::Delete => Op::Delete,
+                Op::Put(value) => {
+                    let mut history = match self.get_stored(key)? {
+                        None => vec![],
+                        Some(bytes) => {
+                            let (current, mut history) = decode_history_value(&bytes)?;
+                            history.insert(0, current);
+                            history.truncate(max_len);
+                            history
+                        }
+                    };
+
+                    // evict the oldest entries until the encoding fits
+                    let entry_len = |entry: &[u8]| 4 + entry.len();
+                    let mut len =
+                        entry_len(value) + history.iter().map(|e| entry_len(e)).sum::<usize>();
+                    while len > MAX_VALUE_LEN {
+                        match history.pop() {
+                            None => bail!("Value for key {:?} is too large for history mode", key),
+                            Some(entry) => len -= entry_len(&entry),
+                        }
+                    }
+                    Op::Put(encode_history_value(value, &history))
+                }
+            };
+            folded.push((key.clone(), op));
+        }
+        Ok(folded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proofs::verify_history;
+    use crate::{Merk, Op};
+
+    #[test]
+    fn history_mode() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_history(&path, Merk::default_db_opts(), 2).unwrap();
+
+        for n in 1..=4u8 {
+            merk.apply(&[(vec![1], Op::Put(vec![n]))], &[]).unwrap();
+        }
+        merk.apply(&[(vec![2], Op::Put(vec![9]))], &[]).unwrap();
+
+        assert_eq!(merk.get(&[1]).unwrap(), Some(vec![4]));
+        assert_eq!(
+            merk.get_history(&[1]).unwrap(),
+            Some(vec![vec![3], vec![2]])
+        );
+        assert_eq!(merk.get_history(&[2]).unwrap(), Some(vec![]));
+        assert_eq!(merk.get_history(&[3]).unwrap(), None);
+
+        let proof = merk.prove_history(&[1]).unwrap();
+        let verified = verify_history(&proof, &[1], merk.root_hash()).unwrap();
+        assert_eq!(verified, Some((vec![4], vec![vec![3], vec![2]])));
+        let proof = merk.prove_history(&[3]).unwrap();
+        assert_eq!(
+            verify_history(&proof, &[3], merk.root_hash()).unwrap(),
+            None
+        );
+
+        // history persists across reopening, and is removed on delete
+        drop(merk);
+        let mut merk = Merk::open_with_history(&path, Merk::default_db_opts(), 2).unwrap();
+        assert_eq!(
+            merk.get_history(&[1]).unwrap(),
+            Some(vec![vec![3], vec![2]])
+        );
+        merk.apply(&[(vec![1], Op::Delete)], &[]).unwrap();
+        merk.apply(&[(vec![1], Op::Put(vec![5]))], &[]).unwrap();
+        assert_eq!(merk.get_history(&[1]).unwrap(), Some(vec![]));
+
+        // resolved merge deltas carry history like any other put
+        let counter = |n: u64| n.to_be_bytes().to_vec();
+        merk.apply(&[(vec![3], Op::Put(counter(8)))], &[]).unwrap();
+        merk.apply_merge(&[3], 1).unwrap();
+        // previews fold in history and pending deltas as apply does
+        let batch = [(vec![1], Op::Put(vec![6]))];
+        let staged_hash = merk.staged_root_hash(&batch).unwrap();
+        merk.apply(&batch, &[]).unwrap();
+        assert_eq!(merk.root_hash(), staged_hash);
+        assert_eq!(merk.get(&[3]).unwrap(), Some(counter(9)));
+        assert_eq!(merk.get_history(&[3]).unwrap(), Some(vec![counter(8)]));
+
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn history_mode_is_recorded() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        drop(Merk::open_with_history(&path, Merk::default_db_opts(), 2).unwrap());
+        assert!(Merk::open(&path).is_err());
+        let merk = Merk::open_with_history(&path, Merk::default_db_opts(), 3).unwrap();
+        merk.destroy().unwrap();
+
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&[(vec![1], Op::Put(vec![1]))], &[]).unwrap();
+        drop(merk);
+        assert!(Merk::open_with_history(&path, Merk::default_db_opts(), 2).is_err());
+        Merk::open(&path).unwrap().destroy().unwrap();
+    }
+
+    #[test]
+    fn history_evicted_to_fit() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_history(&path, Merk::default_db_opts(), 10).unwrap();
+
+        for n in 1..=3u8 {
+            merk.apply(&[(vec![1], Op::Put(vec![n; 30_000]))], &[])
+                .unwrap();
+        }
+        assert_eq!(merk.get_history(&[1]).unwrap(), Some(vec![vec![2; 30_000]]));
+
+        // a value which doesn't fit even without history is rejected
+        let root_hash = merk.root_hash();
+        assert!(merk
+            .apply(&[(vec![2], Op::Put(vec![0; 65_535]))], &[])
+            .is_err());
+        assert_eq!(merk.root_hash(), root_hash);
+
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn merge_from_history_mode() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let other_path = format!("{}.other", path);
+        let plain_path = format!("{}.plain", path);
+        let mut merk = Merk::open_with_history(&path, Merk::default_db_opts(), 2).unwrap();
+        merk.apply(&[(vec![1], Op::Put(vec![1]))], &[]).unwrap();
+        let mut other = Merk::open_with_history(&other_path, Merk::default_db_opts(), 2).unwrap();
+        other.apply(&[(vec![1], Op::Put(vec![2]))], &[]).unwrap();
+        other.apply(&[(vec![1], Op::Put(vec![3]))], &[]).unwrap();
+
+        // only the current values are merged, on top of this store's history
+        merk.merge_from(&other, true).unwrap();
+        assert_eq!(merk.get(&[1]).unwrap(), Some(vec![3]));
+        assert_eq!(merk.get_history(&[1]).unwrap(), Some(vec![vec![1]]));
+
+        let mut plain = Merk::open(&plain_path).unwrap();
+        plain.apply(&[(vec![2], Op::Put(vec![2]))], &[]).unwrap();
+        assert!(merk.merge_from(&plain, false).is_err());
+        assert!(plain.merge_from(&merk, false).is_err());
+
+        merk.destroy().unwrap();
+        other.destroy().unwrap();
+        plain.destroy().unwrap();
+    }
+}
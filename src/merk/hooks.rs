@@ -0,0 +1,172 @@
+//! Provides `NodeHooks`, callbacks for mirroring the physical node writes of
+//! each commit into an external system.
+
+use std::path::Path;
+
+use super::Merk;
+use crate::Result;
+
+type WriteHook = Box<dyn FnMut(&[u8], &[u8]) -> Result<()> + Send>;
+type DeleteHook = Box<dyn FnMut(&[u8]) -> Result<()> + Send>;
+
+/// Callbacks invoked for each tree node written or deleted by a commit,
+/// registered with `Merk::open_with_node_hooks`.
+///
+/// The hooks see the physical layout of the tree as stored in RocksDB: the
+/// key of each node along with its encoded bytes (which, for values stored
+/// outside of their nodes with `open_with_inline_threshold`, do not include
+/// the value). They are also invoked for the nodes written by
+/// `Merk::import_stream`, but not for the aux column family or for nodes
+/// written by a `Restorer`.
+///
+/// Hooks run synchronously in the commit path, as each node is added to the
+/// commit's write batch and before the batch is written, so they must be
+/// fast. If a hook returns an error, the commit fails and the tree is rolled
+/// back like for any other failed commit, but the hooks may already have been
+/// invoked for some of its nodes - and a commit can also fail after the hooks
+/// have run, when writing the batch - so a mirror should be resynchronized
+/// after a failed commit.
+#[derive(Default)]
+pub struct NodeHooks {
+    on_node_write: Option<WriteHook>,
+    on_node_delete: Option<DeleteHook>,
+}
+
+impl NodeHooks {
+    /// Creates a set of hooks with no callbacks.
+    pub fn new() -> Self {
+        NodeHooks::default()
+    }
+
+    /// Sets the callback invoked with the key and encoded bytes of each node
+    /// written by a commit.
+    pub fn on_node_write<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&[u8], &[u8]) -> Result<()> + Send + 'static,
+    {
+        self.on_node_write = Some(Box::new(f));
+        self
+    }
+
+    /// Sets the callback invoked with the key of each node deleted by a
+    /// commit.
+    pub fn on_node_delete<F>(mut self, f: F) -> Self
+    where
+        F: FnMut(&[u8]) -> Result<()> + Send + 'static,
+    {
+        self.on_node_delete = Some(Box::new(f));
+        self
+    }
+
+    pub(crate) fn node_written(&mut self, key: &[u8], bytes: &[u8]) -> Result<()> {
+        match self.on_node_write.as_mut() {
+            None => Ok(()),
+            Some(f) => f(key, bytes),
+        }
+    }
+
+    pub(crate) fn node_deleted(&mut self, key: &[u8]) -> Result<()> {
+        match self.on_node_delete.as_mut() {
+            None => Ok(()),
+            Some(f) => f(key),
+        }
+    }
+}
+
+impl Merk {
+    /// Opens a store with the specified file path like `Merk::open`, invoking
+    /// `hooks` for every node written or deleted by later commits. If no store
+    /// exists at that path, one will be created.
+    pub fn open_with_node_hooks<P: AsRef<Path>>(path: P, hooks: NodeHooks) -> Result<Merk> {
+        let mut merk = Merk::open(path)?;
+        merk.node_hooks = Some(hooks);
+        Ok(merk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+
+    use failure::bail;
+
+    use super::NodeHooks;
+    use crate::test_utils::*;
+    use crate::Merk;
+
+    #[test]
+    fn mirror_nodes() {
+        let mirror = Arc::new(Mutex::new(BTreeMap::new()));
+        let written = mirror.clone();
+        let deleted = mirror.clone();
+        let hooks = NodeHooks::new()
+            .on_node_write(move |key, bytes| {
+                written.lock().unwrap().insert(key.to_vec(), bytes.to_vec());
+                Ok(())
+            })
+            .on_node_delete(move |key| {
+                deleted.lock().unwrap().remove(key);
+                Ok(())
+            });
+
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_node_hooks(&path, hooks).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        merk.apply(&make_batch_rand(50, 1), &[]).unwrap();
+        merk.apply(&make_del_batch_seq(10..40), &[]).unwrap();
+
+        let mut stored = BTreeMap::new();
+        let mut iter = merk.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            stored.insert(iter.key().unwrap().to_vec(), iter.value().unwrap().to_vec());
+            iter.next();
+        }
+        assert_eq!(*mirror.lock().unwrap(), stored);
+
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn mirror_imported_nodes() {
+        let mirror = Arc::new(Mutex::new(BTreeMap::new()));
+        let written = mirror.clone();
+        let hooks = NodeHooks::new().on_node_write(move |key, bytes| {
+            written.lock().unwrap().insert(key.to_vec(), bytes.to_vec());
+            Ok(())
+        });
+
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_node_hooks(&path, hooks).unwrap();
+        let entries = (0..100)
+            .map(|n| (seq_key(n), vec![123; 60]))
+            .collect::<Vec<_>>();
+        merk.import_stream(entries, 10, |_| {}).unwrap();
+
+        let mut stored = BTreeMap::new();
+        let mut iter = merk.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            stored.insert(iter.key().unwrap().to_vec(), iter.value().unwrap().to_vec());
+            iter.next();
+        }
+        assert_eq!(stored.len(), 100);
+        assert_eq!(*mirror.lock().unwrap(), stored);
+
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn failing_hook_aborts_commit() {
+        let hooks = NodeHooks::new().on_node_write(|_, _| bail!("mirror unavailable"));
+
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_node_hooks(&path, hooks).unwrap();
+        assert!(merk.apply(&make_batch_seq(0..10), &[]).is_err());
+        assert!(merk.get(&seq_key(5)).unwrap().is_none());
+        assert!(merk.db().get(seq_key(5)).unwrap().is_none());
+
+        merk.destroy().unwrap();
+    }
+}
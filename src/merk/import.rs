@@ -0,0 +1,293 @@
+//! Provides `Merk::import_stream`, for bulk loading sorted key/value pairs into
+//! an empty store with bounded memory.
+
+use std::time::{Duration, Instant};
+
+use failure::bail;
+use rocksdb::WriteBatch;
+
+use super::history::MAX_VALUE_LEN;
+use super::{Merk, MerkCommitter, VALUES_CF_NAME};
+use crate::proofs::encode_history_value;
+use crate::tree::{Commit, Hash, Link, Tree, NULL_HASH};
+use crate::Result;
+
+/// Progress of a `Merk::import_stream` call, reported each time a set of nodes
+/// is written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ImportProgress {
+    /// The number of key/value pairs written so far.
+    pub keys_applied: usize,
+    /// The total number of key/value pairs being imported.
+    pub total_keys: usize,
+    /// The time elapsed since the import started.
+    pub elapsed: Duration,
+    /// The root hash of the store. Nodes are written bottom-up, so the root is
+    /// only known once the import has finished - this is `None` for every
+    /// report except the last.
+    pub root_hash: Option<Hash>,
+}
+
+impl Merk {
+    /// Imports a stream of key/value pairs into an empty store, writing the
+    /// nodes to the db every `commit_every` keys and calling `progress` after
+    /// each write. Returns the resulting root hash.
+    ///
+    /// The entries must be sorted by key and unique. The tree is built in one
+    /// pass with the same shape as a single `apply` of all the entries, so the
+    /// resulting root hash is the same as applying them all in one batch,
+    /// while only holding `commit_every` nodes (plus one path of the tree) in
+    /// memory. The number of entries must be known up front to determine that
+    /// shape. In history mode (see `open_with_history`), each value is stored
+    /// with an empty history, as when it is put with `apply`.
+    ///
+    /// If the import fails part-way through (e.g. because the entries are not
+    /// sorted), the store's root is never set so it remains empty, but any
+    /// nodes which were already written are left behind and can be removed
+    /// with `gc_orphans`.
+    ///
+    /// Node hooks (see `open_with_node_hooks`) are invoked for each node as it
+    /// is written, as for a commit.
+    pub fn import_stream<I, F>(
+        &mut self,
+        entries: I,
+        commit_every: usize,
+        mut progress: F,
+    ) -> Result<Hash>
+    where
+        I: IntoIterator<Item = (Vec<u8>, Vec<u8>)>,
+        I::IntoIter: ExactSizeIterator,
+        F: FnMut(ImportProgress),
+    {
+        if self.use_tree(|maybe_tree| maybe_tree.is_some()) {
+            bail!("Can only import into an empty store");
+        }
+
+        let entries = entries.into_iter();
+        let total_keys = entries.len();
+        let start = Instant::now();
+
+        let inline_threshold = self.inline_threshold;
+        let mut importer = Importer {
+            merk: self,
+            entries,
+            committer: MerkCommitter::new(0, 0, inline_threshold),
+            commit_every: commit_every.max(1),
+            keys_applied: 0,
+            total_keys,
+            start,
+            prev_key: None,
+            history: self.history_len.is_some(),
+            progress: &mut progress,
+        };
+        let maybe_root = importer.build(total_keys)?;
+        if importer.entries.next().is_some() {
+            bail!("Import stream yielded more entries than its stated length");
+        }
+        importer.flush()?;
+        let keys_applied = importer.keys_applied;
+
+        let root_hash = match maybe_root {
+            None => NULL_HASH,
+            Some(root) => {
+                self.set_root_key(root.key().to_vec())?;
+                self.load_root()?;
+                self.root_hash()
+            }
+        };
+
+        progress(ImportProgress {
+            keys_applied,
+            total_keys,
+            elapsed: start.elapsed(),
+            root_hash: Some(root_hash),
+        });
+
+        Ok(root_hash)
+    }
+}
+
+struct Importer<'a, I, F> {
+    merk: &'a mut Merk,
+    entries: I,
+    committer: MerkCommitter,
+    commit_every: usize,
+    keys_applied: usize,
+    total_keys: usize,
+    start: Instant,
+    prev_key: Option<Vec<u8>>,
+    history: bool,
+    progress: &'a mut F,
+}
+
+impl<'a, I, F> Importer<'a, I, F>
+where
+    I: Iterator<Item = (Vec<u8>, Vec<u8>)>,
+    F: FnMut(ImportProgress),
+{
+    /// Builds a subtree out of the next `len` entries, writing its nodes and
+    /// returning a link to its root. The middle entry becomes the root, as in
+    /// `Walker::build`.
+    fn build(&mut self, len: usize) -> Result<Option<Link>> {
+        if len == 0 {
+            return Ok(None);
+        }
+
+        let mid = len / 2;
+        let left = self.build(mid)?;
+
+        let (key, value) = match self.entries.next() {
+            None => bail!("Import stream ended before its stated length"),
+            Some(entry) => entry,
+        };
+        if let Some(prev_key) = self.prev_key.as_ref() {
+            if key <= *prev_key {
+                bail!("Keys in import stream must be sorted and unique");
+            }
+        }
+        self.prev_key = Some(key.clone());
+        let value = if self.history {
+            if 4 + value.len() > MAX_VALUE_LEN {
+                bail!("Value for key {:?} is too large for history mode", key);
+            }
+            encode_history_value(&value, &[])
+        } else {
+            value
+        };
+
+        let right = self.build(len - mid - 1)?;
+
+        let mut tree = Tree::new(key, value);
+        *tree.slot_mut(true) = left;
+        *tree.slot_mut(false) = right;
+        self.committer.write(&tree)?;
+
+        self.keys_applied += 1;
+        if self.committer.batch.len() >= self.commit_every {
+            self.flush()?;
+            (self.progress)(ImportProgress {
+                keys_applied: self.keys_applied,
+                total_keys: self.total_keys,
+                elapsed: self.start.elapsed(),
+                root_hash: None,
+            });
+        }
+
+        Ok(Some(Link::Reference {
+            hash: tree.hash(),
+            child_heights: tree.child_heights(),
+            key: tree.take_key(),
+        }))
+    }
+
+    /// Writes the pending nodes to the db.
+    fn flush(&mut self) -> Result<()> {
+        let mut batch = WriteBatch::default();
+        let values_cf = self.merk.db.cf_handle(VALUES_CF_NAME).unwrap();
+
+        for (key, maybe_node) in self.committer.batch.drain(..) {
+            if let Some(node) = maybe_node {
+                if let Some(hooks) = self.merk.node_hooks.as_mut() {
+                    hooks.node_written(&key, &node)?;
+                }
+                batch.put(key, node);
+            }
+        }
+        for (key, maybe_value) in self.committer.values.drain(..) {
+            match maybe_value {
+                Some(value) => batch.put_cf(values_cf, key, value),
+                None => batch.delete_cf(values_cf, key),
+            }
+        }
+
+        self.merk.write(batch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::{Merk, Op, HASH_LENGTH};
+
+    fn entries(range: std::ops::Range<u64>) -> Vec<(Vec<u8>, Vec<u8>)> {
+        make_batch_seq(range)
+            .into_iter()
+            .map(|(key, op)| match op {
+                Op::Put(value) => (key, value),
+                Op::Delete => unreachable!(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn import_matches_single_batch() {
+        let mut expected = TempMerk::new().unwrap();
+        expected.apply(&make_batch_seq(1..10_000), &[]).unwrap();
+
+        let mut merk = TempMerk::new().unwrap();
+        let mut reports = vec![];
+        let root_hash = merk
+            .import_stream(entries(1..10_000), 1000, |progress| reports.push(progress))
+            .unwrap();
+
+        assert_eq!(root_hash, expected.root_hash());
+        assert_eq!(merk.root_hash(), expected.root_hash());
+        merk.use_tree(|tree| assert_tree_invariants(tree.unwrap()));
+        assert_eq!(merk.get(&seq_key(5000)).unwrap(), Some(vec![123; 60]));
+
+        assert_eq!(reports.len(), 10);
+        assert_eq!(reports[0].keys_applied, 1000);
+        assert!(reports[..9].iter().all(|report| report.root_hash.is_none()));
+        let last = reports.last().unwrap();
+        assert_eq!(last.keys_applied, 9999);
+        assert_eq!(last.total_keys, 9999);
+        assert_eq!(last.root_hash, Some(root_hash));
+
+        // the store can be used as usual afterwards
+        merk.apply(&make_batch_seq(10_000..10_100), &[]).unwrap();
+        expected
+            .apply(&make_batch_seq(10_000..10_100), &[])
+            .unwrap();
+        assert_eq!(merk.root_hash(), expected.root_hash());
+    }
+
+    #[test]
+    fn import_empty() {
+        let mut merk = TempMerk::new().unwrap();
+        let root_hash = merk.import_stream(vec![], 10, |_| {}).unwrap();
+        assert_eq!(root_hash, [0; HASH_LENGTH]);
+        assert_eq!(merk.root_hash(), [0; HASH_LENGTH]);
+    }
+
+    #[test]
+    fn import_rejects_invalid_input() {
+        let mut merk = TempMerk::new().unwrap();
+        let mut unsorted = entries(0..100);
+        unsorted.swap(10, 20);
+        assert!(merk.import_stream(unsorted, 1, |_| {}).is_err());
+        assert_eq!(merk.root_hash(), [0; HASH_LENGTH]);
+        assert!(!merk.gc_orphans().unwrap().is_empty());
+        assert!(merk.find_orphans().unwrap().is_empty());
+
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        assert!(merk.import_stream(entries(10..20), 10, |_| {}).is_err());
+    }
+
+    #[test]
+    fn import_history_mode() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let expected_path = format!("{}.expected", path);
+        let mut expected =
+            Merk::open_with_history(&expected_path, Merk::default_db_opts(), 2).unwrap();
+        expected.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let mut merk = Merk::open_with_history(&path, Merk::default_db_opts(), 2).unwrap();
+        let root_hash = merk.import_stream(entries(0..100), 10, |_| {}).unwrap();
+        assert_eq!(root_hash, expected.root_hash());
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), Some(vec![123; 60]));
+        assert_eq!(merk.get_history(&seq_key(5)).unwrap(), Some(vec![]));
+
+        merk.destroy().unwrap();
+        expected.destroy().unwrap();
+    }
+}
@@ -0,0 +1,303 @@
+//! Provides `Merk::apply_merge`, for accumulating increments to counter values
+//! without a read-modify-write for each one.
+
+use std::collections::BTreeSet;
+
+use byteorder::{BigEndian, ByteOrder};
+use failure::bail;
+use rocksdb::{MergeOperands, WriteBatch};
+
+use super::{Merk, MERGES_CF_NAME};
+use crate::tree::{Batch, Op};
+use crate::Result;
+
+/// The name of the RocksDB merge operator used for pending counter deltas.
+pub(crate) const COUNTER_MERGE_OPERATOR: &str = "merk_counter_add";
+
+/// Sums pending counter deltas on the RocksDB side, so accumulating a delta
+/// never requires reading the previous ones. Sums wrap on overflow, as when
+/// they are folded into the counter values (see `Merk::apply_merge`).
+pub(crate) fn add_counters(
+    _key: &[u8],
+    existing: Option<&[u8]>,
+    operands: &mut MergeOperands,
+) -> Option<Vec<u8>> {
+    let mut sum = match existing {
+        None => 0u64,
+        Some(bytes) if bytes.len() == 8 => BigEndian::read_u64(bytes),
+        Some(_) => return None,
+    };
+    for operand in operands {
+        if operand.len() != 8 {
+            return None;
+        }
+        sum = sum.wrapping_add(BigEndian::read_u64(operand));
+    }
+    Some(sum.to_be_bytes().to_vec())
+}
+
+impl Merk {
+    /// Adds `delta` to the counter stored at `key`. Counter values are `u64`s
+    /// encoded as 8 big-endian bytes, and a missing key is treated as a counter
+    /// of 0.
+    ///
+    /// The delta is accumulated by a RocksDB merge operator (so this never
+    /// reads the current value) and is not reflected in the tree until the
+    /// next call to `apply`, which folds all pending deltas into their current
+    /// values and puts the resolved values as part of its batch. The resulting
+    /// root hash is the same as applying the resolved values directly. If the
+    /// batch passed to `apply` also contains an operation for a key with a
+    /// pending delta, the operation takes precedence and the delta is
+    /// discarded.
+    ///
+    /// Counters wrap around on overflow, both when deltas are accumulated and
+    /// when they are folded into the current value: the merge operator has no
+    /// way to report an error, so wrapping is the only rule which can be
+    /// applied consistently.
+    ///
+    /// Errors if the current value of `key` is not an 8-byte counter. If the
+    /// value is replaced by one which is not a counter before the delta is
+    /// folded in (e.g. within a write group), the delta is discarded.
+    ///
+    /// Pending deltas are persisted, so they survive reopening the store.
+    pub fn apply_merge(&mut self, key: &[u8], delta: u64) -> Result<()> {
+        if let Some(value) = self.get(key)? {
+            if value.len() != 8 {
+                bail!("Merge target is not an 8-byte counter value");
+            }
+        }
+
+        let merges_cf = self.db.cf_handle(MERGES_CF_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+        batch.merge_cf(merges_cf, key, delta.to_be_bytes());
+        self.write(batch)?;
+        self.pending_merges.insert(key.to_vec());
+        Ok(())
+    }
+
+    /// Gets the sum of the deltas passed to `apply_merge` for `key` which have
+    /// not yet been folded into the tree.
+    pub fn pending_merge(&self, key: &[u8]) -> Result<Option<u64>> {
+        let merges_cf = self.db.cf_handle(MERGES_CF_NAME).unwrap();
+        match self.db.get_cf(merges_cf, key)? {
+            None => Ok(None),
+            Some(delta) => Ok(Some(read_delta(&delta)?)),
+        }
+    }
+
+    /// Reads the keys of the pending deltas from the merges column family, so
+    /// `fold_merges` can look them up without scanning it on every apply.
+    pub(crate) fn load_pending_merges(&mut self) -> Result<()> {
+        let mut pending = BTreeSet::new();
+        {
+            let merges_cf = self.db.cf_handle(MERGES_CF_NAME).unwrap();
+            let mut iter = self.db.raw_iterator_cf(merges_cf);
+            iter.seek_to_first();
+            while iter.valid() {
+                pending.insert(iter.key().unwrap().to_vec());
+                iter.next();
+            }
+            iter.status()?;
+        }
+        self.pending_merges = pending;
+        Ok(())
+    }
+
+    /// Resolves all pending deltas against the current values and merges them
+    /// into `batch` as puts. Returns `None` if there are no pending deltas,
+    /// otherwise the merged batch along with the keys of the resolved deltas
+    /// (which should be deleted from the merges column family when the batch
+    /// is committed).
+    pub(crate) fn fold_merges(
+        &self,
+        batch: &Batch,
+    ) -> Result<Option<(Vec<(Vec<u8>, Op)>, Vec<Vec<u8>>)>> {
+        if self.pending_merges.is_empty() {
+            return Ok(None);
+        }
+
+        let merges_cf = self.db.cf_handle(MERGES_CF_NAME).unwrap();
+        let mut pending = Vec::with_capacity(self.pending_merges.len());
+        for key in self.pending_merges.iter() {
+            if let Some(delta) = self.db.get_cf(merges_cf, key)? {
+                pending.push((key.clone(), read_delta(&delta)?));
+            }
+        }
+        if pending.is_empty() {
+            return Ok(None);
+        }
+
+        let mut merged = Vec::with_capacity(batch.len() + pending.len());
+        let mut resolved_keys = Vec::with_capacity(pending.len());
+        let mut ops = batch.iter().peekable();
+        for (key, delta) in pending {
+            while let Some((op_key, op)) = ops.peek() {
+                if *op_key > key {
+                    break;
+                }
+                merged.push((op_key.clone(), clone_op(op)));
+                ops.next();
+            }
+            resolved_keys.push(key.clone());
+
+            // an explicit operation in the batch overrides the pending delta
+            if merged
+                .last()
+                .map_or(false, |(last_key, _)| *last_key == key)
+            {
+                continue;
+            }
+
+            // the value was replaced by one which isn't a counter after the
+            // delta was queued, which overrides the delta like an explicit
+            // operation does
+            let current = match self.get(&key)? {
+                None => 0,
+                Some(value) if value.len() == 8 => BigEndian::read_u64(&value),
+                Some(_) => continue,
+            };
+            let value = current.wrapping_add(delta);
+            merged.push((key, Op::Put(value.to_be_bytes().to_vec())));
+        }
+        merged.extend(ops.map(|(key, op)| (key.clone(), clone_op(op))));
+
+        Ok(Some((merged, resolved_keys)))
+    }
+}
+
+/// Decodes a pending delta, which the merge operator always stores as 8
+/// big-endian bytes.
+fn read_delta(bytes: &[u8]) -> Result<u64> {
+    if bytes.len() != 8 {
+        bail!("Pending merge delta is not 8 bytes");
+    }
+    Ok(BigEndian::read_u64(bytes))
+}
+
+fn clone_op(op: &Op) -> Op {
+    match op {
+        Op::Put(value) => Op::Put(value.clone()),
+        Op::Delete => Op::Delete,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Op;
+
+    fn counter(n: u64) -> Vec<u8> {
+        n.to_be_bytes().to_vec()
+    }
+
+    #[test]
+    fn merge_matches_direct_apply() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        merk.apply(&[(vec![5; 20], Op::Put(counter(10)))], &[])
+            .unwrap();
+
+        merk.apply_merge(&[5; 20], 2).unwrap();
+        merk.apply_merge(&[5; 20], 3).unwrap();
+        merk.apply_merge(&[6; 20], 7).unwrap();
+        assert_eq!(merk.pending_merge(&[5; 20]).unwrap(), Some(5));
+        assert_eq!(merk.get(&[5; 20]).unwrap(), Some(counter(10)));
+
+        merk.apply(&[put_entry(100)], &[]).unwrap();
+        assert_eq!(merk.get(&[5; 20]).unwrap(), Some(counter(15)));
+        assert_eq!(merk.get(&[6; 20]).unwrap(), Some(counter(7)));
+        assert_eq!(merk.pending_merge(&[5; 20]).unwrap(), None);
+
+        let mut expected = TempMerk::new().unwrap();
+        expected.apply(&make_batch_seq(0..10), &[]).unwrap();
+        let mut batch = vec![
+            (vec![5; 20], Op::Put(counter(15))),
+            (vec![6; 20], Op::Put(counter(7))),
+            put_entry(100),
+        ];
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+        expected.apply(&batch, &[]).unwrap();
+        assert_eq!(merk.root_hash(), expected.root_hash());
+    }
+
+    #[test]
+    fn batch_overrides_pending_merge() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply_merge(&[1], 5).unwrap();
+        merk.apply_merge(&[2], 5).unwrap();
+
+        merk.apply(&[(vec![1], Op::Put(counter(100)))], &[])
+            .unwrap();
+        assert_eq!(merk.get(&[1]).unwrap(), Some(counter(100)));
+        assert_eq!(merk.get(&[2]).unwrap(), Some(counter(5)));
+        assert_eq!(merk.pending_merge(&[1]).unwrap(), None);
+    }
+
+    #[test]
+    fn apply_variants_fold_merges() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply_merge(&[1], 5).unwrap();
+        merk.apply_timed(&[], &[]).unwrap();
+        assert_eq!(merk.get(&[1]).unwrap(), Some(counter(5)));
+        assert_eq!(merk.pending_merge(&[1]).unwrap(), None);
+
+        merk.apply_merge(&[1], 2).unwrap();
+        let mut batch = rocksdb::WriteBatch::default();
+        merk.apply_into(&[], &[], &mut batch).unwrap();
+        assert_eq!(merk.get(&[1]).unwrap(), Some(counter(7)));
+        assert_eq!(merk.pending_merge(&[1]).unwrap(), Some(2));
+        merk.db().write(batch).unwrap();
+        assert_eq!(merk.pending_merge(&[1]).unwrap(), None);
+    }
+
+    #[test]
+    fn merge_wraps_on_overflow() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&[(vec![1], Op::Put(counter(u64::MAX)))], &[])
+            .unwrap();
+
+        merk.apply_merge(&[1], 2).unwrap();
+        merk.apply_merge(&[2], u64::MAX).unwrap();
+        merk.apply_merge(&[2], 3).unwrap();
+        assert_eq!(merk.pending_merge(&[2]).unwrap(), Some(2));
+
+        merk.apply(&[], &[]).unwrap();
+        assert_eq!(merk.get(&[1]).unwrap(), Some(counter(1)));
+        assert_eq!(merk.get(&[2]).unwrap(), Some(counter(2)));
+    }
+
+    #[test]
+    fn merge_into_non_counter_fails() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&[(vec![1], Op::Put(vec![1, 2, 3]))], &[])
+            .unwrap();
+
+        assert!(merk.apply_merge(&[1], 1).is_err());
+        assert_eq!(merk.pending_merge(&[1]).unwrap(), None);
+        merk.apply(&[(vec![2], Op::Put(counter(1)))], &[]).unwrap();
+
+        // a value replaced by a non-counter before folding discards the delta
+        merk.apply_merge(&[3], 1).unwrap();
+        merk.begin_write_group().unwrap();
+        merk.apply(&[(vec![3], Op::Put(vec![1, 2, 3]))], &[])
+            .unwrap();
+        merk.end_write_group().unwrap();
+        merk.apply(&[(vec![4], Op::Put(counter(1)))], &[]).unwrap();
+        assert_eq!(merk.get(&[3]).unwrap(), Some(vec![1, 2, 3]));
+        assert_eq!(merk.pending_merge(&[3]).unwrap(), None);
+    }
+
+    #[test]
+    fn pending_merges_survive_reopen() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = crate::Merk::open(&path).unwrap();
+        merk.apply_merge(&[1], 5).unwrap();
+        drop(merk);
+
+        let mut merk = crate::Merk::open(&path).unwrap();
+        merk.apply(&[], &[]).unwrap();
+        assert_eq!(merk.get(&[1]).unwrap(), Some(counter(5)));
+        assert_eq!(merk.pending_merge(&[1]).unwrap(), None);
+        merk.destroy().unwrap();
+    }
+}
@@ -1,34 +1,209 @@
 pub mod chunks;
+mod deadline;
+mod heights;
+mod history;
+mod hooks;
+mod import;
+mod merge;
+mod nested;
+mod proof_cache;
+mod read_cache;
+mod receipt;
 pub mod restore;
-
-use std::cell::Cell;
-use std::collections::LinkedList;
+mod sample;
+mod scoped;
+mod scrub;
+mod secondary;
+mod selfcheck;
+mod transaction;
+mod versions;
+mod warm;
+mod write_group;
+
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeSet, HashSet, LinkedList};
+use std::ops::{Bound, RangeBounds};
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
 use failure::bail;
 use rocksdb::{checkpoint::Checkpoint, ColumnFamilyDescriptor, WriteBatch};
 
 use crate::error::Result;
-use crate::proofs::encode_into;
-use crate::tree::{Batch, Commit, Fetch, Hash, Link, Op, RefWalker, Tree, Walker, NULL_HASH};
+use crate::merk::proof_cache::{query_hash, ProofCache};
+use crate::merk::write_group::WriteGroup;
+use crate::proofs::{decode_history_value, encode_into, Query, QueryItem};
+use crate::tree::{
+    structure_hash, Batch, BatchEntry, Commit, Fetch, Hash, Link, NoopCommit, Op, RefWalker, Tree,
+    Walker, NULL_HASH,
+};
+
+pub use heights::RootAttestation;
+pub use hooks::NodeHooks;
+pub use import::ImportProgress;
+pub use read_cache::ReadCache;
+pub use receipt::CommitReceipt;
+pub use scoped::ScopedMerk;
+pub use scrub::{ScrubConfig, Scrubber};
+pub use secondary::ReadOnlyMerk;
+pub use transaction::Transaction;
 
 const ROOT_KEY_KEY: &[u8] = b"root";
 const AUX_CF_NAME: &str = "aux";
 const INTERNAL_CF_NAME: &str = "internal";
+const VALUES_CF_NAME: &str = "values";
+const PROOFS_CF_NAME: &str = "proofs";
+const MERGES_CF_NAME: &str = "merges";
 
 fn column_families() -> Vec<ColumnFamilyDescriptor> {
+    let mut merges_opts = Merk::default_db_opts();
+    merges_opts.set_merge_operator_associative(merge::COUNTER_MERGE_OPERATOR, merge::add_counters);
+
     vec![
         // TODO: clone opts or take args
         ColumnFamilyDescriptor::new(AUX_CF_NAME, Merk::default_db_opts()),
         ColumnFamilyDescriptor::new(INTERNAL_CF_NAME, Merk::default_db_opts()),
+        ColumnFamilyDescriptor::new(VALUES_CF_NAME, Merk::default_db_opts()),
+        ColumnFamilyDescriptor::new(PROOFS_CF_NAME, Merk::default_db_opts()),
+        ColumnFamilyDescriptor::new(MERGES_CF_NAME, merges_opts),
     ]
 }
 
+/// The net effect of a single batch operation, as returned by
+/// `Merk::apply_with_changes`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    /// The key did not exist and was put.
+    Inserted,
+    /// The key existed and was put with a different value.
+    Updated,
+    /// The key existed and was deleted.
+    Deleted,
+    /// The operation had no effect: either a put of the existing value, or a
+    /// delete of a key which did not exist.
+    Unchanged,
+}
+
+/// Configures RocksDB's internal info log, which records events such as
+/// flushes, compactions and write stalls.
+///
+/// RocksDB writes this log to files rather than through a callback, so to
+/// surface these events in an application's logs, point `dir` at a location
+/// the application's log shipper collects from.
+///
+/// Verbose levels (`Debug` and `Info`) log on every flush and compaction and
+/// can measurably slow down write-heavy workloads, so production deployments
+/// should generally use `Warn` or above.
+pub struct LogConfig {
+    /// The minimum severity of events to log.
+    pub level: rocksdb::LogLevel,
+    /// The directory to write log files to. If `None`, logs are written to
+    /// the database directory.
+    pub dir: Option<PathBuf>,
+    /// The maximum number of old log files to keep.
+    pub keep_file_num: usize,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        LogConfig {
+            level: rocksdb::LogLevel::Warn,
+            dir: None,
+            keep_file_num: 10,
+        }
+    }
+}
+
+impl LogConfig {
+    /// Applies this logging configuration to the given RocksDB options.
+    pub fn apply_to(&self, opts: &mut rocksdb::Options) {
+        opts.set_log_level(self.level);
+        opts.set_keep_log_file_num(self.keep_file_num);
+        if let Some(dir) = self.dir.as_ref() {
+            opts.set_db_log_dir(dir);
+        }
+    }
+}
+
+/// A breakdown of the time spent in each phase of an apply, in nanoseconds, as
+/// returned by `Merk::apply_timed`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Checking that the batch is sorted and unique.
+    pub sort: u64,
+    /// Walking the tree to apply the operations (including fetching nodes
+    /// from disk, and folding in pending merges and history).
+    pub tree_walk: u64,
+    /// Computing the hashes of the modified nodes and encoding them into the
+    /// write batch.
+    pub hash: u64,
+    /// Writing the batch to RocksDB.
+    pub rocksdb_write: u64,
+}
+
+/// RocksDB write options for a single commit, passed to
+/// `Merk::apply_with_opts`. The default options match those used by `apply`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct WriteOpts {
+    /// Sync the write-ahead log to disk before the commit returns, so the
+    /// commit survives a machine crash (not just a process crash).
+    pub sync: bool,
+    /// Skip the write-ahead log for this commit. This makes the write faster,
+    /// but the commit will be lost if the process crashes before its data is
+    /// flushed, e.g. by a later synced commit or by `Merk::flush`.
+    pub disable_wal: bool,
+}
+
+impl WriteOpts {
+    fn to_rocksdb(self) -> rocksdb::WriteOptions {
+        let mut opts = rocksdb::WriteOptions::default();
+        opts.set_sync(self.sync);
+        opts.disable_wal(self.disable_wal);
+        opts
+    }
+}
+
+/// The differences between `apply` and its variants, which all share the
+/// commit path in `Merk::apply_unchecked_opts`.
+#[derive(Default)]
+pub(crate) struct ApplyOpts<'a> {
+    /// The RocksDB write options for the commit.
+    pub(crate) write_opts: WriteOpts,
+    /// A height to record the new root hash for, in the same commit (see
+    /// `Merk::apply_at_height`).
+    pub(crate) height: Option<u64>,
+    /// Where to record how long each phase took (see `Merk::apply_timed`).
+    pub(crate) timings: Option<&'a mut Timings>,
+    /// A batch to add the writes to rather than writing them (see
+    /// `Merk::apply_into`).
+    pub(crate) user_batch: Option<&'a mut WriteBatch>,
+}
+
+/// A proof generated by `Merk::proof_len`, kept for the next call to
+/// `Merk::prove`.
+struct MeasuredProof {
+    root_hash: Hash,
+    query_hash: Hash,
+    proof: Vec<u8>,
+}
+
 /// A handle to a Merkle key/value store backed by RocksDB.
 pub struct Merk {
     pub(crate) tree: Cell<Option<Tree>>,
+    root_hash: Cell<Hash>,
     pub(crate) db: rocksdb::DB,
     pub(crate) path: PathBuf,
+    pub(crate) inline_threshold: Option<usize>,
+    pub(crate) proof_cache: Option<ProofCache>,
+    pub(crate) history_len: Option<usize>,
+    pub(crate) write_group: Option<WriteGroup>,
+    /// The keys with pending `apply_merge` deltas.
+    pub(crate) pending_merges: BTreeSet<Vec<u8>>,
+    measured_proof: Cell<Option<MeasuredProof>>,
+    pub(crate) node_hooks: Option<NodeHooks>,
+    pub(crate) read_cache: Option<RefCell<Box<dyn ReadCache>>>,
+    #[cfg(test)]
+    pub(crate) fail_writes: bool,
 }
 
 impl Merk {
@@ -42,23 +217,121 @@ impl Merk {
     /// Opens a store with the specified file path and the given options. If no
     /// store exists at that path, one will be created.
     pub fn open_opt<P>(path: P, db_opts: rocksdb::Options) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+    {
+        Merk::open_with_column_families(path, db_opts, vec![])
+    }
+
+    /// Opens a store with the specified file path and options, along with
+    /// additional user-defined column families in the same db. If no store
+    /// exists at that path, one will be created.
+    ///
+    /// Once a db has been opened with extra column families, it must always be
+    /// opened with them. They can be accessed through `Merk::db`, e.g. to
+    /// write to them atomically with tree updates using `apply_into`.
+    pub fn open_with_column_families<P>(
+        path: P,
+        db_opts: rocksdb::Options,
+        extra_cfs: Vec<ColumnFamilyDescriptor>,
+    ) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+    {
+        Merk::open_cfs(path, db_opts, extra_cfs, None)
+    }
+
+    /// Opens a store as in `open_with_column_families`, in history mode if
+    /// `history_len` is set (see `open_with_history`).
+    fn open_cfs<P>(
+        path: P,
+        db_opts: rocksdb::Options,
+        extra_cfs: Vec<ColumnFamilyDescriptor>,
+        history_len: Option<usize>,
+    ) -> Result<Merk>
     where
         P: AsRef<Path>,
     {
         let mut path_buf = PathBuf::new();
         path_buf.push(path);
-        let db = rocksdb::DB::open_cf_descriptors(&db_opts, &path_buf, column_families())?;
+        let mut cfs = column_families();
+        cfs.extend(extra_cfs);
+        let db = rocksdb::DB::open_cf_descriptors(&db_opts, &path_buf, cfs)?;
 
         let mut merk = Merk {
             tree: Cell::new(None),
+            root_hash: Cell::new(NULL_HASH),
             db,
             path: path_buf,
+            inline_threshold: None,
+            proof_cache: None,
+            history_len,
+            write_group: None,
+            pending_merges: BTreeSet::new(),
+            measured_proof: Cell::new(None),
+            node_hooks: None,
+            read_cache: None,
+            #[cfg(test)]
+            fail_writes: false,
         };
         merk.load_root()?;
+        merk.check_history_mode()?;
+        merk.load_pending_merges()?;
+
+        Ok(merk)
+    }
+
+    /// Opens a store with the specified file path and options, storing values
+    /// longer than `inline_threshold` bytes outside of their tree nodes (in a
+    /// separate column family). If no store exists at that path, one will be
+    /// created.
+    ///
+    /// Keeping large values out of the nodes makes traversals cheaper, since
+    /// nodes are read much more often than values. The placement of a value
+    /// does not affect any hashes, and values can always be read regardless of
+    /// the threshold the store is currently opened with - the threshold only
+    /// applies to nodes as they are written.
+    pub fn open_with_inline_threshold<P>(
+        path: P,
+        db_opts: rocksdb::Options,
+        inline_threshold: usize,
+    ) -> Result<Merk>
+    where
+        P: AsRef<Path>,
+    {
+        let mut merk = Merk::open_opt(path, db_opts)?;
+        merk.inline_threshold = Some(inline_threshold);
+        Ok(merk)
+    }
 
+    /// Opens a store with the specified file path, along with a persistent
+    /// cache of up to `max_entries` proofs created by `prove`. If no store
+    /// exists at that path, one will be created.
+    ///
+    /// Cached proofs are keyed by the root hash they were created against and
+    /// the queried keys, so they never need to be invalidated - proofs for old
+    /// roots simply stop being used and are eventually evicted, least recently
+    /// used first. The cache is bounded by entry count rather than by size, so
+    /// `max_entries` should be chosen based on the typical proof size; each
+    /// entry also stores a small amount of bookkeeping data.
+    pub fn open_with_persistent_proof_cache<P: AsRef<Path>>(
+        path: P,
+        max_entries: usize,
+    ) -> Result<Merk> {
+        let mut merk = Merk::open(path)?;
+        merk.proof_cache = Some(ProofCache::load(&merk.db, max_entries)?);
         Ok(merk)
     }
 
+    /// Opens a store with the specified file path, using the default options
+    /// along with the given RocksDB logging configuration. If no store exists
+    /// at that path, one will be created.
+    pub fn open_with_log<P: AsRef<Path>>(path: P, log: &LogConfig) -> Result<Merk> {
+        let mut db_opts = Merk::default_db_opts();
+        log.apply_to(&mut db_opts);
+        Merk::open_opt(path, db_opts)
+    }
+
     pub fn default_db_opts() -> rocksdb::Options {
         let mut opts = rocksdb::Options::default();
         opts.create_if_missing(true);
@@ -83,7 +356,34 @@ impl Merk {
     ///
     /// Note that this is essentially the same as a normal RocksDB `get`, so
     /// should be a fast operation and has almost no tree overhead.
+    ///
+    /// In history mode (see `open_with_history`), this returns the current
+    /// value without its history.
+    ///
+    /// If the store was opened with a read cache (see
+    /// `open_with_read_cache`), the cache is consulted first and populated on
+    /// a miss.
     pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        if let Some(cache) = self.read_cache.as_ref() {
+            if let Some(value) = cache.borrow_mut().get(key) {
+                return Ok(Some(value));
+            }
+        }
+
+        let maybe_value = match (self.history_len, self.get_stored(key)?) {
+            (Some(_), Some(bytes)) => Some(decode_history_value(&bytes)?.0),
+            (_, maybe_value) => maybe_value,
+        };
+
+        if let (Some(cache), Some(value)) = (self.read_cache.as_ref(), maybe_value.as_ref()) {
+            cache.borrow_mut().put(key, value);
+        }
+        Ok(maybe_value)
+    }
+
+    /// Gets the value stored in the tree for the given key, which in history
+    /// mode is the encoded value along with its history.
+    pub(crate) fn get_stored(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
         self.use_tree(|maybe_tree| {
             let mut cursor = match maybe_tree {
                 None => return Ok(None), // empty tree
@@ -113,11 +413,111 @@ impl Merk {
         })
     }
 
+    /// Returns the depth of the node for `key` (the number of hops from the
+    /// root, so the root has depth 0), or `None` if the key does not exist.
+    ///
+    /// A proof of a single key contains roughly one sibling hash and one kv
+    /// hash for each level above it, so this can be used to predict the size
+    /// of a proof before requesting it.
+    pub fn key_depth(&self, key: &[u8]) -> Result<Option<usize>> {
+        self.use_tree(|maybe_tree| {
+            let mut cursor = match maybe_tree {
+                None => return Ok(None), // empty tree
+                Some(tree) => tree,
+            };
+            let mut depth = 0;
+
+            // traverse the nodes which are in memory
+            let mut next_key = loop {
+                if key == cursor.key() {
+                    return Ok(Some(depth));
+                }
+
+                let left = key < cursor.key();
+                let link = match cursor.link(left) {
+                    None => return Ok(None), // not found
+                    Some(link) => link,
+                };
+                depth += 1;
+
+                match link.tree() {
+                    None => break link.key().to_vec(), // pruned, continue from disk
+                    Some(child) => cursor = child,
+                }
+            };
+
+            loop {
+                let node = fetch_existing_node(&self.db, &next_key)?;
+                if key == node.key() {
+                    return Ok(Some(depth));
+                }
+
+                let left = key < node.key();
+                next_key = match node.link(left) {
+                    None => return Ok(None), // not found
+                    Some(link) => link.key().to_vec(),
+                };
+                depth += 1;
+            }
+        })
+    }
+
+    /// Counts the keys within `range` by iterating over the stored nodes with
+    /// RocksDB iterator bounds set to the range, without decoding any nodes
+    /// or comparing keys in Merk.
+    ///
+    /// This is a linear scan of the range, and only counts committed nodes.
+    pub fn count_range_iter<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Result<u64> {
+        // bounds are expressed as an inclusive lower and exclusive upper key,
+        // and the key directly after `key` is `key || 0`
+        fn successor(key: &[u8]) -> Vec<u8> {
+            let mut next = key.to_vec();
+            next.push(0);
+            next
+        }
+
+        let mut read_opts = rocksdb::ReadOptions::default();
+        match range.start_bound() {
+            Bound::Included(key) => read_opts.set_iterate_lower_bound(key.clone()),
+            Bound::Excluded(key) => read_opts.set_iterate_lower_bound(successor(key)),
+            Bound::Unbounded => {}
+        }
+        match range.end_bound() {
+            Bound::Included(key) => read_opts.set_iterate_upper_bound(successor(key)),
+            Bound::Excluded(key) => read_opts.set_iterate_upper_bound(key.clone()),
+            Bound::Unbounded => {}
+        }
+
+        let mut iter = self.db.raw_iterator_opt(read_opts);
+        iter.seek_to_first();
+        let mut count = 0;
+        while iter.valid() {
+            count += 1;
+            iter.next();
+        }
+        iter.status()?;
+
+        Ok(count)
+    }
+
     /// Returns the root hash of the tree (a digest for the entire store which
     /// proofs can be checked against). If the tree is empty, returns the null
     /// hash (zero-filled).
+    ///
+    /// The hash is cached when the tree is committed or loaded, so this is a
+    /// constant-time read which does no hashing, e.g. for services which
+    /// check it on every request.
     pub fn root_hash(&self) -> Hash {
-        self.use_tree(|tree| tree.map_or(NULL_HASH, |tree| tree.hash()))
+        self.root_hash.get()
+    }
+
+    /// Recomputes the cached root hash from the in-memory tree. Called
+    /// whenever the committed tree changes.
+    fn update_root_hash(&self) {
+        let hash = self.use_tree(|tree| tree.map_or(NULL_HASH, |tree| tree.hash()));
+        self.root_hash.set(hash);
+        // a proof measured by `proof_len` is only valid for the old root
+        self.measured_proof.set(None);
     }
 
     /// Applies a batch of operations (puts and deletes) to the tree.
@@ -141,20 +541,140 @@ impl Merk {
     /// store.apply(batch, &[]).unwrap();
     /// ```
     pub fn apply(&mut self, batch: &Batch, aux: &Batch) -> Result<()> {
-        // ensure keys in batch are sorted and unique
-        let mut maybe_prev_key = None;
-        for (key, _) in batch.iter() {
-            if let Some(prev_key) = maybe_prev_key {
-                if prev_key > *key {
-                    bail!("Keys in batch must be sorted");
-                } else if prev_key == *key {
-                    bail!("Keys in batch must be unique");
+        check_batch(batch)?;
+        unsafe { self.apply_unchecked(batch, aux) }
+    }
+
+    /// Applies a batch of operations (puts and deletes) to the tree, returning
+    /// the new root hash along with the net effect of each operation, in key
+    /// order.
+    ///
+    /// Operations which would not change the store (puts of a key's current
+    /// value, or deletes of keys which do not exist) are reported as
+    /// `ChangeKind::Unchanged` and are not applied, so unlike `apply`, deleting
+    /// a missing key is not an error here.
+    ///
+    /// This will fail if the keys in `batch` are not sorted and unique.
+    pub fn apply_with_changes(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+    ) -> Result<(Hash, Vec<(Vec<u8>, ChangeKind)>)> {
+        check_batch(batch)?;
+
+        let mut changes = Vec::with_capacity(batch.len());
+        let mut effective_batch = Vec::with_capacity(batch.len());
+        for (key, op) in batch.iter() {
+            let kind = match (op, self.get(key)?) {
+                (Op::Put(value), Some(old_value)) if *value == old_value => ChangeKind::Unchanged,
+                (Op::Put(_), Some(_)) => ChangeKind::Updated,
+                (Op::Put(_), None) => ChangeKind::Inserted,
+                (Op::Delete, Some(_)) => ChangeKind::Deleted,
+                (Op::Delete, None) => ChangeKind::Unchanged,
+            };
+
+            if kind != ChangeKind::Unchanged {
+                let op = match op {
+                    Op::Put(value) => Op::Put(value.clone()),
+                    Op::Delete => Op::Delete,
+                };
+                effective_batch.push((key.clone(), op));
+            }
+            changes.push((key.clone(), kind));
+        }
+
+        unsafe { self.apply_unchecked(&effective_batch, aux)? };
+
+        Ok((self.root_hash(), changes))
+    }
+
+    /// Applies a batch of operations (puts and deletes) to the tree, without
+    /// any aux operations. This is equivalent to `apply(batch, &[])`; the
+    /// commit path only writes to the aux column family once per aux entry,
+    /// so this never issues any operations against it.
+    pub fn apply_tree(&mut self, batch: &Batch) -> Result<()> {
+        self.apply(batch, &[])
+    }
+
+    /// Moves the value stored at `from` to `to`, as a single atomic commit of
+    /// a delete of `from` and a put to `to`. The value is read once
+    /// internally, so it does not need to be passed through the caller. The
+    /// resulting root hash is the same as applying the delete and put
+    /// explicitly.
+    ///
+    /// If `to` already exists, its value is overwritten. Errors if `from` does
+    /// not exist. Renaming a key to itself does nothing.
+    pub fn rename(&mut self, from: &[u8], to: &[u8]) -> Result<()> {
+        let value = match self.get(from)? {
+            None => bail!("Key to rename does not exist"),
+            Some(value) => value,
+        };
+        if from == to {
+            return Ok(());
+        }
+
+        let mut batch = vec![(from.to_vec(), Op::Delete), (to.to_vec(), Op::Put(value))];
+        batch.sort_by(|a, b| a.0.cmp(&b.0));
+        self.apply(&batch, &[])
+    }
+
+    /// Puts all of the entries of `other` into this store, in a single batch.
+    /// The resulting root hash is the same as applying all of `other`'s
+    /// entries as one batch.
+    ///
+    /// If a key exists in both stores, this errors without modifying the
+    /// store, unless `overwrite` is set, in which case the value from `other`
+    /// replaces the existing one. Since the entries are read from `other` in
+    /// key order, merging stores with disjoint key ranges takes the sorted
+    /// fast path of `apply`. All of `other`'s entries are held in memory
+    /// while they are applied.
+    ///
+    /// Errors if `other` has an active write group, since its entries are read
+    /// from its committed nodes, or if only one of the stores is in history
+    /// mode (see `open_with_history`). When both are, the current values of
+    /// `other` are put with `apply`, so this store keeps its own history for
+    /// each key and `other`'s history is not carried over.
+    pub fn merge_from(&mut self, other: &Merk, overwrite: bool) -> Result<()> {
+        fn collect(db: &rocksdb::DB, key: &[u8], batch: &mut Vec<(Vec<u8>, Op)>) -> Result<()> {
+            let node = fetch_existing_node(db, key)?;
+            if let Some(link) = node.link(true) {
+                collect(db, link.key(), batch)?;
+            }
+            batch.push((node.key().to_vec(), Op::Put(node.value().to_vec())));
+            if let Some(link) = node.link(false) {
+                collect(db, link.key(), batch)?;
+            }
+            Ok(())
+        }
+
+        if other.in_write_group() {
+            bail!("Cannot merge from a store with an active write group");
+        }
+        if self.history_len.is_some() != other.history_len.is_some() {
+            bail!("Cannot merge stores which are not in the same history mode");
+        }
+
+        let mut batch = vec![];
+        if let Some(root) = other.fetch_root()? {
+            collect(&other.db, root.key(), &mut batch)?;
+        }
+        if other.history_len.is_some() {
+            for (_, op) in batch.iter_mut() {
+                if let Op::Put(value) = op {
+                    *value = decode_history_value(value)?.0;
                 }
             }
-            maybe_prev_key = Some(key.to_vec());
         }
 
-        unsafe { self.apply_unchecked(batch, aux) }
+        if !overwrite {
+            for (key, _) in batch.iter() {
+                if self.get(key)?.is_some() {
+                    bail!("Key {:?} exists in both stores", key);
+                }
+            }
+        }
+
+        self.apply(&batch, &[])
     }
 
     /// Applies a batch of operations (puts and deletes) to the tree.
@@ -164,6 +684,11 @@ impl Merk {
     /// this method which checks to ensure the batch is sorted and unique, see
     /// `apply`.
     ///
+    /// If the operation fails (e.g. a delete of a key which does not exist, or
+    /// an error writing to RocksDB), nothing is written and the in-memory tree
+    /// is reloaded from disk, so the store is left in its pre-apply state and
+    /// can continue to be used without reopening.
+    ///
     /// # Example
     /// ```
     /// # let mut store = merk::test_utils::TempMerk::new().unwrap();
@@ -178,17 +703,400 @@ impl Merk {
     /// unsafe { store.apply_unchecked(batch, &[]).unwrap() };
     /// ```
     pub unsafe fn apply_unchecked(&mut self, batch: &Batch, aux: &Batch) -> Result<()> {
+        self.apply_unchecked_opts(batch, aux, ApplyOpts::default())?;
+        Ok(())
+    }
+
+    /// Applies a batch of operations to the tree like `apply`, writing the
+    /// commit with the given RocksDB write options. This allows choosing the
+    /// durability of each commit, e.g. only syncing the last of several
+    /// commits.
+    ///
+    /// Note that with `disable_wal`, the commit will be lost if the process
+    /// crashes before a later commit using the write-ahead log (or a flush)
+    /// persists it. Later commits may then depend on nodes which were never
+    /// written, so `disable_wal` should only be used when the store would be
+    /// discarded or rebuilt after a crash, or followed by a `Merk::flush`.
+    ///
+    /// This will fail if the keys in `batch` are not sorted and unique.
+    pub fn apply_with_opts(&mut self, batch: &Batch, aux: &Batch, opts: WriteOpts) -> Result<()> {
+        check_batch(batch)?;
+        let opts = ApplyOpts {
+            write_opts: opts,
+            ..Default::default()
+        };
+        unsafe { self.apply_unchecked_opts(batch, aux, opts)? };
+        Ok(())
+    }
+
+    /// The commit path shared by `apply` and its variants, which differ only
+    /// in `opts`. Returns the number of bytes the commit added to the RocksDB
+    /// write batch.
+    pub(crate) unsafe fn apply_unchecked_opts(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+        mut opts: ApplyOpts,
+    ) -> Result<usize> {
+        fn elapsed(start: Instant) -> u64 {
+            start.elapsed().as_nanos() as u64
+        }
+
+        if let Some(group) = self.write_group.as_ref() {
+            if group.poisoned {
+                bail!("The write group was discarded by a failed apply");
+            }
+            if opts.user_batch.is_some() {
+                bail!("Cannot apply into a user batch while a write group is active");
+            }
+        }
+
+        // within a write group the resolved merge deltas aren't deleted until
+        // the group ends, so they are left for the first apply after it
+        let start = Instant::now();
+        let fold_merges = self.write_group.is_none();
+        let (prepared, resolved_keys) = self.prepare_batch(batch, fold_merges)?;
+        let batch = prepared.as_deref().unwrap_or(batch);
+        let (deleted_keys, external_deletes) = self.apply_to_tree(batch)?;
+        if let Some(timings) = opts.timings.as_mut() {
+            timings.tree_walk = elapsed(start);
+        }
+
+        // commit changes to the caller's batch, to the current write group, or
+        // to a batch of their own
+        let start = Instant::now();
+        let mut group_batch = self
+            .write_group
+            .as_mut()
+            .map(|group| std::mem::take(&mut group.batch));
+        let mut own_batch = WriteBatch::default();
+        let write_batch = match (opts.user_batch.as_mut(), group_batch.as_mut()) {
+            (Some(user_batch), _) => &mut **user_batch,
+            (None, Some(group_batch)) => group_batch,
+            (None, None) => &mut own_batch,
+        };
+        let prev_size = write_batch.size_in_bytes();
+        let res = self.commit_into(deleted_keys, external_deletes, aux, write_batch);
+        let res = res.map(|_| {
+            let merges_cf = self.db.cf_handle(MERGES_CF_NAME).unwrap();
+            for key in resolved_keys.iter() {
+                write_batch.delete_cf(merges_cf, key);
+            }
+            if let Some(height) = opts.height {
+                let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+                write_batch.put_cf(internal_cf, versions::version_key(height), self.root_hash());
+            }
+            write_batch.size_in_bytes() - prev_size
+        });
+        if let Some(timings) = opts.timings.as_mut() {
+            timings.hash = elapsed(start);
+        }
+
+        let start = Instant::now();
+        let res = res.and_then(|written| {
+            if let Some(group) = self.write_group.as_mut() {
+                group.batch = group_batch.take().unwrap();
+                group.heights.extend(opts.height);
+            } else if opts.user_batch.is_none() {
+                self.write_with_opts(own_batch, opts.write_opts)?;
+            }
+            Ok(written)
+        });
+        if let Some(timings) = opts.timings.as_mut() {
+            timings.rocksdb_write = elapsed(start);
+        }
+
+        match res {
+            Ok(written) => {
+                for key in resolved_keys.iter() {
+                    self.pending_merges.remove(key);
+                }
+                Ok(written)
+            }
+            Err(err) => {
+                // the in-memory tree is ahead of the db, roll it back
+                self.load_root()?;
+                Err(err)
+            }
+        }
+    }
+
+    /// Applies a batch of operations to the tree exactly like `apply`, and
+    /// returns how long each phase of the apply took.
+    ///
+    /// The timing overhead is only incurred when calling this method, so
+    /// `apply` is unaffected.
+    pub fn apply_timed(&mut self, batch: &Batch, aux: &Batch) -> Result<Timings> {
+        let mut timings = Timings::default();
+
+        let start = Instant::now();
+        check_batch(batch)?;
+        timings.sort = start.elapsed().as_nanos() as u64;
+
+        let opts = ApplyOpts {
+            timings: Some(&mut timings),
+            ..Default::default()
+        };
+        unsafe { self.apply_unchecked_opts(batch, aux, opts)? };
+
+        Ok(timings)
+    }
+
+    /// Applies a batch of operations to the tree like `apply`, but appends the
+    /// resulting writes to `user_batch` instead of writing them to the db.
+    /// This lets the caller commit the Merk's changes atomically with writes
+    /// to their own column families in the same db (see
+    /// `open_with_column_families`).
+    ///
+    /// The in-memory tree is updated immediately, so the caller is
+    /// responsible for writing `user_batch` via `Merk::db`. If the batch is
+    /// not written (or the write fails), the in-memory tree no longer matches
+    /// the db and the store must be reopened before it is used again.
+    ///
+    /// This will fail if the keys in `batch` are not sorted and unique, or if
+    /// a write group is active (since the group's writes would then be
+    /// committed separately from `user_batch`).
+    pub fn apply_into(
+        &mut self,
+        batch: &Batch,
+        aux: &Batch,
+        user_batch: &mut WriteBatch,
+    ) -> Result<()> {
+        check_batch(batch)?;
+
+        let opts = ApplyOpts {
+            user_batch: Some(user_batch),
+            ..Default::default()
+        };
+        unsafe { self.apply_unchecked_opts(batch, aux, opts)? };
+        Ok(())
+    }
+
+    /// Prepares `batch` the way every commit does before applying it to the
+    /// tree: pending `apply_merge` deltas are folded in as puts (if
+    /// `fold_merges` is set), then in history mode each put is mapped to a
+    /// value carrying the key's history. Returns the prepared batch (or `None`
+    /// if it is unchanged) along with the keys of the resolved deltas, which
+    /// should be deleted from the merges column family in the same commit.
+    fn prepare_batch(
+        &self,
+        batch: &Batch,
+        fold_merges: bool,
+    ) -> Result<(Option<Vec<BatchEntry>>, Vec<Vec<u8>>)> {
+        let folded = if fold_merges {
+            self.fold_merges(batch)?
+        } else {
+            None
+        };
+        let (mut prepared, resolved_keys) = match folded {
+            None => (None, vec![]),
+            Some((merged, resolved_keys)) => (Some(merged), resolved_keys),
+        };
+
+        // in history mode, carry each key's prior values into its new value
+        if let Some(max_len) = self.history_len {
+            let batch = prepared.as_deref().unwrap_or(batch);
+            prepared = Some(self.fold_history(batch, max_len)?);
+        }
+
+        Ok((prepared, resolved_keys))
+    }
+
+    /// Applies the batch to the in-memory tree, returning the keys of the
+    /// deleted nodes and of those among them whose values were stored outside
+    /// of the node. On failure, the in-memory tree is reloaded from disk.
+    fn apply_to_tree(&mut self, batch: &Batch) -> Result<(LinkedList<Vec<u8>>, Vec<Vec<u8>>)> {
+        // invalidate before the tree changes, so no stale value can be read
+        // from the cache once it has
+        if let Some(cache) = self.read_cache.as_mut() {
+            let cache = cache.get_mut();
+            for (key, _) in batch.iter() {
+                cache.invalidate(key);
+            }
+        }
+
         let maybe_walker = self
             .tree
             .take()
             .take()
             .map(|tree| Walker::new(tree, self.source()));
 
-        let (maybe_tree, deleted_keys) = Walker::apply_to(maybe_walker, batch)?;
+        let mut external_deletes = vec![];
+        let res = Walker::apply_to_tracked(maybe_walker, batch, &mut external_deletes);
+        let (maybe_tree, deleted_keys) = match res {
+            Ok(res) => res,
+            Err(err) => {
+                // the tree was consumed by the failed operation, so restore the
+                // last committed state from disk
+                self.load_root()?;
+                return Err(err);
+            }
+        };
         self.tree.set(maybe_tree);
 
-        // commit changes to db
-        self.commit(deleted_keys, aux)
+        Ok((deleted_keys, external_deletes))
+    }
+
+    /// Returns the serialized RocksDB `WriteBatch` which `apply` would write
+    /// for the given batch and aux operations, without modifying the in-memory
+    /// tree or writing anything to disk. Any pending `apply_merge` deltas (and
+    /// in history mode, each key's history) are folded in, as they would be by
+    /// `apply`.
+    ///
+    /// This is primarily intended for golden-file tests which detect
+    /// unintended changes to the writes Merk produces. The serialization is
+    /// RocksDB's internal `WriteBatch` format along with Merk's node encoding,
+    /// so it may change across major versions of either.
+    ///
+    /// This will fail if the keys in `batch` are not sorted and unique, or if
+    /// a write group is active (since the batch is applied to the committed
+    /// nodes).
+    pub fn apply_batch_bytes(&self, batch: &Batch, aux: &Batch) -> Result<Vec<u8>> {
+        if self.in_write_group() {
+            bail!("Cannot compute the batch bytes while a write group is active");
+        }
+        check_batch(batch)?;
+
+        let (prepared, resolved_keys) = self.prepare_batch(batch, true)?;
+        let batch = prepared.as_deref().unwrap_or(batch);
+
+        let maybe_walker = self
+            .fetch_root()?
+            .map(|tree| Walker::new(tree, self.source()));
+        let mut external_deletes = vec![];
+        let (mut maybe_tree, deleted_keys) =
+            Walker::apply_to_tracked(maybe_walker, batch, &mut external_deletes)?;
+
+        let mut write_batch = WriteBatch::default();
+        self.commit_tree_into(
+            maybe_tree.as_mut(),
+            deleted_keys,
+            external_deletes,
+            aux,
+            &mut write_batch,
+            None,
+        )?;
+        let merges_cf = self.db.cf_handle(MERGES_CF_NAME).unwrap();
+        for key in resolved_keys {
+            write_batch.delete_cf(merges_cf, key);
+        }
+
+        Ok(write_batch.data().to_vec())
+    }
+
+    /// Computes the root hash the tree would have after applying the staged
+    /// operations in `batch`, without modifying the in-memory tree or writing
+    /// anything to disk. Any pending `apply_merge` deltas (and in history
+    /// mode, each key's history) are folded in, as they would be by `apply`.
+    ///
+    /// This is useful to preview the hash while building up a set of
+    /// operations over multiple steps. The returned hash is speculative: it is
+    /// computed against the currently committed state, so it will not match
+    /// the hash after commit if the staged set or the store change in the
+    /// meantime.
+    ///
+    /// This will fail if the keys in `batch` are not sorted and unique, or if
+    /// a write group is active (since the batch is applied to the committed
+    /// nodes).
+    pub fn staged_root_hash(&self, batch: &Batch) -> Result<Hash> {
+        if self.in_write_group() {
+            bail!("Cannot compute a staged root hash while a write group is active");
+        }
+        check_batch(batch)?;
+
+        let (prepared, _) = self.prepare_batch(batch, true)?;
+        let batch = prepared.as_deref().unwrap_or(batch);
+
+        let maybe_walker = self
+            .fetch_root()?
+            .map(|tree| Walker::new(tree, self.source()));
+        let (maybe_tree, _) = Walker::apply_to(maybe_walker, batch)?;
+
+        match maybe_tree {
+            None => Ok(NULL_HASH),
+            Some(mut tree) => {
+                // computes the hashes of the modified nodes
+                tree.commit(&mut NoopCommit {})?;
+                Ok(tree.hash())
+            }
+        }
+    }
+
+    /// Computes a fingerprint of the tree's shape, covering each node's key,
+    /// height and children but not its value. Two stores with the same keys
+    /// arranged the same way will have the same structure hash even if their
+    /// values differ, which helps tell structural divergences apart from
+    /// value divergences when debugging.
+    ///
+    /// This scans every node from disk. It is a diagnostic tool only and is
+    /// **not** a security primitive: unlike the root hash, it is not committed
+    /// to by proofs and must not be relied on for verification.
+    pub fn structure_hash(&self) -> Result<Hash> {
+        fn hash_node(db: &rocksdb::DB, tree: &Tree) -> Result<Hash> {
+            let child_hash = |left| -> Result<Hash> {
+                match tree.link(left) {
+                    None => Ok(NULL_HASH),
+                    Some(link) => hash_node(db, &fetch_existing_node(db, link.key())?),
+                }
+            };
+            let left = child_hash(true)?;
+            let right = child_hash(false)?;
+            Ok(structure_hash(tree.key(), tree.height(), &left, &right))
+        }
+
+        match self.fetch_root()? {
+            None => Ok(NULL_HASH),
+            Some(tree) => hash_node(&self.db, &tree),
+        }
+    }
+
+    /// Returns the keys of all node entries in the db which are not reachable
+    /// from the root, in key order. A healthy store has none, so any returned
+    /// keys indicate an interrupted write or corruption.
+    ///
+    /// This reads every node from disk and holds all reachable keys in
+    /// memory, so it is expensive for large stores.
+    pub fn find_orphans(&self) -> Result<Vec<Vec<u8>>> {
+        let mut reachable = HashSet::new();
+        let mut stack = vec![];
+        if let Some(root) = self.fetch_root()? {
+            stack.push(root);
+        }
+        while let Some(node) = stack.pop() {
+            for left in [true, false].iter() {
+                if let Some(link) = node.link(*left) {
+                    stack.push(fetch_existing_node(&self.db, link.key())?);
+                }
+            }
+            reachable.insert(node.key().to_vec());
+        }
+
+        let mut orphans = vec![];
+        let mut iter = self.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            let key = iter.key().unwrap();
+            if !reachable.contains(key) {
+                orphans.push(key.to_vec());
+            }
+            iter.next();
+        }
+
+        Ok(orphans)
+    }
+
+    /// Deletes all orphaned node entries (see `find_orphans`) from the db,
+    /// returning their keys. This has the same cost as `find_orphans`.
+    pub fn gc_orphans(&mut self) -> Result<Vec<Vec<u8>>> {
+        let orphans = self.find_orphans()?;
+
+        let mut batch = WriteBatch::default();
+        for key in orphans.iter() {
+            batch.delete(key);
+        }
+        self.write(batch)?;
+
+        Ok(orphans)
     }
 
     /// Closes the store and deletes all data from disk.
@@ -213,20 +1121,49 @@ impl Merk {
     /// unique you can use the unsafe `prove_unchecked` for a small performance
     /// gain.
     pub fn prove(&self, query: &[Vec<u8>]) -> Result<Vec<u8>> {
-        // ensure keys in query are sorted and unique
-        let mut maybe_prev_key = None;
-        for key in query.iter() {
-            if let Some(prev_key) = maybe_prev_key {
-                if prev_key > *key {
-                    bail!("Keys in query must be sorted");
-                } else if prev_key == *key {
-                    bail!("Keys in query must be unique");
-                }
+        check_query_keys(query)?;
+
+        if let Some(measured) = self.measured_proof.take() {
+            if measured.root_hash == self.root_hash() && measured.query_hash == query_hash(query) {
+                return Ok(measured.proof);
             }
-            maybe_prev_key = Some(key.to_vec());
+            // keep it for a later call with the measured query
+            self.measured_proof.set(Some(measured));
+        }
+
+        let cache = match self.proof_cache.as_ref() {
+            None => return unsafe { self.prove_unchecked(query) },
+            Some(cache) => cache,
+        };
+
+        let root_hash = self.root_hash();
+        let query_hash = query_hash(query);
+        if let Some(proof) = cache.get(&self.db, &root_hash, &query_hash)? {
+            return Ok(proof);
         }
 
-        unsafe { self.prove_unchecked(query) }
+        let proof = unsafe { self.prove_unchecked(query)? };
+        cache.insert(&self.db, &root_hash, &query_hash, proof.as_slice())?;
+        Ok(proof)
+    }
+
+    /// Returns the exact length in bytes of the proof `prove` would return for
+    /// `query`, for checking a proof's size before sending it.
+    ///
+    /// The proof is generated to measure it, and kept so that a following
+    /// call to `prove` with the same query returns it without generating it
+    /// again. Only the most recently measured proof is kept, and it is
+    /// discarded once returned by `prove` or when the tree changes, but not
+    /// by proving other queries in between.
+    pub fn proof_len(&self, query: &[Vec<u8>]) -> Result<usize> {
+        let proof = self.prove(query)?;
+        let len = proof.len();
+        self.measured_proof.set(Some(MeasuredProof {
+            root_hash: self.root_hash(),
+            query_hash: query_hash(query),
+            proof,
+        }));
+        Ok(len)
     }
 
     /// Creates a Merkle proof for the list of queried keys. For each key in the
@@ -257,48 +1194,302 @@ impl Merk {
         })
     }
 
+    /// Creates a Merkle proof for the list of queried keys like `prove`, but
+    /// encodes it into `bytes` (clearing it first) rather than a new buffer,
+    /// so generating proofs in a tight loop reuses one allocation for the
+    /// proof bytes. The proof cache (if any) is bypassed.
+    ///
+    /// Only the output buffer is reused - the proven nodes are read from the
+    /// in-memory tree, which keeps any nodes it loads from disk.
+    pub fn prove_into(&self, query: &[Vec<u8>], bytes: &mut Vec<u8>) -> Result<()> {
+        check_query_keys(query)?;
+
+        self.use_tree_mut(|maybe_tree| {
+            let tree = match maybe_tree {
+                None => bail!("Cannot create proof for empty tree"),
+                Some(tree) => tree,
+            };
+
+            let mut ref_walker = RefWalker::new(tree, self.source());
+            let (proof, _) = ref_walker.create_proof(query)?;
+
+            bytes.clear();
+            encode_into(proof.iter(), bytes);
+            Ok(())
+        })
+    }
+
+    /// Creates a Merkle proof for the keys and ranges in `query`. Every key
+    /// in the store which falls within a queried range is proven to be in the
+    /// tree, along with the boundary keys needed to prove that no other keys
+    /// exist within the ranges.
+    ///
+    /// The proof returned is in an encoded format which can be verified with
+    /// `merk::verify`.
+    pub fn prove_query(&self, query: &Query) -> Result<Vec<u8>> {
+        self.use_tree_mut(|maybe_tree| {
+            let tree = match maybe_tree {
+                None => bail!("Cannot create proof for empty tree"),
+                Some(tree) => tree,
+            };
+
+            let mut ref_walker = RefWalker::new(tree, self.source());
+            let (proof, _) = ref_walker.create_query_proof(query.items())?;
+
+            let mut bytes = Vec::with_capacity(128);
+            encode_into(proof.iter(), &mut bytes);
+            Ok(bytes)
+        })
+    }
+
+    /// Creates a Merkle proof that every key within `range` maps to `value`,
+    /// to be verified with `merk::proofs::verify_uniform_range`. The proof
+    /// covers the whole range, including the boundaries which prove there are
+    /// no other keys within it.
+    ///
+    /// Errors if any key within the range has a different value.
+    pub fn prove_uniform_range(&self, range: QueryItem, value: &[u8]) -> Result<Vec<u8>> {
+        let mut query = Query::new();
+        query.insert_item(range.clone());
+        let proof = self.prove_query(&query)?;
+
+        let map = crate::proofs::verify(proof.as_slice(), self.root_hash())?;
+        for (key, entry_value) in map.query_item(&range)? {
+            if entry_value.as_slice() != value {
+                bail!("Key {:?} in range does not have the uniform value", key);
+            }
+        }
+
+        Ok(proof)
+    }
+
+    /// Scans `range` for the entries matching `predicate`, returning them along
+    /// with a proof to be verified with `merk::proofs::verify_filtered_range`.
+    ///
+    /// For the verifier to trust that the filter was applied honestly, it has
+    /// to see the entries which did not match as well, so the proof covers
+    /// every entry in the range (including the boundaries which prove there
+    /// are no other keys within it) and the verifier re-applies the predicate.
+    /// The proof is therefore as large as a proof of the whole range no matter
+    /// how selective the predicate is.
+    pub fn prove_filtered_range(
+        &self,
+        range: QueryItem,
+        predicate: impl Fn(&[u8], &[u8]) -> bool,
+    ) -> Result<(Vec<(Vec<u8>, Vec<u8>)>, Vec<u8>)> {
+        let mut query = Query::new();
+        query.insert_item(range.clone());
+        let proof = self.prove_query(&query)?;
+
+        let map = crate::proofs::verify(proof.as_slice(), self.root_hash())?;
+        let matches = map
+            .query_item(&range)?
+            .into_iter()
+            .filter(|(key, value)| predicate(key, value))
+            .collect();
+
+        Ok((matches, proof))
+    }
+
+    /// Creates a Merkle proof containing the nodes for keys `a` and `b` and the
+    /// paths to them from the root (which share their common ancestors), to be
+    /// verified with `merk::proofs::verify_path_between`. This is smaller than
+    /// a proof of the whole range between the keys when they are far apart.
+    ///
+    /// `a` and `b` may be equal. Errors if either key does not exist.
+    pub fn prove_path_between(&self, a: &[u8], b: &[u8]) -> Result<Vec<u8>> {
+        for key in [a, b].iter() {
+            if self.get(key)?.is_none() {
+                bail!("Key {:?} does not exist", key);
+            }
+        }
+
+        let mut query = vec![a.to_vec(), b.to_vec()];
+        query.sort();
+        query.dedup();
+        self.prove(&query)
+    }
+
+    /// Creates a Merkle proof containing every key/value pair in the store.
+    /// Once verified with `merk::verify`, all of the entries can be read back
+    /// with `Map::range(..)`.
+    ///
+    /// This is equivalent to proving an unbounded range query. The proof
+    /// grows with the size of the whole store, so this is only meant for
+    /// small trees (e.g. configuration data) - to transfer a large store, use
+    /// the chunk-based replication in `merk::chunks` instead.
+    pub fn prove_all(&self) -> Result<Vec<u8>> {
+        let mut query = Query::new();
+        query.insert_range_from(vec![]..);
+        self.prove_query(&query)
+    }
+
+    /// Deletes every key which starts with `prefix`, returning the new root
+    /// hash along with a proof of all the key/value pairs which were deleted.
+    ///
+    /// The proof is created against the root hash from before the deletion,
+    /// so it can be verified with `merk::verify` and the old root hash, then
+    /// queried with `QueryItem::prefix(prefix)` to get the removed entries.
+    /// If the store is empty, the proof is empty, which `merk::verify`
+    /// accepts against `NULL_HASH` as proving that nothing was deleted.
+    ///
+    /// Errors if a write group is active, since the keys to delete are read
+    /// from the committed nodes.
+    pub fn delete_prefix_with_proof(&mut self, prefix: &[u8]) -> Result<(Hash, Vec<u8>)> {
+        if self.in_write_group() {
+            bail!("Cannot delete a prefix while a write group is active");
+        }
+
+        let mut query = Query::new();
+        query.insert_item(QueryItem::prefix(prefix));
+
+        let proof = if self.use_tree(|maybe_tree| maybe_tree.is_none()) {
+            vec![]
+        } else {
+            self.prove_query(&query)?
+        };
+
+        let mut batch = vec![];
+        {
+            let mut iter = self.raw_iter();
+            iter.seek(prefix);
+            while iter.valid() {
+                let key = iter.key().unwrap();
+                if !key.starts_with(prefix) {
+                    break;
+                }
+                batch.push((key.to_vec(), Op::Delete));
+                iter.next();
+            }
+        }
+
+        self.apply(&batch, &[])?;
+
+        Ok((self.root_hash(), proof))
+    }
+
     pub fn flush(&self) -> Result<()> {
         Ok(self.db.flush()?)
     }
 
+    /// Re-reads the committed root from RocksDB, discarding all nodes held in
+    /// memory and refreshing the cached root hash. This picks up changes made
+    /// to the store's db outside of this `Merk` (e.g. by writing nodes and a
+    /// new root through `Merk::db`) without reopening it.
+    ///
+    /// The read cache (if any) is cleared. Errors if a write group is active,
+    /// since its uncommitted changes would be lost.
+    pub fn reload(&mut self) -> Result<()> {
+        if self.write_group.is_some() {
+            bail!("Cannot reload while a write group is active");
+        }
+        self.load_root()
+    }
+
     pub fn commit(&mut self, deleted_keys: LinkedList<Vec<u8>>, aux: &Batch) -> Result<()> {
+        // the placement of the deleted values isn't known here, so any
+        // separately stored values for them are deleted too
+        let external_deletes = deleted_keys.iter().cloned().collect();
+        let mut batch = WriteBatch::default();
+        self.commit_into(deleted_keys, external_deletes, aux, &mut batch)?;
+
+        // write to db
+        self.write(batch)?;
+
+        Ok(())
+    }
+
+    /// Commits the in-memory tree and adds the resulting node writes, the
+    /// deletions in `deleted_keys` (and of the separately stored values in
+    /// `external_deletes`), the root pointer update and the aux operations to
+    /// `batch`, without writing it to the db.
+    fn commit_into(
+        &mut self,
+        deleted_keys: LinkedList<Vec<u8>>,
+        external_deletes: Vec<Vec<u8>>,
+        aux: &Batch,
+        batch: &mut WriteBatch,
+    ) -> Result<()> {
+        let mut tree = self.tree.take();
+        let mut hooks = self.node_hooks.take();
+        let res = self.commit_tree_into(
+            tree.as_mut(),
+            deleted_keys,
+            external_deletes,
+            aux,
+            batch,
+            hooks.as_mut(),
+        );
+        self.node_hooks = hooks;
+        self.tree.set(tree);
+        if res.is_ok() {
+            self.update_root_hash();
+        }
+        res
+    }
+
+    /// Commits the given tree (which need not be the in-memory tree) and adds
+    /// the resulting writes to `batch`, as in `commit_into`, invoking `hooks`
+    /// (if any) for each node written or deleted.
+    fn commit_tree_into(
+        &self,
+        maybe_tree: Option<&mut Tree>,
+        deleted_keys: LinkedList<Vec<u8>>,
+        external_deletes: Vec<Vec<u8>>,
+        aux: &Batch,
+        batch: &mut WriteBatch,
+        mut hooks: Option<&mut NodeHooks>,
+    ) -> Result<()> {
         let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
-        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
+        let values_cf = self.db.cf_handle(VALUES_CF_NAME).unwrap();
 
-        let mut batch = rocksdb::WriteBatch::default();
-        let mut to_batch =
-            self.use_tree_mut(|maybe_tree| -> Result<Vec<(Vec<u8>, Option<Vec<u8>>)>> {
-                // TODO: concurrent commit
-                if let Some(tree) = maybe_tree {
-                    // TODO: configurable committer
-                    let mut committer = MerkCommitter::new(tree.height(), 100);
-                    tree.commit(&mut committer)?;
+        // TODO: concurrent commit
+        let (mut to_batch, mut values) = if let Some(tree) = maybe_tree {
+            // TODO: configurable committer
+            let mut committer = MerkCommitter::new(tree.height(), 100, self.inline_threshold);
+            tree.commit(&mut committer)?;
 
-                    // update pointer to root node
-                    batch.put_cf(internal_cf, ROOT_KEY_KEY, tree.key());
+            // update pointer to root node
+            batch.put_cf(internal_cf, ROOT_KEY_KEY, tree.key());
 
-                    Ok(committer.batch)
-                } else {
-                    // empty tree, delete pointer to root
-                    batch.delete_cf(internal_cf, ROOT_KEY_KEY);
+            (committer.batch, committer.values)
+        } else {
+            // empty tree, delete pointer to root
+            batch.delete_cf(internal_cf, ROOT_KEY_KEY);
 
-                    Ok(vec![])
-                }
-            })?;
+            (vec![], vec![])
+        };
 
         // TODO: move this to MerkCommitter impl?
         for key in deleted_keys {
             to_batch.push((key, None));
         }
+        for key in external_deletes {
+            values.push((key, None));
+        }
         to_batch.sort_by(|a, b| a.0.cmp(&b.0));
         for (key, maybe_value) in to_batch {
             if let Some(value) = maybe_value {
+                if let Some(hooks) = hooks.as_mut() {
+                    hooks.node_written(&key, &value)?;
+                }
                 batch.put(key, value);
             } else {
+                if let Some(hooks) = hooks.as_mut() {
+                    hooks.node_deleted(&key)?;
+                }
                 batch.delete(key);
             }
         }
+        for (key, maybe_value) in values {
+            if let Some(value) = maybe_value {
+                batch.put_cf(values_cf, key, value);
+            } else {
+                batch.delete_cf(values_cf, key);
+            }
+        }
 
+        let aux_cf = self.db.cf_handle(AUX_CF_NAME).unwrap();
         for (key, value) in aux {
             match value {
                 Op::Put(value) => batch.put_cf(aux_cf, key, value),
@@ -306,9 +1497,6 @@ impl Merk {
             };
         }
 
-        // write to db
-        self.write(batch)?;
-
         Ok(())
     }
 
@@ -322,13 +1510,22 @@ impl Merk {
         res
     }
 
+    /// Returns the underlying RocksDB instance, e.g. to access user-defined
+    /// column families or to write a batch filled by `apply_into`.
+    ///
+    /// Writing to the Merk's own column families directly will corrupt the
+    /// store.
+    pub fn db(&self) -> &rocksdb::DB {
+        &self.db
+    }
+
     pub fn raw_iter(&self) -> rocksdb::DBRawIterator {
         self.db.raw_iterator()
     }
 
     pub fn checkpoint<P: AsRef<Path>>(&self, path: P) -> Result<Merk> {
         Checkpoint::new(&self.db)?.create_checkpoint(&path)?;
-        Merk::open(path)
+        self.open_in_same_mode(path)
     }
 
     fn source(&self) -> MerkSource {
@@ -350,10 +1547,19 @@ impl Merk {
     }
 
     pub(crate) fn write(&mut self, batch: WriteBatch) -> Result<()> {
-        let mut opts = rocksdb::WriteOptions::default();
-        opts.set_sync(false);
         // TODO: disable WAL once we can ensure consistency with transactions
-        self.db.write_opt(batch, &opts)?;
+        self.write_with_opts(batch, WriteOpts::default())
+    }
+
+    pub(crate) fn write_with_opts(&mut self, batch: WriteBatch, opts: WriteOpts) -> Result<()> {
+        #[cfg(test)]
+        {
+            if self.fail_writes {
+                bail!("Injected write failure");
+            }
+        }
+
+        self.db.write_opt(batch, &opts.to_rocksdb())?;
         Ok(())
     }
 
@@ -368,16 +1574,67 @@ impl Merk {
         fetch_node(&self.db, key)
     }
 
+    pub(crate) fn fetch_external_value(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        fetch_external_value(&self.db, key)
+    }
+
     pub(crate) fn load_root(&mut self) -> Result<()> {
+        // rolling back to the committed state discards the changes of any
+        // active group, so it must not be written when it ends
+        if let Some(group) = self.write_group.as_mut() {
+            group.poisoned = true;
+        }
+        // values read from the discarded state may have been cached
+        if let Some(cache) = self.read_cache.as_mut() {
+            cache.get_mut().clear();
+        }
+        let tree = self.fetch_root()?;
+        self.tree = Cell::new(tree);
+        self.update_root_hash();
+        Ok(())
+    }
+
+    /// Reads the committed root node from disk, independently of the
+    /// in-memory tree.
+    fn fetch_root(&self) -> Result<Option<Tree>> {
         let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
-        let tree = self
-            .db
+        self.db
             .get_pinned_cf(internal_cf, ROOT_KEY_KEY)?
             .map(|root_key| fetch_existing_node(&self.db, &root_key))
-            .transpose()?;
-        self.tree = Cell::new(tree);
-        Ok(())
+            .transpose()
+    }
+}
+
+/// Ensures the keys in `batch` are sorted and unique.
+fn check_batch(batch: &Batch) -> Result<()> {
+    let mut maybe_prev_key: Option<&[u8]> = None;
+    for (key, _) in batch.iter() {
+        if let Some(prev_key) = maybe_prev_key {
+            if prev_key > key.as_slice() {
+                bail!("Keys in batch must be sorted");
+            } else if prev_key == key.as_slice() {
+                bail!("Keys in batch must be unique");
+            }
+        }
+        maybe_prev_key = Some(key);
+    }
+    Ok(())
+}
+
+/// Ensures the keys in `query` are sorted and unique.
+fn check_query_keys(query: &[Vec<u8>]) -> Result<()> {
+    let mut maybe_prev_key: Option<&[u8]> = None;
+    for key in query.iter() {
+        if let Some(prev_key) = maybe_prev_key {
+            if prev_key > key.as_slice() {
+                bail!("Keys in query must be sorted");
+            } else if prev_key == key.as_slice() {
+                bail!("Keys in query must be unique");
+            }
+        }
+        maybe_prev_key = Some(key);
     }
+    Ok(())
 }
 
 #[derive(Clone)]
@@ -393,28 +1650,54 @@ impl<'a> Fetch for MerkSource<'a> {
 
 struct MerkCommitter {
     batch: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+    values: Vec<(Vec<u8>, Option<Vec<u8>>)>,
     height: u8,
     levels: u8,
+    inline_threshold: Option<usize>,
 }
 
 impl MerkCommitter {
-    fn new(height: u8, levels: u8) -> Self {
+    fn new(height: u8, levels: u8, inline_threshold: Option<usize>) -> Self {
         MerkCommitter {
             batch: Vec::with_capacity(10000),
+            values: vec![],
             height,
             levels,
+            inline_threshold,
         }
     }
 }
 
 impl Commit for MerkCommitter {
     fn write(&mut self, tree: &Tree) -> Result<()> {
-        let mut buf = Vec::with_capacity(tree.encoding_length());
-        tree.encode_into(&mut buf);
+        let mut buf = Vec::with_capacity(tree.encoding_length() + 1);
+
+        // the values column family is only written when a value moves into or
+        // out of it, or changes while stored there
+        if self.stores_value_externally(tree) {
+            tree.encode_external_into(&mut buf);
+            if !tree.value_is_external() || tree.value_modified() {
+                self.values
+                    .push((tree.key().to_vec(), Some(tree.value().to_vec())));
+            }
+        } else {
+            tree.encode_into(&mut buf);
+            if tree.value_is_external() {
+                self.values.push((tree.key().to_vec(), None));
+            }
+        }
+
         self.batch.push((tree.key().to_vec(), Some(buf)));
         Ok(())
     }
 
+    fn stores_value_externally(&self, tree: &Tree) -> bool {
+        match self.inline_threshold {
+            Some(threshold) => tree.value().len() > threshold,
+            None => false,
+        }
+    }
+
     fn prune(&self, tree: &Tree) -> (bool, bool) {
         // keep N top levels of tree
         let prune = (self.height - tree.height()) >= self.levels;
@@ -425,12 +1708,27 @@ impl Commit for MerkCommitter {
 fn fetch_node(db: &rocksdb::DB, key: &[u8]) -> Result<Option<Tree>> {
     let bytes = db.get_pinned(key)?;
     if let Some(bytes) = bytes {
-        Ok(Some(Tree::decode(key.to_vec(), &bytes)))
+        let mut node = Tree::decode_stored(key.to_vec(), &bytes);
+        if node.value_is_external() {
+            match fetch_external_value(db, key)? {
+                None => bail!("Missing external value for node {:?}", key),
+                Some(value) => node.set_value_unchecked(value),
+            }
+        }
+        Ok(Some(node))
     } else {
         Ok(None)
     }
 }
 
+/// Reads a value which was stored outside of its node because it was longer
+/// than the inline threshold. Nodes for such values are stored with an empty
+/// value, behind a marker byte (see `Tree::encode_external_into`).
+fn fetch_external_value(db: &rocksdb::DB, key: &[u8]) -> Result<Option<Vec<u8>>> {
+    let values_cf = db.cf_handle(VALUES_CF_NAME).unwrap();
+    Ok(db.get_cf(values_cf, key)?)
+}
+
 fn fetch_existing_node(db: &rocksdb::DB, key: &[u8]) -> Result<Tree> {
     match fetch_node(db, key)? {
         None => bail!("key not found: {:?}", key),
@@ -440,9 +1738,13 @@ fn fetch_existing_node(db: &rocksdb::DB, key: &[u8]) -> Result<Tree> {
 
 #[cfg(test)]
 mod test {
-    use super::{RefWalker, Merk, MerkSource};
+    use super::{LogConfig, Merk, MerkSource, RefWalker, WriteOpts};
+    use crate::proofs::QueryItem;
     use crate::test_utils::*;
+    use crate::tree::HASH_LENGTH;
     use crate::Op;
+    use rocksdb::WriteBatch;
+    use std::collections::LinkedList;
     use std::thread;
 
     // TODO: Close and then reopen test
@@ -465,13 +1767,17 @@ mod test {
         merk.apply(&batch, &[]).expect("apply failed");
 
         assert_invariants(&merk);
-        assert_eq!(
-            merk.root_hash(),
-            [
-                217, 218, 163, 74, 119, 133, 165, 247, 140, 194, 85, 70, 28, 33, 61, 148, 118, 231,
-                134, 111
-            ]
-        );
+        #[cfg(not(feature = "hash256"))]
+        let expected = [
+            217, 218, 163, 74, 119, 133, 165, 247, 140, 194, 85, 70, 28, 33, 61, 148, 118, 231,
+            134, 111,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = [
+            95, 202, 255, 82, 51, 192, 17, 216, 113, 188, 91, 15, 28, 0, 76, 243, 114, 206, 127,
+            120, 103, 38, 8, 215, 74, 27, 72, 32, 36, 55, 250, 218,
+        ];
+        assert_eq!(merk.root_hash(), expected);
     }
 
     #[test]
@@ -579,6 +1885,805 @@ mod test {
         assert!(merk.get(&[3, 3, 3]).unwrap().is_none());
     }
 
+    #[test]
+    fn failed_apply_leaves_state_unchanged() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        let root_hash = merk.root_hash();
+
+        // failure while writing to the db
+        merk.fail_writes = true;
+        assert!(merk
+            .apply(&make_batch_seq(100..200), &[(vec![1], Op::Put(vec![2]))])
+            .is_err());
+        merk.fail_writes = false;
+
+        assert_eq!(merk.root_hash(), root_hash);
+        assert!(merk.get(&seq_key(150)).unwrap().is_none());
+        assert!(merk.get_aux(&[1]).unwrap().is_none());
+        assert_invariants(&merk);
+
+        // failure while applying to the tree
+        assert!(merk.apply(&[(seq_key(1000), Op::Delete)], &[]).is_err());
+        assert_eq!(merk.root_hash(), root_hash);
+        assert!(merk.get(&seq_key(50)).unwrap().is_some());
+
+        // store is still usable
+        merk.apply(&make_batch_seq(100..200), &[])
+            .expect("apply failed");
+        assert!(merk.get(&seq_key(150)).unwrap().is_some());
+        assert_invariants(&merk);
+    }
+
+    #[test]
+    fn root_hash_cache() {
+        use crate::tree::NULL_HASH;
+
+        let computed =
+            |merk: &Merk| merk.use_tree(|tree| tree.map_or(NULL_HASH, |tree| tree.hash()));
+
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        assert_eq!(merk.root_hash(), NULL_HASH);
+
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        assert_eq!(merk.root_hash(), computed(&merk));
+        let root_hash = merk.root_hash();
+
+        merk.fail_writes = true;
+        assert!(merk.apply(&make_batch_seq(100..200), &[]).is_err());
+        merk.fail_writes = false;
+        assert_eq!(merk.root_hash(), root_hash);
+        assert!(merk.apply(&[(seq_key(1000), Op::Delete)], &[]).is_err());
+        assert_eq!(merk.root_hash(), root_hash);
+
+        let mut batch = WriteBatch::default();
+        merk.apply_into(&make_batch_seq(100..200), &[], &mut batch)
+            .expect("apply failed");
+        assert_eq!(merk.root_hash(), computed(&merk));
+        assert_ne!(merk.root_hash(), root_hash);
+        merk.db().write(batch).unwrap();
+
+        merk.apply(&make_del_batch_seq(0..200), &[])
+            .expect("apply failed");
+        assert_eq!(merk.root_hash(), NULL_HASH);
+    }
+
+    #[test]
+    fn staged_root_hash() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        // empty store
+        let batch = make_batch_seq(0..100);
+        let staged_hash = merk.staged_root_hash(&batch).unwrap();
+        assert_eq!(merk.root_hash(), [0; HASH_LENGTH]);
+        merk.apply(&batch, &[]).expect("apply failed");
+        assert_eq!(merk.root_hash(), staged_hash);
+
+        // populated store
+        let mut batch = make_del_batch_seq(0..10);
+        batch.extend(make_batch_seq(100..150));
+        let root_hash = merk.root_hash();
+        let staged_hash = merk.staged_root_hash(&batch).unwrap();
+        assert_ne!(staged_hash, root_hash);
+        assert_eq!(merk.root_hash(), root_hash);
+        assert!(merk.get(&seq_key(0)).unwrap().is_some());
+        merk.apply(&batch, &[]).expect("apply failed");
+        assert_eq!(merk.root_hash(), staged_hash);
+
+        // staging nothing gives the current hash, deleting everything gives
+        // the null hash
+        assert_eq!(merk.staged_root_hash(&[]).unwrap(), merk.root_hash());
+        let batch = make_del_batch_seq(10..150);
+        assert_eq!(merk.staged_root_hash(&batch).unwrap(), [0; HASH_LENGTH]);
+
+        assert!(merk
+            .staged_root_hash(&[(vec![2], Op::Delete), (vec![1], Op::Delete)])
+            .is_err());
+    }
+
+    #[test]
+    fn delete_prefix_with_proof() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        let mut batch = vec![];
+        for prefix in 1..=3 {
+            for i in 0..10 {
+                batch.push((vec![prefix, i], Op::Put(vec![i])));
+            }
+        }
+        merk.apply(&batch, &[]).expect("apply failed");
+        let old_root = merk.root_hash();
+
+        let (new_root, proof) = merk.delete_prefix_with_proof(&[2]).expect("delete failed");
+        assert_eq!(new_root, merk.root_hash());
+        assert_ne!(new_root, old_root);
+        assert!(merk.get(&[2, 0]).unwrap().is_none());
+        assert!(merk.get(&[1, 9]).unwrap().is_some());
+        assert!(merk.get(&[3, 0]).unwrap().is_some());
+
+        let deleted = crate::verify(proof.as_slice(), old_root)
+            .expect("verify failed")
+            .query_item(&QueryItem::prefix(&[2]))
+            .expect("query failed");
+        let expected: Vec<_> = (0..10).map(|i| (vec![2, i], vec![i])).collect();
+        assert_eq!(deleted, expected);
+
+        assert!(crate::verify(proof.as_slice(), new_root).is_err());
+
+        // an empty store gives an empty proof against the null hash
+        use crate::tree::NULL_HASH;
+        let mut empty = TempMerk::open(thread::current().name().unwrap().to_owned() + "_empty")
+            .expect("failed to open merk");
+        let (new_root, proof) = empty.delete_prefix_with_proof(&[2]).unwrap();
+        assert_eq!(new_root, NULL_HASH);
+        assert!(proof.is_empty());
+        let map = crate::verify(proof.as_slice(), NULL_HASH).expect("verify failed");
+        assert!(map.query_item(&QueryItem::prefix(&[2])).unwrap().is_empty());
+        assert!(crate::verify(proof.as_slice(), old_root).is_err());
+    }
+
+    #[test]
+    fn structure_hash() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut a = TempMerk::open(path + "_a").expect("failed to open merk");
+        let path = thread::current().name().unwrap().to_owned();
+        let mut b = TempMerk::open(path + "_b").expect("failed to open merk");
+        assert_eq!(a.structure_hash().unwrap(), [0; HASH_LENGTH]);
+
+        let batch = make_batch_seq(0..100);
+        a.apply(&batch, &[]).expect("apply failed");
+        let batch: Vec<_> = (0..100)
+            .map(|n| (seq_key(n), Op::Put(vec![7; 40])))
+            .collect();
+        b.apply(&batch, &[]).expect("apply failed");
+
+        assert_ne!(a.root_hash(), b.root_hash());
+        assert_eq!(a.structure_hash().unwrap(), b.structure_hash().unwrap());
+
+        b.apply(&[(seq_key(100), Op::Put(vec![1]))], &[])
+            .expect("apply failed");
+        assert_ne!(a.structure_hash().unwrap(), b.structure_hash().unwrap());
+    }
+
+    #[test]
+    fn key_depth() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).expect("failed to open merk");
+        merk.apply(&make_batch_seq(1..8), &[]).unwrap();
+
+        // a sequential batch of 7 builds a perfectly balanced tree
+        assert_eq!(merk.key_depth(&seq_key(4)).unwrap(), Some(0));
+        assert_eq!(merk.key_depth(&seq_key(2)).unwrap(), Some(1));
+        assert_eq!(merk.key_depth(&seq_key(7)).unwrap(), Some(2));
+        assert_eq!(merk.key_depth(&seq_key(100)).unwrap(), None);
+
+        drop(merk);
+
+        // same result when the nodes have to be read from disk
+        let merk = Merk::open(&path).expect("failed to open merk");
+        assert_eq!(merk.key_depth(&seq_key(7)).unwrap(), Some(2));
+        assert_eq!(merk.key_depth(&seq_key(0)).unwrap(), None);
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn apply_batch_bytes() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let root_hash = merk.root_hash();
+
+        let batch = make_batch_seq(50..150);
+        let aux = [(vec![1], Op::Put(vec![2]))];
+        let bytes = merk.apply_batch_bytes(&batch, &aux).unwrap();
+        assert!(!bytes.is_empty());
+
+        // nothing is committed, and the output is deterministic
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(120)).unwrap(), None);
+        assert_eq!(merk.apply_batch_bytes(&batch, &aux).unwrap(), bytes);
+
+        let mut other = TempMerk::new().expect("failed to open merk");
+        other.apply(&make_batch_seq(0..100), &[]).unwrap();
+        assert_eq!(other.apply_batch_bytes(&batch, &aux).unwrap(), bytes);
+        assert_ne!(other.apply_batch_bytes(&batch, &[]).unwrap(), bytes);
+    }
+
+    #[test]
+    fn apply_with_opts() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).expect("failed to open merk");
+
+        let unsynced = WriteOpts {
+            sync: false,
+            disable_wal: true,
+        };
+        merk.apply_with_opts(&make_batch_seq(0..100), &[], unsynced)
+            .unwrap();
+        let synced = WriteOpts {
+            sync: true,
+            disable_wal: false,
+        };
+        merk.apply_with_opts(&make_batch_seq(100..200), &[], synced)
+            .unwrap();
+        merk.flush().unwrap();
+        let root_hash = merk.root_hash();
+        drop(merk);
+
+        let merk = Merk::open(&path).expect("failed to open merk");
+        assert_eq!(merk.root_hash(), root_hash);
+        assert_eq!(merk.get(&seq_key(50)).unwrap(), Some(vec![123; 60]));
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn merge_from() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let mut other = TempMerk::new().expect("failed to open merk");
+        other.apply(&make_batch_seq(100..150), &[]).unwrap();
+        other.apply(&make_batch_seq(150..200), &[]).unwrap();
+
+        let mut expected = TempMerk::new().expect("failed to open merk");
+        expected.apply(&make_batch_seq(0..100), &[]).unwrap();
+        expected.apply(&make_batch_seq(100..200), &[]).unwrap();
+
+        merk.merge_from(&other, false).unwrap();
+        assert_eq!(merk.root_hash(), expected.root_hash());
+        assert_invariants(&merk);
+
+        // colliding keys are rejected unless overwriting
+        let mut colliding = TempMerk::new().expect("failed to open merk");
+        colliding
+            .apply(&[(seq_key(50), Op::Put(vec![1]))], &[])
+            .unwrap();
+        let root_hash = merk.root_hash();
+        assert!(merk.merge_from(&colliding, false).is_err());
+        assert_eq!(merk.root_hash(), root_hash);
+        merk.merge_from(&colliding, true).unwrap();
+        assert_eq!(merk.get(&seq_key(50)).unwrap(), Some(vec![1]));
+
+        // merging an empty store does nothing
+        let empty = TempMerk::new().expect("failed to open merk");
+        let root_hash = merk.root_hash();
+        merk.merge_from(&empty, false).unwrap();
+        assert_eq!(merk.root_hash(), root_hash);
+    }
+
+    #[test]
+    fn count_range_iter() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        assert_eq!(merk.count_range_iter(..).unwrap(), 0);
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        assert_eq!(merk.count_range_iter(..).unwrap(), 100);
+        assert_eq!(merk.count_range_iter(seq_key(10)..seq_key(20)).unwrap(), 10);
+        assert_eq!(
+            merk.count_range_iter(seq_key(10)..=seq_key(20)).unwrap(),
+            11
+        );
+        assert_eq!(merk.count_range_iter(seq_key(90)..).unwrap(), 10);
+        assert_eq!(merk.count_range_iter(..seq_key(5)).unwrap(), 5);
+        assert_eq!(merk.count_range_iter(seq_key(200)..).unwrap(), 0);
+
+        // matches iterating over the entries in the range
+        let range = seq_key(33)..seq_key(66);
+        let entries = crate::verify(&merk.prove_all().unwrap(), merk.root_hash())
+            .unwrap()
+            .range(range.start.as_slice()..range.end.as_slice())
+            .unwrap();
+        assert_eq!(merk.count_range_iter(range).unwrap(), entries.len() as u64);
+    }
+
+    #[test]
+    fn rename() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        merk.apply(&[(vec![5; 3], Op::Put(vec![1, 2, 3]))], &[])
+            .unwrap();
+
+        let mut expected = TempMerk::new().expect("failed to open merk");
+        expected.apply(&make_batch_seq(0..10), &[]).unwrap();
+        expected
+            .apply(&[(vec![5; 3], Op::Put(vec![1, 2, 3]))], &[])
+            .unwrap();
+
+        merk.rename(&[5; 3], &[6; 3]).unwrap();
+        expected
+            .apply(
+                &[
+                    (vec![5; 3], Op::Delete),
+                    (vec![6; 3], Op::Put(vec![1, 2, 3])),
+                ],
+                &[],
+            )
+            .unwrap();
+        assert_eq!(merk.root_hash(), expected.root_hash());
+        assert_eq!(merk.get(&[5; 3]).unwrap(), None);
+        assert_eq!(merk.get(&[6; 3]).unwrap(), Some(vec![1, 2, 3]));
+
+        // renaming onto an existing key overwrites it
+        merk.rename(&[6; 3], &seq_key(3)).unwrap();
+        assert_eq!(merk.get(&seq_key(3)).unwrap(), Some(vec![1, 2, 3]));
+
+        assert!(merk.rename(&[5; 3], &[7; 3]).is_err());
+        merk.rename(&seq_key(3), &seq_key(3)).unwrap();
+        assert_eq!(merk.get(&seq_key(3)).unwrap(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn empty_aux_adds_no_aux_writes() {
+        // an empty aux batch already results in no aux operations, since each
+        // aux entry maps to exactly one write
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        merk.apply_tree(&[put_entry(1)]).expect("apply failed");
+
+        // the root node and the root pointer
+        let mut batch = WriteBatch::default();
+        merk.commit_into(LinkedList::new(), vec![], &[], &mut batch)
+            .expect("commit failed");
+        assert_eq!(batch.len(), 2);
+
+        let mut batch = WriteBatch::default();
+        merk.commit_into(LinkedList::new(), vec![], &[put_entry(2)], &mut batch)
+            .expect("commit failed");
+        assert_eq!(batch.len(), 3);
+
+        assert!(merk.get_aux(&seq_key(2)).unwrap().is_none());
+    }
+
+    #[test]
+    fn prove_all() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        assert!(merk.prove_all().is_err());
+
+        let batch = make_batch_seq(0..50);
+        merk.apply(&batch, &[]).expect("apply failed");
+
+        let proof = merk.prove_all().expect("prove failed");
+        let entries = crate::verify(proof.as_slice(), merk.root_hash())
+            .expect("verify failed")
+            .range(..)
+            .expect("range failed");
+        let expected: Vec<_> = (0..50).map(|n| (seq_key(n), vec![123; 60])).collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn prove_into() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let mut bytes = vec![];
+        for n in 0..20 {
+            let query = vec![seq_key(n * 5), seq_key(n * 5 + 2)];
+            merk.prove_into(&query, &mut bytes).unwrap();
+            assert_eq!(bytes, merk.prove(&query).unwrap());
+        }
+
+        let unsorted = vec![seq_key(2), seq_key(1)];
+        assert!(merk.prove_into(&unsorted, &mut bytes).is_err());
+    }
+
+    #[test]
+    fn prove_uniform_range() {
+        use crate::proofs::verify_uniform_range;
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..50), &[])
+            .expect("apply failed");
+        merk.apply(&[(seq_key(30), Op::Put(vec![0]))], &[])
+            .expect("apply failed");
+
+        let range = QueryItem::Range(seq_key(10)..seq_key(30));
+        let proof = merk
+            .prove_uniform_range(range.clone(), &[123; 60])
+            .expect("prove failed");
+        let count = verify_uniform_range(&proof, &range, &[123; 60], merk.root_hash())
+            .expect("verify failed");
+        assert_eq!(count, 20);
+
+        // the verifier checks the value too
+        assert!(verify_uniform_range(&proof, &range, &[0], merk.root_hash()).is_err());
+        assert!(verify_uniform_range(&proof, &range, &[123; 60], [0; HASH_LENGTH]).is_err());
+        // and that the proof covers the whole range
+        let wider = QueryItem::Range(seq_key(10)..seq_key(40));
+        assert!(verify_uniform_range(&proof, &wider, &[123; 60], merk.root_hash()).is_err());
+
+        let range = QueryItem::RangeInclusive(seq_key(10)..=seq_key(30));
+        assert!(merk.prove_uniform_range(range, &[123; 60]).is_err());
+    }
+
+    #[test]
+    fn prove_filtered_range() {
+        use crate::proofs::verify_filtered_range;
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..50), &[])
+            .expect("apply failed");
+        let batch: Vec<_> = (10..20)
+            .step_by(3)
+            .map(|n| (seq_key(n), Op::Put(vec![1])))
+            .collect();
+        merk.apply(&batch, &[]).expect("apply failed");
+
+        let nonzero = |_: &[u8], value: &[u8]| value == [1];
+        let range = QueryItem::Range(seq_key(5)..seq_key(30));
+        let (matches, proof) = merk
+            .prove_filtered_range(range.clone(), nonzero)
+            .expect("prove failed");
+        let expected: Vec<_> = [10, 13, 16, 19]
+            .iter()
+            .map(|n| (seq_key(*n), vec![1]))
+            .collect();
+        assert_eq!(matches, expected);
+
+        let verified = verify_filtered_range(&proof, &range, nonzero, merk.root_hash())
+            .expect("verify failed");
+        assert_eq!(verified, expected);
+
+        // a proof of only part of the range can't hide matching entries
+        let (_, narrower) = merk
+            .prove_filtered_range(QueryItem::Range(seq_key(5)..seq_key(15)), nonzero)
+            .expect("prove failed");
+        assert!(verify_filtered_range(&narrower, &range, nonzero, merk.root_hash()).is_err());
+    }
+
+    #[test]
+    fn proof_len() {
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+
+        let query = vec![seq_key(5), seq_key(50)];
+        let len = merk.proof_len(&query).expect("proof_len failed");
+        let proof = merk.prove(&query).expect("prove failed");
+        assert_eq!(proof.len(), len);
+        assert_eq!(proof, unsafe { merk.prove_unchecked(&query).unwrap() });
+
+        // a measured proof is only returned for the same query and root, and
+        // is kept while other queries are proven
+        merk.proof_len(&query).expect("proof_len failed");
+        let other = vec![seq_key(6)];
+        assert_eq!(merk.prove(&other).unwrap(), unsafe {
+            merk.prove_unchecked(&other).unwrap()
+        });
+        let measured = merk.measured_proof.take();
+        assert!(measured.is_some());
+        merk.measured_proof.set(measured);
+        assert_eq!(merk.prove(&query).unwrap().len(), len);
+        assert!(merk.measured_proof.take().is_none());
+
+        merk.proof_len(&query).expect("proof_len failed");
+        merk.apply(&[(seq_key(50), Op::Put(vec![1]))], &[])
+            .expect("apply failed");
+        let proof = merk.prove(&query).expect("prove failed");
+        assert_eq!(proof, unsafe { merk.prove_unchecked(&query).unwrap() });
+        assert_ne!(proof.len(), len);
+    }
+
+    #[test]
+    fn prove_path_between() {
+        use crate::proofs::verify_path_between;
+
+        let mut merk = TempMerk::new().expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        let root_hash = merk.root_hash();
+
+        let (a, b) = (seq_key(3), seq_key(90));
+        let proof = merk.prove_path_between(&a, &b).expect("prove failed");
+        let path = verify_path_between(&proof, &a, &b, root_hash).expect("verify failed");
+        assert_eq!(path.a_value, vec![123; 60]);
+        assert_eq!(path.b_value, vec![123; 60]);
+        assert_eq!(path.a_depth, merk.key_depth(&a).unwrap().unwrap());
+        assert_eq!(path.b_depth, merk.key_depth(&b).unwrap().unwrap());
+        // keys on either side of the root only share the root
+        assert_eq!(path.ancestor_depth, 0);
+
+        let mut range = crate::proofs::Query::new();
+        range.insert_range_inclusive(a.clone()..=b.clone());
+        assert!(proof.len() < merk.prove_query(&range).unwrap().len());
+
+        let proof = merk.prove_path_between(&a, &a).expect("prove failed");
+        let path = verify_path_between(&proof, &a, &a, root_hash).expect("verify failed");
+        assert_eq!(path.a_depth, path.ancestor_depth);
+
+        assert!(verify_path_between(&proof, &a, &b, root_hash).is_err());
+        assert!(merk.prove_path_between(&a, &seq_key(200)).is_err());
+    }
+
+    #[test]
+    fn apply_with_changes() {
+        use super::ChangeKind::*;
+
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..3), &[])
+            .expect("apply failed");
+
+        let batch = vec![
+            (seq_key(0), Op::Put(vec![123; 60])),
+            (seq_key(1), Op::Put(vec![1])),
+            (seq_key(2), Op::Delete),
+            (seq_key(3), Op::Put(vec![3])),
+            (seq_key(4), Op::Delete),
+        ];
+        let (root_hash, changes) = merk.apply_with_changes(&batch, &[]).expect("apply failed");
+
+        assert_eq!(root_hash, merk.root_hash());
+        assert_eq!(
+            changes,
+            vec![
+                (seq_key(0), Unchanged),
+                (seq_key(1), Updated),
+                (seq_key(2), Deleted),
+                (seq_key(3), Inserted),
+                (seq_key(4), Unchanged),
+            ]
+        );
+        assert_eq!(merk.get(&seq_key(1)).unwrap(), Some(vec![1]));
+        assert!(merk.get(&seq_key(2)).unwrap().is_none());
+        assert_eq!(merk.get(&seq_key(3)).unwrap(), Some(vec![3]));
+
+        // a batch with no effect leaves the hash as-is
+        let (root_hash, changes) = merk
+            .apply_with_changes(&[(seq_key(4), Op::Delete)], &[])
+            .expect("apply failed");
+        assert_eq!(root_hash, merk.root_hash());
+        assert_eq!(changes, vec![(seq_key(4), Unchanged)]);
+    }
+
+    #[test]
+    fn open_with_log() {
+        let path = thread::current().name().unwrap().to_owned();
+        let log_dir: std::path::PathBuf = format!("{}_logs", path).into();
+        if log_dir.exists() {
+            std::fs::remove_dir_all(&log_dir).unwrap();
+        }
+
+        let log = LogConfig {
+            level: rocksdb::LogLevel::Info,
+            dir: Some(log_dir.clone()),
+            ..Default::default()
+        };
+        let mut merk = Merk::open_with_log(&path, &log).expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..10), &[])
+            .expect("apply failed");
+        merk.flush().unwrap();
+        merk.destroy().unwrap();
+
+        assert!(std::fs::read_dir(&log_dir).unwrap().next().is_some());
+        std::fs::remove_dir_all(&log_dir).unwrap();
+    }
+
+    #[test]
+    fn orphans() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        assert!(merk.find_orphans().unwrap().is_empty());
+
+        merk.apply(&make_batch_seq(0..100), &[])
+            .expect("apply failed");
+        merk.apply(&make_del_batch_seq(10..20), &[])
+            .expect("apply failed");
+        assert!(merk.find_orphans().unwrap().is_empty());
+
+        // a node entry which is not linked into the tree
+        let orphan = crate::tree::Tree::new(seq_key(1000), vec![1]);
+        merk.db.put(seq_key(1000), orphan.encode()).unwrap();
+
+        assert_eq!(merk.find_orphans().unwrap(), vec![seq_key(1000)]);
+        assert_eq!(merk.gc_orphans().unwrap(), vec![seq_key(1000)]);
+        assert!(merk.find_orphans().unwrap().is_empty());
+        assert!(merk.db.get(seq_key(1000)).unwrap().is_none());
+        assert!(merk.get(&seq_key(50)).unwrap().is_some());
+    }
+
+    #[test]
+    fn apply_into() {
+        use rocksdb::ColumnFamilyDescriptor;
+
+        let path = thread::current().name().unwrap().to_owned();
+        let user_cf = || ColumnFamilyDescriptor::new("user", Merk::default_db_opts());
+        let open = || {
+            Merk::open_with_column_families(&path, Merk::default_db_opts(), vec![user_cf()])
+                .expect("failed to open merk")
+        };
+
+        let mut merk = open();
+        merk.apply(&make_batch_seq(0..10), &[])
+            .expect("apply failed");
+
+        let mut batch = WriteBatch::default();
+        batch.put_cf(merk.db().cf_handle("user").unwrap(), b"foo", b"bar");
+
+        // a write group's writes wouldn't be part of the caller's batch
+        merk.begin_write_group().unwrap();
+        assert!(merk
+            .apply_into(&make_batch_seq(10..20), &[], &mut batch)
+            .is_err());
+        merk.end_write_group().unwrap();
+        assert!(merk.get(&seq_key(15)).unwrap().is_none());
+
+        merk.apply_into(&make_batch_seq(10..20), &[put_entry(1)], &mut batch)
+            .expect("apply failed");
+        let root_hash = merk.root_hash();
+
+        // nothing is written until the caller writes the batch
+        assert!(merk.db().get(seq_key(15)).unwrap().is_none());
+        assert!(merk.get_aux(&seq_key(1)).unwrap().is_none());
+        merk.db().write(batch).unwrap();
+        drop(merk);
+
+        let merk = open();
+        assert_eq!(merk.root_hash(), root_hash);
+        assert!(merk.get(&seq_key(15)).unwrap().is_some());
+        assert!(merk.get_aux(&seq_key(1)).unwrap().is_some());
+        let user_cf = merk.db().cf_handle("user").unwrap();
+        assert_eq!(
+            merk.db().get_cf(user_cf, b"foo").unwrap(),
+            Some(b"bar".to_vec())
+        );
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn inline_threshold() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_inline_threshold(&path, Merk::default_db_opts(), 8).unwrap();
+        let mut plain = TempMerk::open(path.clone() + "_plain").unwrap();
+
+        let lengths = [0, 7, 8, 9, 100];
+        let batch: Vec<_> = lengths
+            .iter()
+            .map(|len| (vec![*len as u8], Op::Put(vec![*len as u8; *len])))
+            .collect();
+        merk.apply(&batch, &[]).expect("apply failed");
+        plain.apply(&batch, &[]).expect("apply failed");
+        assert_eq!(merk.root_hash(), plain.root_hash());
+
+        let external = |merk: &Merk, key: u8| merk.fetch_external_value(&[key]).unwrap().is_some();
+        assert!(!external(&merk, 0));
+        assert!(!external(&merk, 7));
+        assert!(!external(&merk, 8));
+        assert!(external(&merk, 9));
+        assert!(external(&merk, 100));
+
+        // chunks contain the full values
+        let chunks: Vec<_> = merk
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let plain_chunks: Vec<_> = plain
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        assert_eq!(chunks, plain_chunks);
+        drop(merk);
+
+        // values are read from disk regardless of the threshold
+        let merk = Merk::open(&path).unwrap();
+        for len in lengths.iter() {
+            assert_eq!(
+                merk.get(&[*len as u8]).unwrap(),
+                Some(vec![*len as u8; *len])
+            );
+        }
+        assert_eq!(merk.root_hash(), plain.root_hash());
+
+        // moving a value inline or deleting it removes the external entry
+        drop(merk);
+        let mut merk = Merk::open_with_inline_threshold(&path, Merk::default_db_opts(), 8).unwrap();
+        merk.apply(&[(vec![9], Op::Put(vec![1])), (vec![100], Op::Delete)], &[])
+            .expect("apply failed");
+        assert!(!external(&merk, 9));
+        assert!(!external(&merk, 100));
+        assert_eq!(merk.get(&[9]).unwrap(), Some(vec![1]));
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn inline_threshold_placement_writes() {
+        // returns how many more writes an apply makes with an inline threshold
+        // than without one
+        fn extra_writes(merk: &mut Merk, plain: &mut Merk, batch: &[(Vec<u8>, Op)]) -> usize {
+            let mut writes = WriteBatch::default();
+            merk.apply_into(batch, &[], &mut writes).unwrap();
+            let mut plain_writes = WriteBatch::default();
+            plain.apply_into(batch, &[], &mut plain_writes).unwrap();
+            let extra = writes.len() - plain_writes.len();
+            merk.write(writes).unwrap();
+            plain.write(plain_writes).unwrap();
+            extra
+        }
+
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_inline_threshold(&path, Merk::default_db_opts(), 8).unwrap();
+        let mut plain = TempMerk::open(path.clone() + "_plain").unwrap();
+
+        let batch: Vec<_> = (0..10).map(|i| (seq_key(i), Op::Put(vec![1]))).collect();
+        assert_eq!(extra_writes(&mut merk, &mut plain, &batch), 0);
+
+        let root_key = merk.use_tree(|tree| tree.unwrap().key().to_vec());
+        assert_ne!(root_key, seq_key(0));
+        let put = |key: &[u8], value: Vec<u8>| vec![(key.to_vec(), Op::Put(value))];
+        let delete = |key: &[u8]| vec![(key.to_vec(), Op::Delete)];
+        let cases = vec![
+            // moving the root's value out of the node writes it separately
+            (put(&root_key, vec![2; 100]), 1),
+            // rewriting the root for a change below it leaves its value alone
+            (put(&seq_key(0), vec![3]), 0),
+            // changing a separately stored value rewrites it
+            (put(&root_key, vec![4; 100]), 1),
+            // moving it back into the node deletes it
+            (put(&root_key, vec![5]), 1),
+            (put(&root_key, vec![6; 100]), 1),
+            // only deleting a node whose value is stored separately deletes it
+            (delete(&seq_key(0)), 0),
+            (delete(&root_key), 1),
+        ];
+        for (batch, extra) in cases {
+            assert_eq!(extra_writes(&mut merk, &mut plain, &batch), extra);
+        }
+        assert!(merk.fetch_external_value(&root_key).unwrap().is_none());
+
+        assert_eq!(merk.root_hash(), plain.root_hash());
+        for i in 1..10 {
+            assert_eq!(
+                merk.get(&seq_key(i)).unwrap(),
+                plain.get(&seq_key(i)).unwrap()
+            );
+        }
+        merk.destroy().unwrap();
+    }
+
+    #[test]
+    fn leaf_hash_matches_stored() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+        merk.apply(&make_batch_seq(0..10), &[])
+            .expect("apply failed");
+        merk.apply(&[(seq_key(5), Op::Put(vec![1, 2, 3]))], &[])
+            .expect("apply failed");
+
+        let node = merk.fetch_node(&seq_key(5)).unwrap().unwrap();
+        assert_eq!(crate::leaf_hash(&seq_key(5), &[1, 2, 3]), *node.kv_hash());
+        let node = merk.fetch_node(&seq_key(6)).unwrap().unwrap();
+        assert_eq!(crate::leaf_hash(&seq_key(6), &[123; 60]), *node.kv_hash());
+    }
+
+    #[test]
+    fn apply_timed() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = TempMerk::open(path).expect("failed to open merk");
+
+        let timings = merk
+            .apply_timed(&make_batch_seq(0..1000), &[])
+            .expect("apply failed");
+        assert!(timings.tree_walk > 0);
+        assert!(timings.hash > 0);
+        assert!(timings.rocksdb_write > 0);
+        assert!(merk.get(&seq_key(500)).unwrap().is_some());
+        assert_invariants(&merk);
+
+        assert!(merk
+            .apply_timed(&[(vec![2], Op::Delete), (vec![1], Op::Delete)], &[])
+            .is_err());
+    }
+
     #[test]
     fn reopen() {
         fn collect(mut node: RefWalker<MerkSource>, nodes: &mut Vec<Vec<u8>>) {
@@ -690,6 +2795,53 @@ mod test {
         assert_eq!(merk.get(&[2]).unwrap(), Some(vec![0]));
     }
 
+    #[test]
+    fn reload() {
+        let path = thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let stale_hash = merk.root_hash();
+
+        let mut other = Merk::open(path.clone() + ".other").unwrap();
+        other.apply(&make_batch_seq(50..200), &[]).unwrap();
+
+        // replace the store's contents through the raw db handle
+        let mut batch = WriteBatch::default();
+        let mut iter = merk.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            batch.delete(iter.key().unwrap());
+            iter.next();
+        }
+        drop(iter);
+        let mut iter = other.raw_iter();
+        iter.seek_to_first();
+        while iter.valid() {
+            batch.put(iter.key().unwrap(), iter.value().unwrap());
+            iter.next();
+        }
+        drop(iter);
+        let root_key = other.use_tree(|tree| tree.unwrap().key().to_vec());
+        let internal_cf = merk.db().cf_handle(super::INTERNAL_CF_NAME).unwrap();
+        batch.put_cf(internal_cf, super::ROOT_KEY_KEY, root_key);
+        merk.db().write(batch).unwrap();
+        assert_eq!(merk.root_hash(), stale_hash);
+
+        merk.reload().unwrap();
+        assert_eq!(merk.root_hash(), other.root_hash());
+        assert_eq!(merk.get(&seq_key(150)).unwrap(), Some(vec![123; 60]));
+        assert_eq!(merk.get(&seq_key(10)).unwrap(), None);
+        merk.apply(&make_batch_seq(200..210), &[]).unwrap();
+        merk.use_tree(|tree| assert_tree_invariants(tree.unwrap()));
+
+        merk.begin_write_group().unwrap();
+        assert!(merk.reload().is_err());
+        merk.end_write_group().unwrap();
+
+        other.destroy().unwrap();
+        merk.destroy().unwrap();
+    }
+
     #[test]
     fn checkpoint_iterator() {
         let path = thread::current().name().unwrap().to_owned();
@@ -0,0 +1,108 @@
+//! Helpers for nesting Merk trees, where a key in a parent Merk holds the root
+//! hash of a child Merk.
+//!
+//! The child is an independent store, so updates to it are not seen by the
+//! parent until the child's new root hash is written into the parent with
+//! `Merk::set_child_root`. Nested proofs can only be created once the parent
+//! has committed to the child's current root.
+
+use super::Merk;
+use crate::tree::{Op, NULL_HASH};
+use crate::Result;
+use byteorder::{BigEndian, ByteOrder};
+use failure::bail;
+
+impl Merk {
+    /// Writes the current root hash of `child` as the value of `key`, so that
+    /// the root hash of this Merk commits to the contents of `child`. This
+    /// should be called after every change to `child`.
+    pub fn set_child_root(&mut self, key: &[u8], child: &Merk) -> Result<()> {
+        let root_hash = child.root_hash().to_vec();
+        self.apply(&[(key.to_vec(), Op::Put(root_hash))], &[])
+    }
+
+    /// Creates a proof spanning this Merk and the child Merk stored at
+    /// `parent_key`, proving the value of `child_key` within the child (or
+    /// its absence). The proof can be verified against this Merk's root hash
+    /// with `merk::verify_nested`.
+    ///
+    /// Errors if the value at `parent_key` is not the current root hash of
+    /// `child`, e.g. if the child was updated without calling
+    /// `set_child_root`.
+    pub fn prove_nested(
+        &self,
+        parent_key: &[u8],
+        child: &Merk,
+        child_key: &[u8],
+    ) -> Result<Vec<u8>> {
+        let child_root = child.root_hash();
+        match self.get(parent_key)? {
+            Some(value) if value == child_root => {}
+            _ => bail!("Child root hash is not committed in parent"),
+        }
+
+        let parent_proof = self.prove(&[parent_key.to_vec()])?;
+        // an empty child has no nodes to prove with, and verifies as absent
+        let child_proof = if child_root == NULL_HASH {
+            vec![]
+        } else {
+            child.prove(&[child_key.to_vec()])?
+        };
+
+        let mut bytes = Vec::with_capacity(4 + parent_proof.len() + child_proof.len());
+        let mut length = [0; 4];
+        BigEndian::write_u32(&mut length, parent_proof.len() as u32);
+        bytes.extend_from_slice(&length);
+        bytes.extend_from_slice(parent_proof.as_slice());
+        bytes.extend_from_slice(child_proof.as_slice());
+
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::tree::HASH_LENGTH;
+    use crate::verify_nested;
+
+    #[test]
+    fn nested_proof() {
+        let mut parent = TempMerk::new().unwrap();
+        let mut child = TempMerk::new().unwrap();
+        parent.apply(&make_batch_seq(0..20), &[]).unwrap();
+        child.apply(&make_batch_seq(0..50), &[]).unwrap();
+        parent.set_child_root(b"ns", &child).unwrap();
+
+        let proof = parent.prove_nested(b"ns", &child, &seq_key(7)).unwrap();
+        let value = verify_nested(&proof, b"ns", &seq_key(7), parent.root_hash()).unwrap();
+        assert_eq!(value, Some(vec![123; 60]));
+
+        let proof = parent.prove_nested(b"ns", &child, &seq_key(70)).unwrap();
+        let value = verify_nested(&proof, b"ns", &seq_key(70), parent.root_hash()).unwrap();
+        assert_eq!(value, None);
+        assert!(verify_nested(&proof, b"ns", &seq_key(70), [0; HASH_LENGTH]).is_err());
+
+        // child updates must be propagated before proving
+        child.apply(&[put_entry(70)], &[]).unwrap();
+        assert!(parent.prove_nested(b"ns", &child, &seq_key(70)).is_err());
+        parent.set_child_root(b"ns", &child).unwrap();
+        let proof = parent.prove_nested(b"ns", &child, &seq_key(70)).unwrap();
+        let value = verify_nested(&proof, b"ns", &seq_key(70), parent.root_hash()).unwrap();
+        assert_eq!(value, Some(vec![123; 60]));
+
+        // the value must come from the queried parent key
+        assert!(verify_nested(&proof, b"nt", &seq_key(70), parent.root_hash()).is_err());
+    }
+
+    #[test]
+    fn nested_empty_child() {
+        let mut parent = TempMerk::new().unwrap();
+        let child = TempMerk::new().unwrap();
+        parent.set_child_root(b"ns", &child).unwrap();
+
+        let proof = parent.prove_nested(b"ns", &child, &[1]).unwrap();
+        let value = verify_nested(&proof, b"ns", &[1], parent.root_hash()).unwrap();
+        assert_eq!(value, None);
+    }
+}
@@ -0,0 +1,231 @@
+//! Provides `ProofCache`, a persistent LRU cache of encoded proofs stored in
+//! its own column family.
+
+use std::cell::Cell;
+
+use blake2_rfc::blake2b::Blake2b;
+use byteorder::{BigEndian, ByteOrder};
+use rocksdb::{WriteBatch, DB};
+
+use super::PROOFS_CF_NAME;
+use crate::tree::{Hash, HASH_LENGTH};
+use crate::Result;
+
+// entries are keyed by `ENTRY_PREFIX || root_hash || query_hash` and hold the
+// entry's access counter followed by the proof bytes. the recency index is
+// keyed by `AGE_PREFIX || counter` and holds the entry key, so the least
+// recently used entry is always the first key with `AGE_PREFIX`.
+const ENTRY_PREFIX: u8 = b'e';
+const AGE_PREFIX: u8 = b'a';
+
+/// A cache of proofs keyed by the root hash they were created against and a
+/// hash of the query, persisted so that it survives restarts. When the cache
+/// holds more than `max_entries` proofs, the least recently used ones are
+/// evicted.
+pub(crate) struct ProofCache {
+    max_entries: usize,
+    len: Cell<usize>,
+    next_counter: Cell<u64>,
+}
+
+impl ProofCache {
+    /// Loads the cache state from the proofs column family.
+    pub(crate) fn load(db: &DB, max_entries: usize) -> Result<Self> {
+        let cf = db.cf_handle(PROOFS_CF_NAME).unwrap();
+
+        let mut len = 0;
+        let mut next_counter = 0;
+        let mut iter = db.raw_iterator_cf(cf);
+        iter.seek(&[AGE_PREFIX]);
+        while iter.valid() {
+            let key = iter.key().unwrap();
+            if key[0] != AGE_PREFIX {
+                break;
+            }
+            len += 1;
+            next_counter = BigEndian::read_u64(&key[1..]) + 1;
+            iter.next();
+        }
+
+        let cache = ProofCache {
+            max_entries,
+            len: Cell::new(len),
+            next_counter: Cell::new(next_counter),
+        };
+        // the limit may have been lowered since the cache was last used
+        cache.evict(db)?;
+
+        Ok(cache)
+    }
+
+    /// Returns the number of proofs in the cache.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.len.get()
+    }
+
+    /// Gets a cached proof, marking it as the most recently used.
+    pub(crate) fn get(
+        &self,
+        db: &DB,
+        root_hash: &Hash,
+        query_hash: &Hash,
+    ) -> Result<Option<Vec<u8>>> {
+        let cf = db.cf_handle(PROOFS_CF_NAME).unwrap();
+        let entry_key = entry_key(root_hash, query_hash);
+
+        let entry = match db.get_cf(cf, &entry_key)? {
+            None => return Ok(None),
+            Some(entry) => entry,
+        };
+        let proof = entry[8..].to_vec();
+
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(cf, age_key(BigEndian::read_u64(&entry)));
+        self.put_entry(&mut batch, &entry_key, &proof, db);
+        db.write(batch)?;
+
+        Ok(Some(proof))
+    }
+
+    /// Adds a proof to the cache, evicting the least recently used proofs if
+    /// the cache is full.
+    pub(crate) fn insert(
+        &self,
+        db: &DB,
+        root_hash: &Hash,
+        query_hash: &Hash,
+        proof: &[u8],
+    ) -> Result<()> {
+        if self.max_entries == 0 {
+            return Ok(());
+        }
+
+        let mut batch = WriteBatch::default();
+        self.put_entry(&mut batch, &entry_key(root_hash, query_hash), proof, db);
+        db.write(batch)?;
+        self.len.set(self.len.get() + 1);
+
+        self.evict(db)
+    }
+
+    /// Returns `true` if a proof for the given root and query is cached.
+    #[cfg(test)]
+    pub(crate) fn contains(&self, db: &DB, root_hash: &Hash, query_hash: &Hash) -> bool {
+        let cf = db.cf_handle(PROOFS_CF_NAME).unwrap();
+        db.get_cf(cf, entry_key(root_hash, query_hash))
+            .unwrap()
+            .is_some()
+    }
+
+    fn put_entry(&self, batch: &mut WriteBatch, entry_key: &[u8], proof: &[u8], db: &DB) {
+        let cf = db.cf_handle(PROOFS_CF_NAME).unwrap();
+        let counter = self.next_counter.get();
+        self.next_counter.set(counter + 1);
+
+        let mut entry = Vec::with_capacity(8 + proof.len());
+        entry.extend_from_slice(&counter.to_be_bytes());
+        entry.extend_from_slice(proof);
+        batch.put_cf(cf, entry_key, entry);
+        batch.put_cf(cf, age_key(counter), entry_key);
+    }
+
+    fn evict(&self, db: &DB) -> Result<()> {
+        let cf = db.cf_handle(PROOFS_CF_NAME).unwrap();
+
+        while self.len.get() > self.max_entries {
+            let mut batch = WriteBatch::default();
+            {
+                let mut iter = db.raw_iterator_cf(cf);
+                iter.seek(&[AGE_PREFIX]);
+                let oldest = iter.key().unwrap();
+                batch.delete_cf(cf, oldest);
+                batch.delete_cf(cf, iter.value().unwrap());
+            }
+            db.write(batch)?;
+            self.len.set(self.len.get() - 1);
+        }
+
+        Ok(())
+    }
+}
+
+/// Hashes a list of queried keys, for use as part of a cache key.
+pub(crate) fn query_hash(keys: &[Vec<u8>]) -> Hash {
+    let mut hasher = Blake2b::new(HASH_LENGTH);
+    for key in keys {
+        hasher.update(&(key.len() as u32).to_be_bytes());
+        hasher.update(key);
+    }
+
+    let res = hasher.finalize();
+    let mut hash: Hash = Default::default();
+    hash.copy_from_slice(res.as_bytes());
+    hash
+}
+
+fn entry_key(root_hash: &Hash, query_hash: &Hash) -> Vec<u8> {
+    let mut key = Vec::with_capacity(1 + 2 * HASH_LENGTH);
+    key.push(ENTRY_PREFIX);
+    key.extend_from_slice(root_hash);
+    key.extend_from_slice(query_hash);
+    key
+}
+
+fn age_key(counter: u64) -> Vec<u8> {
+    let mut key = vec![AGE_PREFIX];
+    key.extend_from_slice(&counter.to_be_bytes());
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::Merk;
+
+    #[test]
+    fn persistent_lru() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_persistent_proof_cache(&path, 2).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let root_hash = merk.root_hash();
+
+        let query = |n| vec![seq_key(n)];
+        let cached = |merk: &Merk, n| {
+            let cache = merk.proof_cache.as_ref().unwrap();
+            cache.contains(&merk.db, &root_hash, &query_hash(&query(n)))
+        };
+
+        let proof = merk.prove(&query(1)).unwrap();
+        merk.prove(&query(2)).unwrap();
+        assert!(cached(&merk, 1) && cached(&merk, 2));
+
+        // a hit returns the cached proof and marks it as recently used
+        assert_eq!(merk.prove(&query(1)).unwrap(), proof);
+        merk.prove(&query(3)).unwrap();
+        assert!(cached(&merk, 1) && !cached(&merk, 2) && cached(&merk, 3));
+        drop(merk);
+
+        // the cache and its recency order survive reopening
+        let merk = Merk::open_with_persistent_proof_cache(&path, 2).unwrap();
+        assert_eq!(merk.proof_cache.as_ref().unwrap().len(), 2);
+        assert_eq!(merk.prove(&query(1)).unwrap(), proof);
+        merk.prove(&query(4)).unwrap();
+        assert!(cached(&merk, 1) && !cached(&merk, 3) && cached(&merk, 4));
+        drop(merk);
+
+        // lowering the limit evicts down to it
+        let mut merk = Merk::open_with_persistent_proof_cache(&path, 1).unwrap();
+        assert_eq!(merk.proof_cache.as_ref().unwrap().len(), 1);
+        assert!(!cached(&merk, 1) && cached(&merk, 4));
+
+        // proofs against a new root are cached separately
+        merk.apply(&[put_entry(1000)], &[]).unwrap();
+        let new_proof = merk.prove(&query(1)).unwrap();
+        let values = crate::verify_query(&new_proof, &query(1), merk.root_hash()).unwrap();
+        assert_eq!(values, vec![Some(vec![123; 60])]);
+
+        merk.destroy().unwrap();
+    }
+}
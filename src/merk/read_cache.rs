@@ -0,0 +1,149 @@
+//! Provides `ReadCache`, for putting an external read-through cache in front
+//! of `Merk::get`.
+
+use std::cell::RefCell;
+use std::path::Path;
+
+use super::Merk;
+use crate::Result;
+
+/// An external cache (e.g. a shared in-memory store) consulted by `Merk::get`
+/// before reading the tree, registered with `Merk::open_with_read_cache`.
+///
+/// On a miss, the value read from the store is passed to `put`, and every
+/// key in a batch passed to `apply` (or any of its variants) is passed to
+/// `invalidate` before the tree is modified. If the tree is rolled back to
+/// its committed state (e.g. after a failed commit, or a failed write group),
+/// the cache is cleared. Only values of keys which exist are cached, so
+/// lookups of missing keys always read the store.
+///
+/// The methods can't fail: a cache which is unavailable should treat `get`
+/// as a miss and ignore `put`, but it must not silently drop invalidations,
+/// or later reads may return stale values.
+///
+/// # Consistency
+///
+/// As long as the cache is only populated by this `Merk`, `get` returns the
+/// same values it would without the cache: keys are invalidated before the
+/// tree changes, and no reads happen during a commit. While a write group
+/// is active, values from its commits are cached like any other, since `get`
+/// already returns them, and if the group is discarded the rollback clears
+/// them from the cache. Clearing is conservative - it costs misses after a
+/// failed commit, but never returns a stale value.
+///
+/// Writes which don't go through `apply` are not seen by the cache, so it
+/// must be cleared after writing through `Merk::db`, and caches shared by
+/// several stores (or processes) are only invalidated for commits made
+/// through this instance.
+pub trait ReadCache: Send {
+    /// Returns the cached value for `key`, or `None` on a miss.
+    fn get(&mut self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Caches `value` as the value for `key`, after a miss was read from the
+    /// store.
+    fn put(&mut self, key: &[u8], value: &[u8]);
+
+    /// Removes any cached value for `key`, since a commit is changing it.
+    fn invalidate(&mut self, key: &[u8]);
+
+    /// Removes all cached values, since the tree was rolled back.
+    fn clear(&mut self);
+}
+
+impl Merk {
+    /// Opens a store with the specified file path like `Merk::open`, with
+    /// `cache` in front of `get`. See `ReadCache` for the consistency
+    /// guarantees. If no store exists at that path, one will be created.
+    pub fn open_with_read_cache<P: AsRef<Path>>(
+        path: P,
+        cache: Box<dyn ReadCache>,
+    ) -> Result<Merk> {
+        let mut merk = Merk::open(path)?;
+        merk.read_cache = Some(RefCell::new(cache));
+        Ok(merk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    use super::ReadCache;
+    use crate::test_utils::*;
+    use crate::{Merk, Op};
+
+    #[derive(Clone, Default)]
+    struct MapCache {
+        entries: Arc<Mutex<HashMap<Vec<u8>, Vec<u8>>>>,
+        hits: Arc<Mutex<usize>>,
+    }
+
+    impl ReadCache for MapCache {
+        fn get(&mut self, key: &[u8]) -> Option<Vec<u8>> {
+            let value = self.entries.lock().unwrap().get(key).cloned();
+            if value.is_some() {
+                *self.hits.lock().unwrap() += 1;
+            }
+            value
+        }
+
+        fn put(&mut self, key: &[u8], value: &[u8]) {
+            self.entries
+                .lock()
+                .unwrap()
+                .insert(key.to_vec(), value.to_vec());
+        }
+
+        fn invalidate(&mut self, key: &[u8]) {
+            self.entries.lock().unwrap().remove(key);
+        }
+
+        fn clear(&mut self) {
+            self.entries.lock().unwrap().clear();
+        }
+    }
+
+    #[test]
+    fn read_through() {
+        let cache = MapCache::default();
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open_with_read_cache(&path, Box::new(cache.clone())).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        // misses populate the cache, missing keys are not cached
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), Some(vec![123; 60]));
+        assert_eq!(merk.get(&seq_key(500)).unwrap(), None);
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+        assert_eq!(*cache.hits.lock().unwrap(), 0);
+
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), Some(vec![123; 60]));
+        assert_eq!(*cache.hits.lock().unwrap(), 1);
+
+        // commits invalidate the keys they change
+        merk.apply(&[(seq_key(5), Op::Put(vec![1]))], &[]).unwrap();
+        assert!(cache.entries.lock().unwrap().is_empty());
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), Some(vec![1]));
+
+        merk.get(&seq_key(6)).unwrap();
+        merk.apply(&[(seq_key(6), Op::Delete)], &[]).unwrap();
+        assert_eq!(merk.get(&seq_key(6)).unwrap(), None);
+
+        // a failed commit does not leave stale values
+        merk.get(&seq_key(7)).unwrap();
+        let batch = [(seq_key(6), Op::Delete), (seq_key(7), Op::Delete)];
+        assert!(merk.apply(&batch, &[]).is_err());
+        assert!(!cache.entries.lock().unwrap().contains_key(&seq_key(7)));
+        assert_eq!(merk.get(&seq_key(7)).unwrap(), Some(vec![123; 60]));
+
+        // neither does a discarded write group
+        merk.begin_write_group().unwrap();
+        merk.apply(&[(seq_key(8), Op::Put(vec![2]))], &[]).unwrap();
+        assert_eq!(merk.get(&seq_key(8)).unwrap(), Some(vec![2]));
+        assert!(merk.apply(&batch, &[]).is_err());
+        assert_eq!(merk.get(&seq_key(8)).unwrap(), Some(vec![123; 60]));
+        assert!(merk.end_write_group().is_err());
+
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,101 @@
+//! Provides `Merk::apply_with_receipt`, which returns a compact record of each
+//! commit for appending to an operation log.
+
+use std::time::SystemTime;
+
+use ed::{Decode, Encode};
+
+use super::{check_batch, ApplyOpts, Merk};
+use crate::tree::{Batch, Hash};
+use crate::Result;
+
+/// A durable record of a single commit, as returned by
+/// `Merk::apply_with_receipt`.
+///
+/// Receipts are built from values already computed during the commit, so
+/// producing one adds no reads or hashing. They can be encoded with `ed` (or
+/// `serde`, with the `serde` feature) to be persisted, e.g. for auditing the
+/// sequence of root hashes or for computing write metrics offline.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Encode, Decode)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CommitReceipt {
+    /// The root hash before the commit.
+    pub prev_root: Hash,
+    /// The root hash after the commit.
+    pub new_root: Hash,
+    /// The number of operations in the applied batch.
+    pub num_keys: u64,
+    /// The number of bytes the commit added to the RocksDB write batch,
+    /// including nodes, aux writes and bookkeeping.
+    pub bytes_written: u64,
+    /// When the commit completed, in milliseconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl Merk {
+    /// Applies a batch of operations to the tree exactly like `apply`, and
+    /// returns a `CommitReceipt` describing the commit.
+    ///
+    /// If a write group is active, `bytes_written` counts the bytes this
+    /// commit added to the group, which are written when the group ends.
+    ///
+    /// This will fail if the keys in `batch` are not sorted and unique.
+    pub fn apply_with_receipt(&mut self, batch: &Batch, aux: &Batch) -> Result<CommitReceipt> {
+        check_batch(batch)?;
+
+        let prev_root = self.root_hash();
+        let opts = ApplyOpts::default();
+        let bytes_written = unsafe { self.apply_unchecked_opts(batch, aux, opts)? };
+        let timestamp = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_millis() as u64);
+
+        Ok(CommitReceipt {
+            prev_root,
+            new_root: self.root_hash(),
+            num_keys: batch.len() as u64,
+            bytes_written: bytes_written as u64,
+            timestamp,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ed::{Decode, Encode};
+
+    use super::CommitReceipt;
+    use crate::test_utils::*;
+    use crate::Op;
+
+    #[test]
+    fn apply_with_receipt() {
+        let mut merk = TempMerk::new().unwrap();
+        let prev_root = merk.root_hash();
+
+        let receipt = merk
+            .apply_with_receipt(&make_batch_seq(0..100), &[])
+            .unwrap();
+        assert_eq!(receipt.prev_root, prev_root);
+        assert_eq!(receipt.new_root, merk.root_hash());
+        assert_eq!(receipt.num_keys, 100);
+        assert!(receipt.bytes_written > 100 * 60);
+        assert!(receipt.timestamp > 0);
+
+        // receipts chain from one commit to the next
+        let next = merk
+            .apply_with_receipt(&[(seq_key(5), Op::Delete)], &[])
+            .unwrap();
+        assert_eq!(next.prev_root, receipt.new_root);
+        assert_eq!(next.num_keys, 1);
+        assert!(next.bytes_written < receipt.bytes_written);
+
+        let bytes = next.encode().unwrap();
+        assert_eq!(CommitReceipt::decode(bytes.as_slice()).unwrap(), next);
+
+        // a failed commit produces no receipt
+        let batch = [(seq_key(5), Op::Delete)];
+        assert!(merk.apply_with_receipt(&batch, &[]).is_err());
+        assert_eq!(merk.root_hash(), next.new_root);
+    }
+}
@@ -3,29 +3,229 @@
 
 use super::Merk;
 use crate::{
-    merk::MerkSource,
+    merk::{chunks::split_merged_chunk, MerkSource},
     proofs::{
-        chunk::{verify_leaf, verify_trunk, MIN_TRUNK_HEIGHT},
+        chunk::{chunk_hash, verify_leaf, verify_trunk, MIN_TRUNK_HEIGHT},
         verify::{Child, Tree as ProofTree},
-        Decoder, Node,
+        Decoder, Node, Op,
     },
     tree::{Link, RefWalker, Tree},
-    Hash, Result,
+    Error, Hash, Result,
 };
 use failure::bail;
 use rocksdb::WriteBatch;
-use std::iter::Peekable;
 use std::{path::Path, u8};
 
+/// Checks that a downloaded chunk matches the hash listed for it in a
+/// manifest from the producer (see `ChunkProducer::chunk_hash`), so corrupted
+/// chunks can be rejected before they are passed to the `Restorer`.
+///
+/// This only checks the bytes against the listed hash, it does not verify the
+/// chunk against the tree - the `Restorer` still does that, so a manifest
+/// from an untrusted source can't cause an invalid restore.
+pub fn verify_chunk_hash(bytes: &[u8], expected_hash: Hash) -> Result<()> {
+    let hash = chunk_hash(bytes);
+    if hash != expected_hash {
+        bail!(
+            "Chunk did not match expected hash\n\tExpected: {:?}\n\tActual: {:?}",
+            expected_hash,
+            hash
+        );
+    }
+    Ok(())
+}
+
+/// Returns the keys of the nodes in a chunk proof, in the order they appear
+/// (which is key order for a valid chunk).
+fn kv_keys(ops: &[Op]) -> impl Iterator<Item = &[u8]> {
+    ops.iter().filter_map(|op| match op {
+        Op::Push(Node::KV(key, _)) => Some(key.as_slice()),
+        _ => None,
+    })
+}
+
+/// Verifies leaf chunks against the hashes in a verified trunk, without
+/// writing them anywhere. Each chunk is verified independently, so many
+/// chunks can be verified concurrently (see `verify_all_parallel`, with the
+/// `parallel` feature enabled).
+pub struct ChunkVerifier {
+    leaf_hashes: Vec<Hash>,
+    boundaries: Vec<Vec<u8>>,
+}
+
+impl ChunkVerifier {
+    /// Verifies the trunk chunk (index 0) against `expected_root_hash`, and
+    /// creates a verifier for the leaf chunks it commits to.
+    pub fn new(expected_root_hash: Hash, trunk_bytes: &[u8]) -> Result<Self> {
+        let trunk_ops = Decoder::new(trunk_bytes).collect::<Result<Vec<_>>>()?;
+        let boundaries = kv_keys(&trunk_ops).map(|key| key.to_vec()).collect();
+
+        let (trunk, height) = verify_trunk(trunk_ops.into_iter().map(Ok))?;
+        if trunk.hash() != expected_root_hash {
+            bail!(
+                "Proof did not match expected hash\n\tExpected: {:?}\n\tActual: {:?}",
+                expected_root_hash,
+                trunk.hash()
+            );
+        }
+
+        let trunk_height = height / 2;
+        let leaf_hashes = if trunk_height >= MIN_TRUNK_HEIGHT {
+            trunk.layer(trunk_height).map(|node| node.hash()).collect()
+        } else {
+            vec![]
+        };
+
+        Ok(ChunkVerifier {
+            leaf_hashes,
+            boundaries,
+        })
+    }
+
+    /// Returns the number of leaf chunks committed to by the trunk.
+    pub fn leaf_count(&self) -> usize {
+        self.leaf_hashes.len()
+    }
+
+    /// Verifies the leaf chunk with the given index (as passed to
+    /// `ChunkProducer::chunk`, so the first leaf chunk has index 1).
+    pub fn verify_chunk(&self, index: usize, chunk_bytes: &[u8]) -> Result<()> {
+        let leaf_hash = match index.checked_sub(1).and_then(|i| self.leaf_hashes.get(i)) {
+            None => bail!("Chunk index out-of-bounds"),
+            Some(leaf_hash) => *leaf_hash,
+        };
+        verify_leaf(Decoder::new(chunk_bytes), leaf_hash)?;
+        Ok(())
+    }
+
+    /// Verifies each of the given `(index, bytes)` leaf chunks in turn. If any
+    /// fail, returns the error for the one with the lowest index.
+    pub fn verify_all(&self, chunks: &[(usize, Vec<u8>)]) -> Result<()> {
+        let failure = chunks
+            .iter()
+            .filter_map(|(index, bytes)| {
+                let err = self.verify_chunk(*index, bytes).err()?;
+                Some((*index, err))
+            })
+            .min_by_key(|(index, _)| *index);
+        ChunkVerifier::failure_to_result(failure)
+    }
+
+    /// Verifies the given `(index, bytes)` leaf chunks concurrently on the
+    /// rayon thread pool. The result is the same as for `verify_all`: if any
+    /// chunks fail, the error is reported for the one with the lowest index,
+    /// regardless of the order the chunks were verified in.
+    #[cfg(feature = "parallel")]
+    pub fn verify_all_parallel(&self, chunks: &[(usize, Vec<u8>)]) -> Result<()> {
+        use rayon::prelude::*;
+
+        let failure = chunks
+            .par_iter()
+            .filter_map(|(index, bytes)| {
+                let err = self.verify_chunk(*index, bytes).err()?;
+                Some((*index, err))
+            })
+            .min_by_key(|(index, _)| *index);
+        ChunkVerifier::failure_to_result(failure)
+    }
+
+    /// Verifies that `chunks` is the complete set of leaf chunks, in order
+    /// (so the first chunk is the one with index 1). Errors naming the first
+    /// chunk, in index order, which is missing, surplus, overlaps the key
+    /// range of a neighbouring chunk or the trunk, or fails verification
+    /// (e.g. because entries within its range are missing).
+    ///
+    /// Each chunk is discarded once it has been verified, so memory use is
+    /// bounded by the size of the largest chunk.
+    pub fn verify_chunk_set<I, C>(&self, chunks: I) -> Result<()>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<[u8]>,
+    {
+        let mut count = 0;
+        for (leaf_index, bytes) in chunks.into_iter().enumerate() {
+            let index = leaf_index + 1;
+            if leaf_index >= self.leaf_hashes.len() {
+                bail!(
+                    "Chunk {} is beyond the {} chunks committed to by the trunk",
+                    index,
+                    self.leaf_hashes.len()
+                );
+            }
+
+            let ops = match Decoder::new(bytes.as_ref()).collect::<Result<Vec<_>>>() {
+                Err(err) => bail!("Chunk {} could not be decoded: {}", index, err),
+                Ok(ops) => ops,
+            };
+            self.check_chunk_bounds(leaf_index, &ops)?;
+
+            let leaf_hash = self.leaf_hashes[leaf_index];
+            if let Err(err) = verify_leaf(ops.into_iter().map(Ok), leaf_hash) {
+                bail!("Chunk {} failed verification: {}", index, err);
+            }
+            count += 1;
+        }
+
+        if count < self.leaf_hashes.len() {
+            bail!(
+                "Chunk {} is missing, expected {} chunks",
+                count + 1,
+                self.leaf_hashes.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the keys of the leaf chunk at `leaf_index` are in order and
+    /// lie strictly between the trunk keys bounding it.
+    fn check_chunk_bounds(&self, leaf_index: usize, ops: &[Op]) -> Result<()> {
+        let index = leaf_index + 1;
+
+        // leaf `i` lies between the trunk keys `i - 1` and `i` (in order)
+        let start_key = leaf_index
+            .checked_sub(1)
+            .map(|i| self.boundaries[i].as_slice());
+        let end_key = self.boundaries.get(leaf_index).map(Vec::as_slice);
+        let mut prev_key: Option<&[u8]> = None;
+        for key in kv_keys(ops) {
+            if prev_key.map_or(false, |prev_key| key <= prev_key) {
+                bail!("Chunk {} has keys out of order", index);
+            }
+            if start_key.map_or(false, |start_key| key <= start_key) {
+                bail!("Chunk {} overlaps the trunk or chunk {}", index, index - 1);
+            }
+            if end_key.map_or(false, |end_key| key >= end_key) {
+                bail!("Chunk {} overlaps the trunk or chunk {}", index, index + 1);
+            }
+            prev_key = Some(key);
+        }
+
+        Ok(())
+    }
+
+    fn failure_to_result(failure: Option<(usize, Error)>) -> Result<()> {
+        match failure {
+            None => Ok(()),
+            Some((index, err)) => bail!("Chunk {} failed verification: {}", index, err),
+        }
+    }
+}
+
 /// A `Restorer` handles decoding, verifying, and storing chunk proofs to
-/// replicate an entire Merk tree. It expects the chunks to be processed in
-/// order, retrying the last chunk if verification fails.
+/// replicate an entire Merk tree. It expects the trunk chunk to be processed
+/// first, then the leaf chunks either in order (retrying the last chunk if
+/// verification fails) or in any order by index via `process_chunk_at`.
 pub struct Restorer {
-    leaf_hashes: Option<Peekable<std::vec::IntoIter<Hash>>>,
-    parent_keys: Option<Peekable<std::vec::IntoIter<Vec<u8>>>>,
+    leaf_hashes: Option<Vec<Hash>>,
+    parent_keys: Option<Vec<Vec<u8>>>,
+    processed: Vec<bool>,
+    remaining: usize,
     trunk_height: Option<usize>,
+    height: Option<usize>,
     merk: Merk,
     expected_root_hash: Hash,
+    expected_height: Option<usize>,
     stated_length: usize,
 }
 
@@ -44,21 +244,89 @@ impl Restorer {
         db_path: P,
         expected_root_hash: Hash,
         stated_length: usize,
+    ) -> Result<Self> {
+        Restorer::new_in_mode(db_path, expected_root_hash, stated_length, None)
+    }
+
+    /// Creates a new `Restorer` as in `new`, for replicating a store which was
+    /// created in history mode (see `Merk::open_with_history`). The new store
+    /// is created in history mode with the given `max_history`, so it must
+    /// also be opened with `Merk::open_with_history` after the restore.
+    pub fn new_with_history<P: AsRef<Path>>(
+        db_path: P,
+        expected_root_hash: Hash,
+        stated_length: usize,
+        max_history: usize,
+    ) -> Result<Self> {
+        Restorer::new_in_mode(
+            db_path,
+            expected_root_hash,
+            stated_length,
+            Some(max_history),
+        )
+    }
+
+    fn new_in_mode<P: AsRef<Path>>(
+        db_path: P,
+        expected_root_hash: Hash,
+        stated_length: usize,
+        history_len: Option<usize>,
     ) -> Result<Self> {
         if db_path.as_ref().exists() {
             bail!("The given path already exists");
         }
 
+        let merk = Merk::open_cfs(db_path, Merk::default_db_opts(), vec![], history_len)?;
         Ok(Self {
             expected_root_hash,
             stated_length,
+            expected_height: None,
             trunk_height: None,
-            merk: Merk::open(db_path)?,
+            height: None,
+            merk,
             leaf_hashes: None,
             parent_keys: None,
+            processed: vec![],
+            remaining: 0,
         })
     }
 
+    /// Verifies a complete set of chunks (the trunk followed by the leaf chunks
+    /// in order, as yielded by `ChunkProducer`) against `expected_root_hash`,
+    /// without writing anything to disk. This runs the same verification as a
+    /// restore, and additionally checks that the keys of each leaf chunk fall
+    /// between the trunk keys bounding it (see
+    /// `ChunkVerifier::verify_chunk_set`), so a peer can vet a chunk set before
+    /// committing disk space to restoring it.
+    pub fn validate_only<I, C>(expected_root_hash: Hash, chunks: I) -> Result<()>
+    where
+        I: IntoIterator<Item = C>,
+        C: AsRef<[u8]>,
+    {
+        let mut chunks = chunks.into_iter();
+        let verifier = match chunks.next() {
+            None => bail!("Expected at least the trunk chunk"),
+            Some(trunk) => ChunkVerifier::new(expected_root_hash, trunk.as_ref())?,
+        };
+        verifier.verify_chunk_set(chunks)
+    }
+
+    /// Sets the height the restored tree is expected to have (e.g. as derived
+    /// from the known size of the dataset). When the trunk is processed, the
+    /// height it proves is compared against this value, erroring early if they
+    /// differ so that chunks for the wrong tree are caught before downloading
+    /// any leaf chunks.
+    pub fn expect_height(&mut self, height: usize) {
+        self.expected_height = Some(height);
+    }
+
+    /// Returns the height of the tree being restored, as proven by the trunk.
+    /// If called before the first chunk is processed, this method will return
+    /// `None`.
+    pub fn tree_height(&self) -> Option<usize> {
+        self.height
+    }
+
     /// Verifies a chunk and writes it to the working RocksDB instance. Expects
     /// to be called for each chunk in order. Returns the number of remaining
     /// chunks.
@@ -70,10 +338,76 @@ impl Restorer {
 
         match self.leaf_hashes {
             None => self.process_trunk(ops),
-            Some(_) => self.process_leaf(ops),
+            Some(_) => {
+                let leaf_index = self.next_leaf_index()?;
+                self.process_leaf(ops, leaf_index)
+            }
         }
     }
 
+    /// Verifies the chunk with the given index (as passed to
+    /// `ChunkProducer::chunk`) and writes it to the working RocksDB instance.
+    /// Returns the number of remaining chunks.
+    ///
+    /// The trunk (index 0) must be processed first, since it contains the
+    /// hashes the other chunks are verified against, but the leaf chunks may
+    /// then be processed in any order, e.g. in descending order as yielded by
+    /// `ChunkProducer::into_rev_iter`. The restored tree is the same
+    /// regardless of the order.
+    pub fn process_chunk_at(&mut self, index: usize, chunk_bytes: &[u8]) -> Result<usize> {
+        let ops = Decoder::new(chunk_bytes);
+
+        if self.leaf_hashes.is_none() {
+            if index != 0 {
+                bail!("The trunk chunk must be processed first");
+            }
+            return self.process_trunk(ops);
+        }
+
+        if index == 0 {
+            bail!("The trunk chunk has already been processed");
+        }
+        let leaf_index = index - 1;
+        match self.processed.get(leaf_index) {
+            None => bail!("Chunk index out-of-bounds"),
+            Some(true) => bail!("Chunk has already been processed"),
+            Some(false) => self.process_leaf(ops, leaf_index),
+        }
+    }
+
+    /// Verifies a merged chunk (as yielded by `ChunkIter::merged`) and writes
+    /// its leaf chunks to the working RocksDB instance. The trunk chunk, which
+    /// is never merged, may also be passed here. Returns the number of
+    /// remaining unmerged chunks.
+    ///
+    /// Every leaf chunk is verified before any of them are written, so a
+    /// merged chunk which fails verification can be retried as a whole.
+    pub fn process_merged_chunk(&mut self, chunk_bytes: &[u8]) -> Result<usize> {
+        if self.leaf_hashes.is_none() {
+            return self.process_chunk(chunk_bytes);
+        }
+
+        let start = self.next_leaf_index()?;
+        let chunks = split_merged_chunk(chunk_bytes)?;
+        let end = start + chunks.len();
+        if end > self.processed.len() || self.processed[start..end].contains(&true) {
+            bail!("Received more chunks than expected");
+        }
+
+        let leaf_hashes = &self.leaf_hashes.as_ref().unwrap()[start..end];
+        let leaves = chunks
+            .into_iter()
+            .zip(leaf_hashes.iter())
+            .map(|(chunk, leaf_hash)| verify_leaf(Decoder::new(chunk), *leaf_hash))
+            .collect::<Result<Vec<_>>>()?;
+
+        for (i, leaf) in leaves.into_iter().enumerate() {
+            self.write_leaf(leaf, start + i)?;
+        }
+
+        Ok(self.remaining_chunks_unchecked())
+    }
+
     /// Consumes the `Restorer` and returns the newly-created, fully-populated
     /// Merk instance. This method will return an error if called before
     /// processing all chunks (e.g. `restorer.remaining_chunks()` is not equal
@@ -97,7 +431,16 @@ impl Restorer {
     /// the first chunk is processed, this method will return `None` since we do
     /// not yet have enough information to know about the number of chunks.
     pub fn remaining_chunks(&self) -> Option<usize> {
-        self.leaf_hashes.as_ref().map(|lh| lh.len())
+        self.leaf_hashes.as_ref().map(|_| self.remaining)
+    }
+
+    /// Returns the index (within the trunk's leaf layer) of the first leaf
+    /// chunk which has not yet been processed.
+    fn next_leaf_index(&self) -> Result<usize> {
+        match self.processed.iter().position(|processed| !processed) {
+            None => bail!("Received more chunks than expected"),
+            Some(index) => Ok(index),
+        }
     }
 
     /// Writes the data contained in `tree` (extracted from a verified chunk
@@ -139,6 +482,17 @@ impl Restorer {
             );
         }
 
+        if let Some(expected_height) = self.expected_height {
+            if height != expected_height {
+                bail!(
+                    "Proof did not match expected height\n\tExpected: {}\n\tActual: {}",
+                    expected_height,
+                    height
+                );
+            }
+        }
+        self.height = Some(height);
+
         let root_key = trunk.key().to_vec();
 
         let trunk_height = height / 2;
@@ -148,17 +502,15 @@ impl Restorer {
             let leaf_hashes = trunk
                 .layer(trunk_height)
                 .map(|node| node.hash())
-                .collect::<Vec<Hash>>()
-                .into_iter()
-                .peekable();
+                .collect::<Vec<Hash>>();
+            self.processed = vec![false; leaf_hashes.len()];
+            self.remaining = leaf_hashes.len();
             self.leaf_hashes = Some(leaf_hashes);
 
             let parent_keys = trunk
                 .layer(trunk_height - 1)
                 .map(|node| node.key().to_vec())
-                .collect::<Vec<Vec<u8>>>()
-                .into_iter()
-                .peekable();
+                .collect::<Vec<Vec<u8>>>();
             self.parent_keys = Some(parent_keys);
             assert_eq!(
                 self.parent_keys.as_ref().unwrap().len(),
@@ -169,8 +521,8 @@ impl Restorer {
             assert_eq!(self.remaining_chunks_unchecked(), chunks_remaining);
             chunks_remaining
         } else {
-            self.leaf_hashes = Some(vec![].into_iter().peekable());
-            self.parent_keys = Some(vec![].into_iter().peekable());
+            self.leaf_hashes = Some(vec![]);
+            self.parent_keys = Some(vec![]);
             0
         };
 
@@ -186,37 +538,41 @@ impl Restorer {
         Ok(chunks_remaining)
     }
 
-    /// Verifies a leaf chunk then writes it to the RocksDB. This needs to be
-    /// called in order, retrying the last chunk for any failed verifications.
-    fn process_leaf(&mut self, ops: Decoder) -> Result<usize> {
-        let leaf_hashes = self.leaf_hashes.as_mut().unwrap();
-        let leaf_hash = leaf_hashes
-            .peek()
-            .expect("Received more chunks than expected");
+    /// Verifies the leaf chunk at `leaf_index` within the trunk's leaf layer,
+    /// then writes it to the RocksDB.
+    fn process_leaf(&mut self, ops: Decoder, leaf_index: usize) -> Result<usize> {
+        let leaf_hash = self.leaf_hashes.as_ref().unwrap()[leaf_index];
 
-        let leaf = verify_leaf(ops, *leaf_hash)?;
-        self.rewrite_parent_link(&leaf)?;
+        let leaf = verify_leaf(ops, leaf_hash)?;
+        self.write_leaf(leaf, leaf_index)?;
+
+        Ok(self.remaining_chunks_unchecked())
+    }
+
+    /// Writes a verified leaf chunk to the RocksDB, linking it to its parent
+    /// and marking it as processed.
+    fn write_leaf(&mut self, leaf: ProofTree, leaf_index: usize) -> Result<()> {
+        self.rewrite_parent_link(&leaf, leaf_index)?;
         self.write_chunk(leaf)?;
 
-        let leaf_hashes = self.leaf_hashes.as_mut().unwrap();
-        leaf_hashes.next();
+        self.processed[leaf_index] = true;
+        self.remaining -= 1;
 
-        Ok(self.remaining_chunks_unchecked())
+        Ok(())
     }
 
     /// The parent of the root node of the leaf does not know the key of its
     /// children when it is first written. Now that we have verified this leaf,
     /// we can write the key into the parent node's entry. Note that this does
     /// not need to recalcuate hashes since it already had the child hash.
-    fn rewrite_parent_link(&mut self, leaf: &ProofTree) -> Result<()> {
-        let parent_keys = self.parent_keys.as_mut().unwrap();
-        let parent_key = parent_keys.peek().unwrap().clone();
+    fn rewrite_parent_link(&mut self, leaf: &ProofTree, leaf_index: usize) -> Result<()> {
+        let parent_key = self.parent_keys.as_ref().unwrap()[leaf_index / 2].clone();
         let mut parent = self
             .merk
             .fetch_node(parent_key.as_slice())?
             .expect("Could not find parent of leaf chunk");
 
-        let is_left_child = self.remaining_chunks_unchecked() % 2 == 0;
+        let is_left_child = leaf_index % 2 == 0;
         if let Some(Link::Reference { ref mut key, .. }) = parent.link_mut(is_left_child) {
             *key = leaf.key().to_vec();
         } else {
@@ -226,11 +582,6 @@ impl Restorer {
         let parent_bytes = parent.encode();
         self.merk.db.put(parent_key, parent_bytes)?;
 
-        if !is_left_child {
-            let parent_keys = self.parent_keys.as_mut().unwrap();
-            parent_keys.next();
-        }
-
         Ok(())
     }
 
@@ -284,7 +635,8 @@ impl Restorer {
     /// panic if called before processing the first chunk (since that chunk
     /// gives us the information to know how many chunks to expect).
     pub fn remaining_chunks_unchecked(&self) -> usize {
-        self.leaf_hashes.as_ref().unwrap().len()
+        assert!(self.leaf_hashes.is_some());
+        self.remaining
     }
 }
 
@@ -304,6 +656,19 @@ impl Merk {
     ) -> Result<Restorer> {
         Restorer::new(path, expected_root_hash, stated_length)
     }
+
+    /// Creates a new `Restorer` as in `restore`, for replicating a store
+    /// which was created in history mode. The new Merk instance is created in
+    /// history mode with the given `max_history` (see
+    /// `Restorer::new_with_history`).
+    pub fn restore_with_history<P: AsRef<Path>>(
+        path: P,
+        expected_root_hash: Hash,
+        stated_length: usize,
+        max_history: usize,
+    ) -> Result<Restorer> {
+        Restorer::new_with_history(path, expected_root_hash, stated_length, max_history)
+    }
 }
 
 impl ProofTree {
@@ -337,7 +702,7 @@ impl Child {
 mod tests {
     use super::*;
     use crate::test_utils::*;
-    use crate::tree::{Op, Batch};
+    use crate::tree::{Batch, Op, HASH_LENGTH};
     use std::path::PathBuf;
 
     fn restore_test(batches: &[&Batch], expected_nodes: usize) {
@@ -372,6 +737,7 @@ mod tests {
         let restored = restorer.finalize().unwrap();
         assert_eq!(restored.root_hash(), original.root_hash());
         assert_raw_db_entries_eq(&restored, &original, expected_nodes);
+        assert_chunks_equal(&restored, &original);
        
         std::fs::remove_dir_all(&path).unwrap();
     }
@@ -407,6 +773,302 @@ mod tests {
         restore_test(&[&make_batch_seq(0..1)], 1);
     }
 
+    #[test]
+    fn restore_expected_height() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+
+        let mut chunks = original.chunks().unwrap();
+        let trunk = chunks.chunk(0).unwrap();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len()).unwrap();
+        restorer.expect_height(13);
+        let err = restorer.process_chunk(trunk.as_slice()).unwrap_err();
+        assert!(err.to_string().contains("Expected: 13"));
+        assert!(err.to_string().contains("Actual: 14"));
+        assert_eq!(restorer.tree_height(), None);
+        assert_eq!(restorer.remaining_chunks(), None);
+        drop(restorer);
+        std::fs::remove_dir_all(&path).unwrap();
+
+        let mut restorer = Merk::restore(&path, original.root_hash(), chunks.len()).unwrap();
+        restorer.expect_height(14);
+        restorer.process_chunk(trunk.as_slice()).unwrap();
+        assert_eq!(restorer.tree_height(), Some(14));
+        drop(restorer);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_merged() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+
+        let merged_iter = original.chunks().unwrap().into_iter().merged(64 * 1024);
+        let stated_length = merged_iter.unmerged_len();
+        let chunks: Vec<_> = merged_iter.map(Result::unwrap).collect();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        let mut restorer = Merk::restore(&path, original.root_hash(), stated_length).unwrap();
+        for (i, chunk) in chunks.iter().enumerate() {
+            if i == 1 {
+                // a corrupted merged chunk is rejected as a whole
+                let mut bad_chunk = chunk.clone();
+                *bad_chunk.last_mut().unwrap() ^= 1;
+                assert!(restorer.process_merged_chunk(&bad_chunk).is_err());
+                assert_eq!(restorer.remaining_chunks(), Some(stated_length - 1));
+            }
+            restorer.process_merged_chunk(chunk).unwrap();
+        }
+        assert_eq!(restorer.remaining_chunks(), Some(0));
+
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_raw_db_entries_eq(&restored, &original, 10_000);
+        assert_chunks_equal(&restored, &original);
+
+        drop(restored);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn restore_history_mode() {
+        let original_path = format!("{}.original", std::thread::current().name().unwrap());
+        let mut original =
+            Merk::open_with_history(&original_path, Merk::default_db_opts(), 2).unwrap();
+        original.apply(&make_batch_seq(0..100), &[]).unwrap();
+        original
+            .apply(&[(seq_key(5), Op::Put(vec![1]))], &[])
+            .unwrap();
+
+        let chunks = original.chunks().unwrap();
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        let mut restorer =
+            Merk::restore_with_history(&path, original.root_hash(), chunks.len(), 2).unwrap();
+        for chunk in chunks {
+            restorer.process_chunk(chunk.unwrap().as_slice()).unwrap();
+        }
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_eq!(restored.get(&seq_key(5)).unwrap(), Some(vec![1]));
+
+        // the restored store keeps its mode marker
+        drop(restored);
+        assert!(Merk::open(&path).is_err());
+        let restored = Merk::open_with_history(&path, Merk::default_db_opts(), 2).unwrap();
+        assert_eq!(
+            restored.get_history(&seq_key(5)).unwrap(),
+            Some(vec![vec![123; 60]])
+        );
+
+        drop(restored);
+        std::fs::remove_dir_all(&path).unwrap();
+        original.destroy().unwrap();
+    }
+
+    #[test]
+    fn verify_chunk_hashes() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+
+        let mut producer = original.chunks().unwrap();
+        let hashes: Vec<_> = (0..producer.len())
+            .map(|index| producer.chunk_hash(index).unwrap())
+            .collect();
+
+        for (index, chunk) in original.chunks().unwrap().into_iter().enumerate() {
+            let mut chunk = chunk.unwrap();
+            verify_chunk_hash(&chunk, hashes[index]).unwrap();
+
+            let last = chunk.len() - 1;
+            chunk[last] ^= 1;
+            assert!(verify_chunk_hash(&chunk, hashes[index]).is_err());
+        }
+    }
+
+    #[test]
+    fn validate_only() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        let root_hash = original.root_hash();
+
+        let chunks: Vec<_> = original
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        Restorer::validate_only(root_hash, &chunks).unwrap();
+
+        assert!(Restorer::validate_only([0; HASH_LENGTH], &chunks).is_err());
+        assert!(Restorer::validate_only(root_hash, &chunks[..chunks.len() - 1]).is_err());
+        let mut swapped = chunks.clone();
+        swapped.swap(1, 2);
+        assert!(Restorer::validate_only(root_hash, &swapped).is_err());
+        let mut corrupted = chunks.clone();
+        let last = corrupted[3].len() - 1;
+        corrupted[3][last] ^= 1;
+        assert!(Restorer::validate_only(root_hash, &corrupted).is_err());
+
+        // a small tree fits in the trunk
+        let mut small = TempMerk::new().unwrap();
+        small.apply(&make_batch_seq(0..10), &[]).unwrap();
+        let chunks: Vec<_> = small
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        Restorer::validate_only(small.root_hash(), &chunks).unwrap();
+    }
+
+    #[test]
+    fn chunk_verifier() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+        let root_hash = original.root_hash();
+
+        let chunks: Vec<_> = original
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .enumerate()
+            .collect();
+        assert!(ChunkVerifier::new([0; HASH_LENGTH], &chunks[0].1).is_err());
+        let verifier = ChunkVerifier::new(root_hash, &chunks[0].1).unwrap();
+        assert_eq!(verifier.leaf_count(), chunks.len() - 1);
+
+        let leaves = &chunks[1..];
+        verifier.verify_all(leaves).unwrap();
+        assert!(verifier.verify_chunk(0, &chunks[1].1).is_err());
+        assert!(verifier.verify_chunk(chunks.len(), &chunks[1].1).is_err());
+
+        // the lowest failing index is reported, regardless of order
+        let mut corrupted = leaves.to_vec();
+        corrupted.reverse();
+        for (index, bytes) in corrupted.iter_mut() {
+            if *index == 3 || *index == 5 {
+                let last = bytes.len() - 1;
+                bytes[last] ^= 1;
+            }
+        }
+        let err = verifier.verify_all(&corrupted).unwrap_err();
+        assert!(err.to_string().starts_with("Chunk 3 "));
+
+        #[cfg(feature = "parallel")]
+        {
+            verifier.verify_all_parallel(leaves).unwrap();
+            let err = verifier.verify_all_parallel(&corrupted).unwrap_err();
+            assert!(err.to_string().starts_with("Chunk 3 "));
+        }
+    }
+
+    #[test]
+    fn verify_chunk_set() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+
+        let chunks: Vec<_> = original
+            .chunks()
+            .unwrap()
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+        let verifier = ChunkVerifier::new(original.root_hash(), &chunks[0]).unwrap();
+        let leaves = &chunks[1..];
+        verifier.verify_chunk_set(leaves).unwrap();
+
+        let err = |chunks: &[Vec<u8>]| verifier.verify_chunk_set(chunks).unwrap_err().to_string();
+
+        assert_eq!(
+            err(&leaves[..leaves.len() - 2]),
+            format!(
+                "Chunk {} is missing, expected {} chunks",
+                leaves.len() - 1,
+                leaves.len()
+            )
+        );
+
+        let mut extra = leaves.to_vec();
+        extra.push(leaves[0].clone());
+        assert!(err(&extra).starts_with(&format!("Chunk {} is beyond", leaves.len() + 1)));
+
+        // a chunk in the wrong position overlaps its neighbours' ranges
+        let mut swapped = leaves.to_vec();
+        swapped.swap(2, 3);
+        assert_eq!(err(&swapped), "Chunk 3 overlaps the trunk or chunk 4");
+
+        let mut duplicated = leaves.to_vec();
+        duplicated[4] = leaves[3].clone();
+        assert_eq!(err(&duplicated), "Chunk 5 overlaps the trunk or chunk 4");
+
+        // a chunk missing some of its entries fails verification
+        let mut truncated = leaves.to_vec();
+        let ops: Vec<_> = Decoder::new(&leaves[5]).map(Result::unwrap).collect();
+        let mut bytes = vec![];
+        crate::proofs::encode_into(ops[..ops.len() - 2].iter(), &mut bytes);
+        truncated[5] = bytes;
+        assert!(err(&truncated).starts_with("Chunk 6 failed verification"));
+    }
+
+    #[test]
+    fn restore_reverse() {
+        let mut original = TempMerk::new().unwrap();
+        original.apply(&make_batch_seq(0..10_000), &[]).unwrap();
+
+        let producer = original.chunks().unwrap();
+        let stated_length = producer.len();
+
+        let path: PathBuf = std::thread::current().name().unwrap().into();
+        if path.exists() {
+            std::fs::remove_dir_all(&path).unwrap();
+        }
+
+        let mut restorer = Merk::restore(&path, original.root_hash(), stated_length).unwrap();
+        let trunk_index = stated_length - 1;
+        assert!(restorer.process_chunk_at(trunk_index, &[]).is_err());
+
+        let mut indexes = vec![];
+        for chunk in producer.into_rev_iter() {
+            let (index, chunk) = chunk.unwrap();
+            indexes.push(index);
+
+            if index == 1 {
+                // a chunk can't be verified against another chunk's hash
+                let other = original.chunks().unwrap().chunk(2).unwrap();
+                assert!(restorer.process_chunk_at(1, &other).is_err());
+            }
+            restorer.process_chunk_at(index, &chunk).unwrap();
+        }
+        let mut expected_indexes = vec![0];
+        expected_indexes.extend((1..stated_length).rev());
+        assert_eq!(indexes, expected_indexes);
+        assert_eq!(restorer.remaining_chunks(), Some(0));
+        assert!(restorer.process_chunk_at(1, &[]).is_err());
+
+        let restored = restorer.finalize().unwrap();
+        assert_eq!(restored.root_hash(), original.root_hash());
+        assert_raw_db_entries_eq(&restored, &original, 10_000);
+        assert_chunks_equal(&restored, &original);
+
+        drop(restored);
+        std::fs::remove_dir_all(&path).unwrap();
+    }
+
     fn assert_raw_db_entries_eq(restored: &Merk, original: &Merk, length: usize) {
         let mut original_entries = original.raw_iter();
         let mut restored_entries = restored.raw_iter();
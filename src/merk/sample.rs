@@ -0,0 +1,88 @@
+//! Provides `Merk::sample_keys`, for drawing a random sample of keys without
+//! scanning the whole store.
+
+use rand::prelude::*;
+
+use super::{fetch_existing_node, Merk};
+use crate::tree::Tree;
+use crate::Result;
+
+impl Merk {
+    /// Draws `n` random keys from the store. The sample is deterministic for a
+    /// given `seed` and store state.
+    ///
+    /// Each key is drawn by a random descent from the root: at each node, the
+    /// descent stops at the node or continues into one of its children, with
+    /// probabilities weighted by the size of a full subtree of each child's
+    /// height (`2^height - 1`). Subtree sizes are not stored in the tree, so
+    /// this is only an estimate - it is exact for perfectly balanced subtrees,
+    /// but AVL subtrees can contain fewer nodes than a full one of the same
+    /// height, so keys in sparser subtrees are somewhat oversampled. The
+    /// bias is bounded by the AVL balance invariant, which makes this suitable
+    /// for histograms and load generation but not for exact statistics.
+    ///
+    /// Keys are drawn independently (with replacement), so the sample may
+    /// contain duplicates, and each draw reads one path of nodes from disk.
+    /// Returns an empty sample if the store is empty.
+    pub fn sample_keys(&self, n: usize, seed: u64) -> Result<Vec<Vec<u8>>> {
+        fn subtree_weight(height: u8) -> u64 {
+            (1u64 << height.min(62)) - 1
+        }
+
+        let root = match self.fetch_root()? {
+            None => return Ok(vec![]),
+            Some(root) => root,
+        };
+
+        let mut rng = SmallRng::seed_from_u64(seed);
+        let mut samples = Vec::with_capacity(n);
+        for _ in 0..n {
+            let mut child: Option<Tree> = None;
+            let key = loop {
+                let node = child.as_ref().unwrap_or(&root);
+                let (left_height, right_height) = node.child_heights();
+                let left_weight = subtree_weight(left_height);
+                let right_weight = subtree_weight(right_height);
+
+                let choice = rng.gen_range(0..=left_weight + right_weight);
+                let next_key = if choice < left_weight {
+                    node.link(true).unwrap().key().to_vec()
+                } else if choice < left_weight + right_weight {
+                    node.link(false).unwrap().key().to_vec()
+                } else {
+                    break node.key().to_vec();
+                };
+                child = Some(fetch_existing_node(&self.db, &next_key)?);
+            };
+            samples.push(key);
+        }
+
+        Ok(samples)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+
+    #[test]
+    fn sample_keys() {
+        let mut merk = TempMerk::new().unwrap();
+        assert!(merk.sample_keys(10, 0).unwrap().is_empty());
+
+        merk.apply(&make_batch_seq(0..1000), &[]).unwrap();
+
+        let sample = merk.sample_keys(2000, 1).unwrap();
+        assert_eq!(sample.len(), 2000);
+        assert_eq!(merk.sample_keys(2000, 1).unwrap(), sample);
+        assert_ne!(merk.sample_keys(2000, 2).unwrap(), sample);
+
+        for key in sample.iter() {
+            assert!(merk.get(key).unwrap().is_some());
+        }
+
+        // roughly uniform: about half of the draws land in each half
+        let lower = sample.iter().filter(|key| **key < seq_key(500)).count();
+        assert!(lower > 800 && lower < 1200);
+    }
+}
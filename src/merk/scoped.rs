@@ -0,0 +1,204 @@
+//! Provides `ScopedMerk`, a view of a `Merk` restricted to the keys under a
+//! prefix, for apps which keep several logical stores in one tree.
+
+use std::ops::{Bound, RangeBounds};
+
+use super::Merk;
+use crate::proofs::QueryItem;
+use crate::tree::{Batch, Hash, Op};
+use crate::Result;
+
+/// A view of the keys in a `Merk` which start with a prefix, created by
+/// `Merk::scoped`.
+///
+/// Keys passed to the view are relative to the prefix: it is prepended to
+/// every key before it reaches the store, and stripped from every key which is
+/// returned, so code using the view works in a flat keyspace.
+///
+/// Isolation between scopes is only logical. All scopes share the store's
+/// single root hash, so proofs created by the view are verified against the
+/// global root hash (with the prefixed keys), and a proof for one scope may
+/// reveal hashes of neighboring keys from other scopes.
+pub struct ScopedMerk<'a> {
+    merk: &'a mut Merk,
+    prefix: Vec<u8>,
+}
+
+impl<'a> ScopedMerk<'a> {
+    /// Returns the prefix of this view.
+    pub fn prefix(&self) -> &[u8] {
+        self.prefix.as_slice()
+    }
+
+    /// Returns the key in the underlying store for the given key, i.e. `key`
+    /// with the prefix prepended. This is the key to look up when verifying
+    /// proofs created by the view.
+    pub fn prefixed_key(&self, key: &[u8]) -> Vec<u8> {
+        let mut prefixed = Vec::with_capacity(self.prefix.len() + key.len());
+        prefixed.extend_from_slice(&self.prefix);
+        prefixed.extend_from_slice(key);
+        prefixed
+    }
+
+    /// Returns the root hash of the underlying store, which is shared by all
+    /// scopes.
+    pub fn root_hash(&self) -> Hash {
+        self.merk.root_hash()
+    }
+
+    /// Gets the value for `key` within the scope.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        self.merk.get(&self.prefixed_key(key))
+    }
+
+    /// Applies a batch of operations to the keys within the scope, like
+    /// `Merk::apply`. The aux keys are prefixed as well.
+    pub fn apply(&mut self, batch: &Batch, aux: &Batch) -> Result<()> {
+        let batch = self.prefixed_batch(batch);
+        let aux = self.prefixed_batch(aux);
+        self.merk.apply(&batch, &aux)
+    }
+
+    /// Returns all the key/value pairs within the scope, in key order.
+    pub fn iter(&self) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.range(..)
+    }
+
+    /// Returns the key/value pairs within the scope whose keys are in `range`,
+    /// in key order. This scans the committed nodes.
+    pub fn range<R: RangeBounds<Vec<u8>>>(&self, range: R) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        let start = match range.start_bound() {
+            Bound::Included(key) => self.prefixed_key(key),
+            Bound::Excluded(key) => {
+                let mut start = self.prefixed_key(key);
+                start.push(0);
+                start
+            }
+            Bound::Unbounded => self.prefix.clone(),
+        };
+
+        let mut keys = vec![];
+        {
+            let mut iter = self.merk.raw_iter();
+            iter.seek(&start);
+            while iter.valid() {
+                let key = iter.key().unwrap();
+                if !key.starts_with(&self.prefix) {
+                    break;
+                }
+                let in_range = match range.end_bound() {
+                    Bound::Included(end) => key[self.prefix.len()..] <= end[..],
+                    Bound::Excluded(end) => key[self.prefix.len()..] < end[..],
+                    Bound::Unbounded => true,
+                };
+                if !in_range {
+                    break;
+                }
+                keys.push(key.to_vec());
+                iter.next();
+            }
+            iter.status()?;
+        }
+
+        // read values through the store, which resolves values stored
+        // outside their nodes and strips history
+        let mut entries = Vec::with_capacity(keys.len());
+        for key in keys {
+            if let Some(value) = self.merk.get(&key)? {
+                entries.push((key[self.prefix.len()..].to_vec(), value));
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Creates a proof for the given keys within the scope, like
+    /// `Merk::prove`. The proof is verified against the global root hash with
+    /// the prefixed keys (see `prefixed_key`).
+    pub fn prove(&self, query: &[Vec<u8>]) -> Result<Vec<u8>> {
+        let query: Vec<_> = query.iter().map(|key| self.prefixed_key(key)).collect();
+        self.merk.prove(&query)
+    }
+
+    /// Creates a proof of every key/value pair within the scope, which can be
+    /// verified against the global root hash and queried with
+    /// `QueryItem::prefix(prefix)`.
+    pub fn prove_all(&self) -> Result<Vec<u8>> {
+        let mut query = crate::proofs::Query::new();
+        query.insert_item(QueryItem::prefix(&self.prefix));
+        self.merk.prove_query(&query)
+    }
+
+    fn prefixed_batch(&self, batch: &Batch) -> Vec<(Vec<u8>, Op)> {
+        batch
+            .iter()
+            .map(|(key, op)| {
+                let op = match op {
+                    Op::Put(value) => Op::Put(value.clone()),
+                    Op::Delete => Op::Delete,
+                };
+                (self.prefixed_key(key), op)
+            })
+            .collect()
+    }
+}
+
+impl Merk {
+    /// Returns a view of the keys which start with `prefix`, with the prefix
+    /// prepended to and stripped from keys automatically. See `ScopedMerk`.
+    pub fn scoped(&mut self, prefix: &[u8]) -> ScopedMerk {
+        ScopedMerk {
+            merk: self,
+            prefix: prefix.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proofs::{verify, QueryItem};
+    use crate::test_utils::*;
+    use crate::verify_query;
+
+    #[test]
+    fn scoped_view() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+
+        merk.scoped(b"a/")
+            .apply(&make_batch_seq(0..5), &[])
+            .unwrap();
+        merk.scoped(b"b/")
+            .apply(&make_batch_seq(3..8), &[])
+            .unwrap();
+
+        let a = merk.scoped(b"a/");
+        assert_eq!(a.get(&seq_key(1)).unwrap(), Some(vec![123; 60]));
+        assert_eq!(a.get(&seq_key(6)).unwrap(), None);
+        let keys: Vec<_> = a.iter().unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(keys, (0..5).map(seq_key).collect::<Vec<_>>());
+        let keys: Vec<_> = a
+            .range(seq_key(1)..=seq_key(3))
+            .unwrap()
+            .into_iter()
+            .map(|(key, _)| key)
+            .collect();
+        assert_eq!(keys, (1..4).map(seq_key).collect::<Vec<_>>());
+
+        let root_hash = a.root_hash();
+        let proof = a.prove(&[seq_key(2), seq_key(7)]).unwrap();
+        let query = vec![a.prefixed_key(&seq_key(2)), a.prefixed_key(&seq_key(7))];
+        let values = verify_query(&proof, &query, root_hash).unwrap();
+        assert_eq!(values, vec![Some(vec![123; 60]), None]);
+
+        let proof = a.prove_all().unwrap();
+        let map = verify(&proof, root_hash).unwrap();
+        let entries = map.query_item(&QueryItem::prefix(b"a/")).unwrap();
+        assert_eq!(entries.len(), 5);
+
+        merk.scoped(b"b/")
+            .apply(&make_del_batch_seq(3..8), &[])
+            .unwrap();
+        assert!(merk.scoped(b"b/").iter().unwrap().is_empty());
+        assert_eq!(merk.scoped(b"a/").iter().unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,236 @@
+//! Provides `Merk::start_scrubber`, which continuously audits the stored tree
+//! in the background to detect corruption such as bit rot.
+
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use failure::bail;
+
+use super::{fetch_external_value, Merk, ReadOnlyMerk};
+use crate::error::Error;
+use crate::tree::{kv_hash, Tree};
+use crate::Result;
+
+/// Configures the pace of a scrubber started with `Merk::start_scrubber`.
+#[derive(Clone, Copy, Debug)]
+pub struct ScrubConfig {
+    /// The number of nodes audited on each tick.
+    pub nodes_per_tick: usize,
+    /// The time to wait between ticks.
+    pub interval: Duration,
+}
+
+impl Default for ScrubConfig {
+    fn default() -> Self {
+        ScrubConfig {
+            nodes_per_tick: 1_000,
+            interval: Duration::from_secs(1),
+        }
+    }
+}
+
+/// A handle to a running scrubber. The scrubber is stopped when the handle is
+/// dropped or `stop` is called.
+pub struct Scrubber {
+    stop_sender: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+    secondary_path: PathBuf,
+}
+
+impl Scrubber {
+    /// Stops the scrubber, waiting for the current tick to finish.
+    pub fn stop(self) {}
+}
+
+impl Drop for Scrubber {
+    fn drop(&mut self) {
+        // dropping the sender wakes the thread up and ends its loop
+        self.stop_sender.take();
+        if let Some(thread) = self.thread.take() {
+            thread.join().ok();
+        }
+        std::fs::remove_dir_all(&self.secondary_path).ok();
+    }
+}
+
+impl Merk {
+    /// Starts a background thread which audits the stored nodes, a slice at a
+    /// time, calling `on_error` with the key of each node which fails a check
+    /// along with the error. The checks are the same as `selfcheck`'s: each
+    /// node must decode, its key/value hash must match its key and value, and
+    /// its children's hashes and heights must match its links.
+    ///
+    /// The scrubber reads through its own RocksDB secondary instance (kept in
+    /// a `.scrubber` directory next to the store), so it never holds locks
+    /// needed by foreground operations. Before each tick it catches up with
+    /// the store's latest writes, then audits the next `nodes_per_tick` nodes
+    /// in key order, continuing from where the previous tick left off and
+    /// wrapping around to the first key after reaching the end.
+    ///
+    /// Each tick reads `nodes_per_tick` nodes along with their children, so
+    /// the I/O cost is about 3 node reads per audited node, and the CPU cost
+    /// is dominated by hashing their keys and values. The pace is bounded by
+    /// `config`: a full pass over a store of `n` nodes takes about
+    /// `n / nodes_per_tick` ticks. The scrubber runs until the returned handle
+    /// is dropped or stopped. Only one scrubber can run per store at a time.
+    pub fn start_scrubber<F>(&self, config: ScrubConfig, mut on_error: F) -> Result<Scrubber>
+    where
+        F: FnMut(Vec<u8>, Error) + Send + 'static,
+    {
+        let mut secondary_path = self.path.clone().into_os_string();
+        secondary_path.push(".scrubber");
+        let secondary_path: PathBuf = secondary_path.into();
+        let replica = ReadOnlyMerk::open(&self.path, &secondary_path)?;
+
+        let (stop_sender, stop_receiver) = mpsc::channel();
+        let thread = thread::spawn(move || {
+            let mut cursor = None;
+            loop {
+                match replica.try_catch_up() {
+                    Ok(()) => cursor = scrub_tick(&replica, cursor, config, &mut on_error),
+                    Err(err) => on_error(vec![], err),
+                }
+
+                match stop_receiver.recv_timeout(config.interval) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+        });
+
+        Ok(Scrubber {
+            stop_sender: Some(stop_sender),
+            thread: Some(thread),
+            secondary_path,
+        })
+    }
+}
+
+/// Audits up to `config.nodes_per_tick` nodes after the `cursor` key (or from
+/// the first key if `None`), returning the key to continue from.
+fn scrub_tick<F>(
+    replica: &ReadOnlyMerk,
+    cursor: Option<Vec<u8>>,
+    config: ScrubConfig,
+    on_error: &mut F,
+) -> Option<Vec<u8>>
+where
+    F: FnMut(Vec<u8>, Error),
+{
+    let mut iter = replica.db.raw_iterator();
+    match cursor.as_ref() {
+        None => iter.seek_to_first(),
+        Some(key) => {
+            iter.seek(key);
+            if iter.valid() && iter.key() == Some(key.as_slice()) {
+                iter.next();
+            }
+        }
+    }
+
+    let mut cursor = cursor;
+    for _ in 0..config.nodes_per_tick {
+        if !iter.valid() {
+            // wrap around on the next tick
+            return None;
+        }
+
+        let key = iter.key().unwrap().to_vec();
+        if let Err(err) = check_stored_node(&replica.db, &key, iter.value().unwrap()) {
+            on_error(key.clone(), err);
+        }
+        cursor = Some(key);
+        iter.next();
+    }
+
+    cursor
+}
+
+fn check_stored_node(db: &rocksdb::DB, key: &[u8], bytes: &[u8]) -> Result<()> {
+    let node = match Tree::try_decode_stored(bytes) {
+        Err(_) => bail!("Could not decode node {:?}", key),
+        Ok(node) => node,
+    };
+
+    let value = if node.value_is_external() {
+        fetch_external_value(db, key)?.unwrap_or_default()
+    } else {
+        node.value().to_vec()
+    };
+    if kv_hash(key, &value) != *node.kv_hash() {
+        bail!("Corrupted key/value hash for node {:?}", key);
+    }
+
+    for left in [true, false].iter() {
+        let link = match node.link(*left) {
+            None => continue,
+            Some(link) => link,
+        };
+
+        let child = match db.get_pinned(link.key())? {
+            None => bail!("Missing child node {:?}", link.key()),
+            Some(bytes) => match Tree::try_decode_stored(&bytes[..]) {
+                Err(_) => bail!("Could not decode child node {:?}", link.key()),
+                Ok(child) => child,
+            },
+        };
+        if child.hash() != *link.hash() {
+            bail!("Hash mismatch for child node {:?}", link.key());
+        }
+        if child.height() != link.height() {
+            bail!("Height mismatch for child node {:?}", link.key());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::ScrubConfig;
+    use crate::test_utils::*;
+    use crate::tree::Tree;
+    use crate::Merk;
+
+    #[test]
+    fn scrubber_reports_corruption() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let errors = Arc::new(Mutex::new(vec![]));
+        let config = ScrubConfig {
+            nodes_per_tick: 10,
+            interval: Duration::from_millis(1),
+        };
+        let scrubber = {
+            let errors = errors.clone();
+            merk.start_scrubber(config, move |key, err| {
+                errors.lock().unwrap().push((key, err.to_string()));
+            })
+            .unwrap()
+        };
+
+        // replace a leaf with a different, validly-encoded node
+        let leaf = Tree::new(seq_key(0), vec![1, 2, 3]);
+        merk.db().put(seq_key(0), leaf.encode()).unwrap();
+
+        let started = Instant::now();
+        while errors.lock().unwrap().is_empty() {
+            assert!(started.elapsed() < Duration::from_secs(10));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        scrubber.stop();
+
+        let errors = errors.lock().unwrap();
+        assert!(errors.iter().all(|(_, err)| err.contains("mismatch")));
+        drop(errors);
+
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+//! Provides `ReadOnlyMerk`, a read-only view of a store opened as a RocksDB
+//! secondary instance, for serving reads on a replica which follows a primary
+//! `Merk`.
+
+use std::cell::Cell;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use super::history::is_history_store;
+use super::{
+    fetch_existing_node, fetch_node, Merk, AUX_CF_NAME, INTERNAL_CF_NAME, MERGES_CF_NAME,
+    PROOFS_CF_NAME, ROOT_KEY_KEY, VALUES_CF_NAME,
+};
+use crate::proofs::decode_history_value;
+use crate::tree::{Hash, NULL_HASH};
+use crate::Result;
+
+/// A read-only handle to a store which is being written to by a primary
+/// `Merk` (possibly in another process).
+///
+/// The handle sees the state of the store as of its last catch-up with the
+/// primary, which happens when it is opened and on each call to
+/// `try_catch_up`. Reads go directly to RocksDB rather than through an
+/// in-memory tree, since the primary may rewrite any node between catch-ups.
+/// Pending deltas from `Merk::apply_merge` are not visible.
+pub struct ReadOnlyMerk {
+    pub(crate) db: rocksdb::DB,
+    last_catch_up: Cell<Instant>,
+    history: Cell<bool>,
+}
+
+impl ReadOnlyMerk {
+    /// Opens the store at `primary_path` as a secondary instance. RocksDB
+    /// keeps the secondary's own info log files at `secondary_path`, which
+    /// must not be shared with any other instance.
+    pub fn open<P, Q>(primary_path: P, secondary_path: Q) -> Result<ReadOnlyMerk>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+    {
+        let mut db_opts = Merk::default_db_opts();
+        // secondary instances must keep all table files open
+        db_opts.set_max_open_files(-1);
+
+        let opened_at = Instant::now();
+        let cfs = [
+            AUX_CF_NAME,
+            INTERNAL_CF_NAME,
+            VALUES_CF_NAME,
+            PROOFS_CF_NAME,
+            MERGES_CF_NAME,
+        ];
+        let db = rocksdb::DB::open_cf_as_secondary(
+            &db_opts,
+            primary_path.as_ref(),
+            secondary_path.as_ref(),
+            cfs.iter(),
+        )?;
+
+        let history = is_history_store(&db)?;
+        Ok(ReadOnlyMerk {
+            db,
+            last_catch_up: Cell::new(opened_at),
+            history: Cell::new(history),
+        })
+    }
+
+    /// Catches up with the primary, making all writes it has made so far
+    /// visible to this handle.
+    pub fn try_catch_up(&self) -> Result<()> {
+        // record the time from before catching up, since writes made while
+        // catching up may not be included
+        let started_at = Instant::now();
+        self.db.try_catch_up_with_primary()?;
+        self.last_catch_up.set(started_at);
+        // the primary may have created the store since it was opened
+        self.history.set(is_history_store(&self.db)?);
+        Ok(())
+    }
+
+    /// Returns the time of the last catch-up with the primary (or of opening
+    /// the handle, if it has not caught up since).
+    pub fn last_catch_up(&self) -> Instant {
+        self.last_catch_up.get()
+    }
+
+    /// Gets a value for the given key as of the last catch-up. If the key is
+    /// not found, `None` is returned. For stores created in history mode, this
+    /// returns the current value without its history, like `Merk::get`.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        let maybe_value = fetch_node(&self.db, key)?.map(|node| node.value().to_vec());
+        match maybe_value {
+            Some(bytes) if self.history.get() => Ok(Some(decode_history_value(&bytes)?.0)),
+            maybe_value => Ok(maybe_value),
+        }
+    }
+
+    /// Gets a value for the given key like `get`, but first catches up with
+    /// the primary if the last catch-up was longer than `max_staleness` ago,
+    /// so the value read reflects the primary's state from no more than
+    /// `max_staleness` ago.
+    pub fn get_fresh(&self, key: &[u8], max_staleness: Duration) -> Result<Option<Vec<u8>>> {
+        if self.last_catch_up.get().elapsed() > max_staleness {
+            self.try_catch_up()?;
+        }
+        self.get(key)
+    }
+
+    /// Returns the root hash of the tree as of the last catch-up.
+    pub fn root_hash(&self) -> Result<Hash> {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        match self.db.get_pinned_cf(internal_cf, ROOT_KEY_KEY)? {
+            None => Ok(NULL_HASH),
+            Some(root_key) => Ok(fetch_existing_node(&self.db, &root_key)?.hash()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::ReadOnlyMerk;
+    use crate::test_utils::*;
+    use crate::tree::Op;
+    use crate::Merk;
+
+    #[test]
+    fn get_fresh() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let secondary_path = format!("{}.secondary", path);
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+
+        let replica = ReadOnlyMerk::open(&path, &secondary_path).unwrap();
+        assert_eq!(replica.get(&seq_key(5)).unwrap(), Some(vec![123; 60]));
+        assert_eq!(replica.root_hash().unwrap(), merk.root_hash());
+
+        merk.apply(&make_batch_seq(10..20), &[]).unwrap();
+
+        // within the staleness bound, the replica isn't caught up
+        let hour = Duration::from_secs(3600);
+        assert_eq!(replica.get_fresh(&seq_key(15), hour).unwrap(), None);
+
+        let caught_up_before = replica.last_catch_up();
+        let fresh = replica
+            .get_fresh(&seq_key(15), Duration::from_secs(0))
+            .unwrap();
+        assert_eq!(fresh, Some(vec![123; 60]));
+        assert!(replica.last_catch_up() > caught_up_before);
+        assert_eq!(replica.root_hash().unwrap(), merk.root_hash());
+
+        drop(replica);
+        merk.destroy().unwrap();
+        std::fs::remove_dir_all(&secondary_path).unwrap();
+    }
+    #[test]
+    fn get_history_mode() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let secondary_path = format!("{}.secondary", path);
+        let mut merk = Merk::open_with_history(&path, Merk::default_db_opts(), 2).unwrap();
+        merk.apply(&[(vec![1], Op::Put(vec![2]))], &[]).unwrap();
+        merk.apply(&[(vec![1], Op::Put(vec![3]))], &[]).unwrap();
+
+        let replica = ReadOnlyMerk::open(&path, &secondary_path).unwrap();
+        assert_eq!(replica.get(&[1]).unwrap(), Some(vec![3]));
+
+        drop(replica);
+        merk.destroy().unwrap();
+        std::fs::remove_dir_all(&secondary_path).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+//! Provides `Merk::open_with_selfcheck`, which audits the top of the tree when
+//! opening a store so that corruption is caught at startup.
+
+use std::path::Path;
+
+use failure::bail;
+
+use super::{fetch_existing_node, Merk};
+use crate::tree::{kv_hash, Tree};
+use crate::Result;
+
+impl Merk {
+    /// Opens a store with the specified file path like `Merk::open`, then runs
+    /// `selfcheck` with the given depth before returning, erroring if any
+    /// corruption is found. If no store exists at that path, one will be
+    /// created.
+    ///
+    /// The root node must load for the store to open at all, so a `depth` of
+    /// 0 only additionally checks the root's own hashes. Each extra level
+    /// roughly doubles the number of nodes read, so `depth` bounds the time
+    /// spent at startup.
+    pub fn open_with_selfcheck<P: AsRef<Path>>(path: P, depth: usize) -> Result<Merk> {
+        let merk = Merk::open(path)?;
+        merk.selfcheck(depth)?;
+        Ok(merk)
+    }
+
+    /// Opens a store with the specified file path like `Merk::open`, then
+    /// audits the root node and its children before returning, erroring if
+    /// they are inconsistent. Intended for opening a store after an unclean
+    /// shutdown.
+    ///
+    /// RocksDB replays its write-ahead log when opening, and each commit is
+    /// written as a single atomic batch, so the recovered state contains
+    /// either all or none of a commit's writes, and the root hash is
+    /// reconstructed from the recovered root node. This checks that the
+    /// recovered root node and the nodes it links to are actually present and
+    /// match, so that a store whose persisted nodes were lost or torn (e.g. by
+    /// a commit written without the write-ahead log, or by a faulty disk) is
+    /// rejected rather than failing on a later read.
+    pub fn open_verify_recovery<P: AsRef<Path>>(path: P) -> Result<Merk> {
+        Merk::open_with_selfcheck(path, 1)
+    }
+
+    /// Audits the top `depth` levels of the tree as stored on disk, checking
+    /// that:
+    /// - the committed root node matches the in-memory root hash,
+    /// - each node's stored key/value hash matches its key and value,
+    /// - each node within `depth` levels of the root can be read and decoded,
+    ///   and its hash and height match those stored in its parent's link.
+    ///
+    /// Nodes below `depth` are trusted, so this does not guarantee the whole
+    /// store is intact.
+    pub fn selfcheck(&self, depth: usize) -> Result<()> {
+        let root = match self.fetch_root()? {
+            None => return Ok(()),
+            Some(root) => root,
+        };
+
+        if root.hash() != self.root_hash() {
+            bail!("Committed root node does not match the loaded root hash");
+        }
+
+        self.check_node(&root, depth)
+    }
+
+    fn check_node(&self, node: &Tree, depth: usize) -> Result<()> {
+        if kv_hash(node.key(), node.value()) != *node.kv_hash() {
+            bail!("Corrupted key/value hash for node {:?}", node.key());
+        }
+
+        if depth == 0 {
+            return Ok(());
+        }
+
+        for left in [true, false].iter() {
+            let link = match node.link(*left) {
+                None => continue,
+                Some(link) => link,
+            };
+
+            // check that the raw bytes decode before `fetch_existing_node`
+            // (which panics on invalid encodings) reads them
+            let bytes = match self.db.get_pinned(link.key())? {
+                None => bail!("Missing child node {:?}", link.key()),
+                Some(bytes) => bytes,
+            };
+            if Tree::try_decode_stored(&bytes[..]).is_err() {
+                bail!("Could not decode child node {:?}", link.key());
+            }
+
+            let child = fetch_existing_node(&self.db, link.key())?;
+            if child.hash() != *link.hash() {
+                bail!("Hash mismatch for child node {:?}", link.key());
+            }
+            if child.height() != link.height() {
+                bail!("Height mismatch for child node {:?}", link.key());
+            }
+
+            self.check_node(&child, depth - 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::test_utils::*;
+    use crate::tree::Tree;
+    use crate::Merk;
+
+    #[test]
+    fn selfcheck_detects_corruption() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..3), &[]).unwrap();
+        merk.selfcheck(10).unwrap();
+
+        // replace a leaf with a different, validly-encoded node
+        let leaf = Tree::new(seq_key(0), vec![1, 2, 3]);
+        merk.db().put(seq_key(0), leaf.encode()).unwrap();
+        drop(merk);
+
+        let merk = Merk::open_with_selfcheck(&path, 0).unwrap();
+        drop(merk);
+        assert!(Merk::open_with_selfcheck(&path, 1).is_err());
+
+        // an undecodable node is reported rather than panicking
+        let merk = Merk::open(&path).unwrap();
+        merk.db().put(seq_key(0), [1, 2, 3]).unwrap();
+        let err = merk.selfcheck(1).unwrap_err();
+        assert!(err.to_string().contains("Could not decode"));
+
+        merk.destroy().unwrap();
+    }
+
+    /// Copies the files of an open store, like the state left on disk if the
+    /// process had crashed at that point.
+    fn copy_crashed(from: &str, to: &str) {
+        std::fs::create_dir_all(to).unwrap();
+        for entry in std::fs::read_dir(from).unwrap() {
+            let entry = entry.unwrap();
+            std::fs::copy(entry.path(), Path::new(to).join(entry.file_name())).unwrap();
+        }
+    }
+
+    #[test]
+    fn recovery_after_crash() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let crashed_path = format!("{}.crashed", path);
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        merk.flush().unwrap();
+
+        // this commit is only in the write-ahead log, not in any flushed table
+        merk.apply(&make_batch_seq(100..200), &[]).unwrap();
+        let root_hash = merk.root_hash();
+        copy_crashed(&path, &crashed_path);
+
+        let recovered = Merk::open_verify_recovery(&crashed_path).unwrap();
+        assert_eq!(recovered.root_hash(), root_hash);
+        assert_eq!(recovered.get(&seq_key(150)).unwrap(), Some(vec![123; 60]));
+        recovered.destroy().unwrap();
+
+        // a commit whose node writes were lost is detected
+        let child_key = merk.use_tree(|tree| tree.unwrap().link(true).unwrap().key().to_vec());
+        merk.db().delete(&child_key).unwrap();
+        copy_crashed(&path, &crashed_path);
+        drop(merk);
+
+        assert!(Merk::open(&crashed_path).is_ok());
+        assert!(Merk::open_verify_recovery(&crashed_path).is_err());
+
+        Merk::open(&crashed_path).unwrap().destroy().unwrap();
+        Merk::open(&path).unwrap().destroy().unwrap();
+    }
+}
@@ -0,0 +1,124 @@
+//! Provides `Transaction`, for assembling a batch with read-your-writes
+//! semantics and applying it atomically.
+
+use std::collections::BTreeMap;
+
+use super::Merk;
+use crate::tree::Op;
+use crate::Result;
+
+/// A set of pending writes to a `Merk`, created by `Merk::transaction`.
+///
+/// Reads through the transaction see its own pending writes on top of the
+/// store's state. The transaction borrows the store mutably, so the base
+/// state can't change while it is open - reads are isolated at the level of
+/// a snapshot of the store plus the transaction's local overlay.
+///
+/// Nothing is written until `commit`, which applies all pending writes as a
+/// single batch. Dropping the transaction without committing (or calling
+/// `abort`) discards them.
+pub struct Transaction<'a> {
+    merk: &'a mut Merk,
+    ops: BTreeMap<Vec<u8>, Op>,
+}
+
+impl<'a> Transaction<'a> {
+    /// Puts a value for `key`, replacing any pending write for it.
+    pub fn put(&mut self, key: Vec<u8>, value: Vec<u8>) {
+        self.ops.insert(key, Op::Put(value));
+    }
+
+    /// Deletes `key`, replacing any pending write for it.
+    pub fn delete(&mut self, key: Vec<u8>) {
+        self.ops.insert(key, Op::Delete);
+    }
+
+    /// Gets the value for `key`, as it would be after committing the
+    /// transaction.
+    pub fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+        match self.ops.get(key) {
+            Some(Op::Put(value)) => Ok(Some(value.clone())),
+            Some(Op::Delete) => Ok(None),
+            None => self.merk.get(key),
+        }
+    }
+
+    /// Returns the number of keys with pending writes.
+    pub fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    /// Returns `true` if there are no pending writes.
+    pub fn is_empty(&self) -> bool {
+        self.ops.is_empty()
+    }
+
+    /// Applies all pending writes atomically. Deletes of keys which do not exist
+    /// in the store are dropped, since they would cause the batch to fail.
+    pub fn commit(self) -> Result<()> {
+        let mut batch = Vec::with_capacity(self.ops.len());
+        for (key, op) in self.ops {
+            if let Op::Delete = op {
+                if self.merk.get(&key)?.is_none() {
+                    continue;
+                }
+            }
+            batch.push((key, op));
+        }
+
+        // keys from the map are sorted and unique
+        unsafe { self.merk.apply_unchecked(&batch, &[]) }
+    }
+
+    /// Discards all pending writes. This is the same as dropping the
+    /// transaction.
+    pub fn abort(self) {}
+}
+
+impl Merk {
+    /// Starts a transaction, which accumulates writes and applies them
+    /// atomically on `Transaction::commit`.
+    pub fn transaction(&mut self) -> Transaction {
+        Transaction {
+            merk: self,
+            ops: BTreeMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+
+    #[test]
+    fn transaction() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        let root_hash = merk.root_hash();
+
+        let mut tx = merk.transaction();
+        tx.put(seq_key(20), vec![1]);
+        tx.delete(seq_key(5));
+        tx.delete(seq_key(50));
+        assert_eq!(tx.get(&seq_key(20)).unwrap(), Some(vec![1]));
+        assert_eq!(tx.get(&seq_key(5)).unwrap(), None);
+        assert_eq!(tx.get(&seq_key(6)).unwrap(), Some(vec![123; 60]));
+        tx.abort();
+        assert_eq!(merk.root_hash(), root_hash);
+
+        {
+            let mut tx = merk.transaction();
+            tx.put(seq_key(20), vec![1]);
+        }
+        assert_eq!(merk.get(&seq_key(20)).unwrap(), None);
+
+        let mut tx = merk.transaction();
+        tx.put(seq_key(20), vec![1]);
+        tx.put(seq_key(20), vec![2]);
+        tx.delete(seq_key(5));
+        tx.delete(seq_key(50));
+        tx.commit().unwrap();
+        assert_eq!(merk.get(&seq_key(20)).unwrap(), Some(vec![2]));
+        assert_eq!(merk.get(&seq_key(5)).unwrap(), None);
+    }
+}
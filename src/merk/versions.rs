@@ -0,0 +1,320 @@
+//! Provides version retention for time-travel reads: `Merk::retain_version`
+//! keeps a checkpoint of the current state, and `Merk::retained_roots` lists
+//! the versions which can still be opened.
+//!
+//! Versions share their version-to-root mapping with the heights recorded by
+//! `Merk::apply_at_height`, so a height can be retained (and released) as a
+//! version and the root of a retained version can be read with
+//! `Merk::root_at`.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::path::PathBuf;
+
+use failure::bail;
+use rocksdb::WriteBatch;
+
+use super::{Merk, INTERNAL_CF_NAME};
+use crate::proofs::{encode_into, results, Decoder, Node, Op as ProofOp, Query};
+use crate::tree::{kv_hash, Hash, HASH_LENGTH};
+use crate::Result;
+
+/// The prefix of the keys in the internal column family which map a version
+/// number (or height) to its root hash.
+const VERSION_KEY_PREFIX: &[u8] = b"version";
+
+pub(super) fn version_key(version: u64) -> Vec<u8> {
+    let mut key = VERSION_KEY_PREFIX.to_vec();
+    key.extend_from_slice(&version.to_be_bytes());
+    key
+}
+
+impl Merk {
+    /// Retains the current state of the store as `version` (e.g. a block
+    /// height), so it can later be opened with `open_version`.
+    ///
+    /// The state is kept as a RocksDB checkpoint in a `.versions` directory
+    /// next to the store, which shares its table files with the store where
+    /// possible. Checkpoints are not removed by `destroy`, so they should be
+    /// released with `release_version` when no longer needed.
+    ///
+    /// Errors if `version` is already retained, or if a different root is
+    /// recorded for it (by `apply_at_height`). A height whose root is the
+    /// current root can be retained. Errors if a write group is active, since
+    /// the checkpoint would not include its changes.
+    pub fn retain_version(&mut self, version: u64) -> Result<()> {
+        if self.in_write_group() {
+            bail!("Cannot retain a version while a write group is active");
+        }
+        if self.version_path(version).exists() {
+            bail!("Version {} is already retained", version);
+        }
+        let recorded = self.root_at(version)?;
+        if recorded.map_or(false, |root_hash| root_hash != self.root_hash()) {
+            bail!(
+                "A different root is already recorded for version {}",
+                version
+            );
+        }
+
+        std::fs::create_dir_all(self.versions_path())?;
+        self.checkpoint(self.version_path(version))?;
+
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+        batch.put_cf(internal_cf, version_key(version), self.root_hash());
+        self.write(batch)
+    }
+
+    /// Returns the `(version, root_hash)` pairs for the versions which can
+    /// currently be opened with `open_version`, in ascending order of version.
+    ///
+    /// Versions whose checkpoint has been removed from disk (or which were only
+    /// recorded by `apply_at_height`) are skipped.
+    pub fn retained_roots(&self) -> Result<Vec<(u64, Hash)>> {
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let mut iter = self.db.raw_iterator_cf(internal_cf);
+        iter.seek(VERSION_KEY_PREFIX);
+
+        let mut roots = vec![];
+        while iter.valid() {
+            let key = iter.key().unwrap();
+            if !key.starts_with(VERSION_KEY_PREFIX) {
+                break;
+            }
+
+            let version_bytes = &key[VERSION_KEY_PREFIX.len()..];
+            let root_hash = iter.value().unwrap();
+            if version_bytes.len() != 8 || root_hash.len() != HASH_LENGTH {
+                bail!("Invalid version record");
+            }
+            let version = u64::from_be_bytes(version_bytes.try_into().unwrap());
+
+            if self.version_path(version).exists() {
+                roots.push((version, root_hash.try_into().unwrap()));
+            }
+            iter.next();
+        }
+
+        Ok(roots)
+    }
+
+    /// Opens the state of the store retained as `version`. Errors if the
+    /// version was never retained or its checkpoint has been removed.
+    ///
+    /// The returned `Merk` is independent of this one, so writing to it does
+    /// not affect the current state (but does change the retained version).
+    pub fn open_version(&self, version: u64) -> Result<Merk> {
+        let path = self.version_path(version);
+        if !path.exists() {
+            bail!("Version {} is not retained", version);
+        }
+        self.open_in_same_mode(path)
+    }
+
+    /// Finds the earliest retained version between `from_version` and
+    /// `to_version` (inclusive) at which `key` existed, and creates a proof of
+    /// its value against that version's root hash. Returns the matching
+    /// version along with the proof, which can be verified with
+    /// `merk::verify_query` and the root hash listed for the version by
+    /// `retained_roots`.
+    ///
+    /// This opens each retained version in the range until one contains the
+    /// key. Errors if none of them do.
+    pub fn prove_existed_between(
+        &self,
+        key: &[u8],
+        from_version: u64,
+        to_version: u64,
+    ) -> Result<(u64, Vec<u8>)> {
+        let versions = self
+            .retained_roots()?
+            .into_iter()
+            .map(|(version, _)| version)
+            .filter(|version| (from_version..=to_version).contains(version));
+
+        for version in versions {
+            let merk = self.open_version(version)?;
+            if merk.get(key)?.is_some() {
+                return Ok((version, merk.prove(&[key.to_vec()])?));
+            }
+        }
+
+        bail!(
+            "Key {:?} does not exist at any retained version between {} and {}",
+            key,
+            from_version,
+            to_version
+        )
+    }
+
+    /// Creates a proof for `query` against the current root hash, for a client
+    /// holding a `PartialMerk` created from a proof of the same query against
+    /// `old_root` to update it with `PartialMerk::apply_update`.
+    ///
+    /// Entries which are unchanged since `old_root` are only included as
+    /// their key/value hashes, which the client fills back in from the
+    /// entries it already holds, so the update mostly carries the changed
+    /// entries. `old_root` must be the current root or the root of a version
+    /// retained with `retain_version`.
+    pub fn prove_update(&self, old_root: Hash, query: &Query) -> Result<Vec<u8>> {
+        let new_proof = self.prove_query(query)?;
+        let old_proof = if old_root == self.root_hash() {
+            new_proof.clone()
+        } else {
+            let version = match self
+                .retained_roots()?
+                .into_iter()
+                .find(|(_, root_hash)| *root_hash == old_root)
+            {
+                None => bail!("Old root is not retained"),
+                Some((version, _)) => version,
+            };
+            self.open_version(version)?.prove_query(query)?
+        };
+        let old_entries: BTreeMap<_, _> = results(&old_proof)?.into_iter().collect();
+
+        let mut ops = vec![];
+        for op in Decoder::new(&new_proof) {
+            ops.push(match op? {
+                ProofOp::Push(Node::KV(key, value)) if old_entries.get(&key) == Some(&value) => {
+                    ProofOp::Push(Node::KVHash(kv_hash(&key, &value)))
+                }
+                op => op,
+            });
+        }
+
+        let mut bytes = Vec::with_capacity(new_proof.len());
+        encode_into(ops.iter(), &mut bytes);
+        Ok(bytes)
+    }
+
+    /// Releases a version retained with `retain_version`, deleting its
+    /// checkpoint from disk along with its root record, so this also prunes
+    /// roots recorded by `apply_at_height`. Releasing a version which is
+    /// neither retained nor recorded is a no-op.
+    pub fn release_version(&mut self, version: u64) -> Result<()> {
+        let path = self.version_path(version);
+        if path.exists() {
+            std::fs::remove_dir_all(path)?;
+        }
+
+        let internal_cf = self.db.cf_handle(INTERNAL_CF_NAME).unwrap();
+        let mut batch = WriteBatch::default();
+        batch.delete_cf(internal_cf, version_key(version));
+        self.write(batch)
+    }
+
+    fn versions_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".versions");
+        path.into()
+    }
+
+    fn version_path(&self, version: u64) -> PathBuf {
+        self.versions_path().join(version.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::proofs::{to_partial_merk, Query};
+    use crate::test_utils::*;
+    use crate::{Merk, Op, HASH_LENGTH};
+
+    #[test]
+    fn retained_roots() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        assert!(merk.retained_roots().unwrap().is_empty());
+
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        let hash_1 = merk.root_hash();
+        merk.retain_version(10).unwrap();
+        assert!(merk.retain_version(10).is_err());
+
+        merk.apply(&make_batch_seq(10..20), &[]).unwrap();
+        let hash_2 = merk.root_hash();
+        merk.begin_write_group().unwrap();
+        assert!(merk.retain_version(20).is_err());
+        merk.end_write_group().unwrap();
+        merk.retain_version(20).unwrap();
+        merk.retain_version(2).unwrap();
+
+        assert_eq!(
+            merk.retained_roots().unwrap(),
+            vec![(2, hash_2), (10, hash_1), (20, hash_2)]
+        );
+
+        let old = merk.open_version(10).unwrap();
+        assert_eq!(old.root_hash(), hash_1);
+        assert_eq!(old.get(&seq_key(15)).unwrap(), None);
+        drop(old);
+
+        let (version, proof) = merk.prove_existed_between(&seq_key(15), 0, 100).unwrap();
+        assert_eq!(version, 2);
+        let values = crate::verify_query(&proof, &[seq_key(15)], hash_2).unwrap();
+        assert_eq!(values, vec![Some(vec![123; 60])]);
+        assert_eq!(
+            merk.prove_existed_between(&seq_key(5), 3, 100).unwrap().0,
+            10
+        );
+        assert!(merk.prove_existed_between(&seq_key(15), 3, 19).is_err());
+        assert!(merk.prove_existed_between(&seq_key(100), 0, 100).is_err());
+
+        // versions removed from disk are no longer listed
+        std::fs::remove_dir_all(format!("{}.versions/20", path)).unwrap();
+        merk.release_version(2).unwrap();
+        assert_eq!(merk.retained_roots().unwrap(), vec![(10, hash_1)]);
+        assert!(merk.open_version(20).is_err());
+
+        merk.destroy().unwrap();
+        std::fs::remove_dir_all(format!("{}.versions", path)).unwrap();
+    }
+
+    #[test]
+    fn prove_update() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let old_root = merk.root_hash();
+        merk.retain_version(1).unwrap();
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(20)..seq_key(30));
+        let mut partial = to_partial_merk(&merk.prove_query(&query).unwrap(), old_root).unwrap();
+
+        let mut between = seq_key(22);
+        between.push(0);
+        merk.apply(
+            &[
+                (seq_key(21), Op::Put(vec![1])),
+                (between.clone(), Op::Put(vec![2])),
+                (seq_key(27), Op::Delete),
+                (seq_key(50), Op::Put(vec![3])),
+            ],
+            &[],
+        )
+        .unwrap();
+        let new_root = merk.root_hash();
+
+        let update = merk.prove_update(old_root, &query).unwrap();
+        assert!(update.len() < merk.prove_query(&query).unwrap().len());
+        let changed = partial.apply_update(&update, new_root).unwrap();
+        assert_eq!(changed, vec![seq_key(21), between.clone(), seq_key(27)]);
+        assert_eq!(partial.root_hash(), new_root);
+        assert_eq!(partial.get(&seq_key(21)).unwrap(), Some(&[1][..]));
+        assert_eq!(partial.get(&between).unwrap(), Some(&[2][..]));
+        assert_eq!(partial.get(&seq_key(27)).unwrap(), None);
+        assert_eq!(partial.get(&seq_key(28)).unwrap(), Some(&[123; 60][..]));
+
+        // an update against the current root changes nothing
+        let update = merk.prove_update(new_root, &query).unwrap();
+        assert!(partial.apply_update(&update, new_root).unwrap().is_empty());
+
+        assert!(merk.prove_update([1; HASH_LENGTH], &query).is_err());
+
+        merk.destroy().unwrap();
+        std::fs::remove_dir_all(format!("{}.versions", path)).unwrap();
+    }
+}
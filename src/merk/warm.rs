@@ -0,0 +1,107 @@
+//! Provides `Merk::export_warm_set` and `Merk::warm_from`, for persisting which
+//! nodes are resident in memory and preloading them after a restart.
+
+use std::cmp::Ordering;
+
+use super::Merk;
+use crate::tree::Tree;
+use crate::Result;
+
+impl Merk {
+    /// Returns the keys of the nodes which are currently resident in memory,
+    /// in key order. Passing these to `warm_from` after reopening the store
+    /// avoids a cold start.
+    pub fn export_warm_set(&self) -> Vec<Vec<u8>> {
+        fn collect(tree: &Tree, keys: &mut Vec<Vec<u8>>) {
+            if let Some(child) = tree.child(true) {
+                collect(child, keys);
+            }
+            keys.push(tree.key().to_vec());
+            if let Some(child) = tree.child(false) {
+                collect(child, keys);
+            }
+        }
+
+        self.use_tree(|maybe_tree| {
+            let mut keys = vec![];
+            if let Some(tree) = maybe_tree {
+                collect(tree, &mut keys);
+            }
+            keys
+        })
+    }
+
+    /// Loads the nodes for the given keys (as returned by `export_warm_set`)
+    /// into memory, along with the nodes on the paths to them from the root.
+    ///
+    /// The warm set is advisory: keys which are no longer in the tree (e.g. if
+    /// the warm set was exported before later changes) are simply ignored,
+    /// though the nodes on the path to where they would be are still loaded.
+    pub fn warm_from(&self, keys: &[Vec<u8>]) -> Result<()> {
+        let source = self.source();
+
+        self.use_tree_mut(|maybe_tree| -> Result<()> {
+            let tree = match maybe_tree {
+                None => return Ok(()),
+                Some(tree) => tree,
+            };
+
+            for key in keys {
+                let mut cursor = &mut *tree;
+                loop {
+                    let left = match key.as_slice().cmp(cursor.key()) {
+                        Ordering::Equal => break,
+                        Ordering::Less => true,
+                        Ordering::Greater => false,
+                    };
+
+                    let pruned = match cursor.link(left) {
+                        None => break,
+                        Some(link) => link.is_reference(),
+                    };
+                    if pruned {
+                        cursor.load(left, &source)?;
+                    }
+                    cursor = cursor.child_mut(left).unwrap();
+                }
+            }
+
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+    use crate::Merk;
+
+    #[test]
+    fn warm_set_roundtrip() {
+        let path = std::thread::current().name().unwrap().to_owned();
+        let mut merk = Merk::open(&path).unwrap();
+        merk.apply(&make_batch_seq(0..1000), &[]).unwrap();
+        drop(merk);
+
+        // only the root is resident after opening
+        let merk = Merk::open(&path).unwrap();
+        assert_eq!(merk.export_warm_set().len(), 1);
+
+        let hot: Vec<_> = (100..110).map(seq_key).collect();
+        merk.warm_from(&hot).unwrap();
+        let warm_set = merk.export_warm_set();
+        assert!(hot.iter().all(|key| warm_set.contains(key)));
+        assert!(warm_set.len() < 200);
+        drop(merk);
+
+        let merk = Merk::open(&path).unwrap();
+        merk.warm_from(&warm_set).unwrap();
+        assert_eq!(merk.export_warm_set(), warm_set);
+
+        // stale keys are ignored
+        merk.warm_from(&[vec![255; 3], seq_key(5000)]).unwrap();
+        assert_eq!(merk.get(&seq_key(100)).unwrap(), Some(vec![123; 60]));
+
+        merk.destroy().unwrap();
+    }
+}
@@ -0,0 +1,145 @@
+//! Provides `Merk::begin_write_group` and `Merk::end_write_group`, for
+//! combining the RocksDB writes of several applies into one.
+
+use failure::bail;
+use rocksdb::WriteBatch;
+
+use super::Merk;
+use crate::Result;
+
+/// The state of a write group started by `Merk::begin_write_group`.
+#[derive(Default)]
+pub(crate) struct WriteGroup {
+    /// The RocksDB writes of the applies in the group so far.
+    pub(crate) batch: WriteBatch,
+    /// The heights recorded by `apply_at_height` within the group.
+    pub(crate) heights: Vec<u64>,
+    /// Set when an apply within the group fails and the tree is rolled back to
+    /// its state from before the group, discarding the group's changes.
+    pub(crate) poisoned: bool,
+}
+
+impl Merk {
+    /// Starts a write group. Until `end_write_group` is called, `apply` and
+    /// its variants update the in-memory tree immediately, but add their
+    /// RocksDB writes to a single `WriteBatch` rather than writing each one,
+    /// saving a write (and possibly an fsync) per apply in bursts of small
+    /// batches. Errors if a write group is already active.
+    ///
+    /// The applied changes are not durable until the group ends: if the
+    /// process crashes (or the `Merk` is dropped) first, the store reopens at
+    /// its state from before the group. Reads through the in-memory tree
+    /// (e.g. `get`, `root_hash` and proofs) see the grouped changes, but
+    /// methods which read committed nodes directly from disk (e.g. `chunks`)
+    /// do not, and those which would mix the two (`staged_root_hash`,
+    /// `apply_batch_bytes`, `delete_prefix_with_proof`, and `merge_from` from
+    /// this store) error until the group ends. Pending deltas from
+    /// `apply_merge` are not folded in until the first apply after the group
+    /// ends.
+    ///
+    /// If an apply within the group fails, the tree is rolled back to its
+    /// state from before the group and the group is discarded: further
+    /// applies error until the group is ended, and `end_write_group` errors
+    /// without writing anything, so the failure can't go unnoticed.
+    pub fn begin_write_group(&mut self) -> Result<()> {
+        if self.write_group.is_some() {
+            bail!("A write group is already active");
+        }
+        self.write_group = Some(WriteGroup::default());
+        Ok(())
+    }
+
+    /// Returns `true` if a write group started by `begin_write_group` is
+    /// active.
+    pub fn in_write_group(&self) -> bool {
+        self.write_group.is_some()
+    }
+
+    /// Ends the active write group, writing the changes of all of its applies
+    /// to RocksDB at once. Errors if no write group is active, or if the group
+    /// was discarded by a failed apply (in which case the group is still
+    /// ended).
+    ///
+    /// If the write fails, the group is discarded and the tree is rolled back
+    /// to its state from before the group.
+    pub fn end_write_group(&mut self) -> Result<()> {
+        let group = match self.write_group.take() {
+            None => bail!("No write group is active"),
+            Some(group) => group,
+        };
+        if group.poisoned {
+            bail!("The write group was discarded by a failed apply");
+        }
+
+        if let Err(err) = self.write(group.batch) {
+            self.load_root()?;
+            return Err(err);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_utils::*;
+
+    #[test]
+    fn write_group() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        assert!(merk.end_write_group().is_err());
+
+        merk.begin_write_group().unwrap();
+        assert!(merk.begin_write_group().is_err());
+        for i in 1..5 {
+            merk.apply(&make_batch_seq(i * 10..(i + 1) * 10), &[])
+                .unwrap();
+        }
+        assert_eq!(merk.get(&seq_key(45)).unwrap(), Some(vec![123; 60]));
+        assert!(merk.db().get(seq_key(45)).unwrap().is_none());
+        let root_hash = merk.root_hash();
+        merk.end_write_group().unwrap();
+        assert!(merk.db().get(seq_key(45)).unwrap().is_some());
+
+        let mut expected = TempMerk::new().unwrap();
+        expected.apply(&make_batch_seq(0..50), &[]).unwrap();
+        assert_eq!(root_hash, expected.root_hash());
+
+        // a failed apply discards the group, which then fails to end
+        merk.begin_write_group().unwrap();
+        merk.apply(&make_batch_seq(50..60), &[]).unwrap();
+        assert!(merk.apply(&make_del_batch_seq(100..101), &[]).is_err());
+        assert!(merk.in_write_group());
+        assert_eq!(merk.root_hash(), root_hash);
+        assert!(merk.get(&seq_key(55)).unwrap().is_none());
+        assert!(merk.apply(&make_batch_seq(60..70), &[]).is_err());
+        assert!(merk.end_write_group().is_err());
+        assert!(!merk.in_write_group());
+        assert!(merk.db().get(seq_key(55)).unwrap().is_none());
+
+        merk.apply(&make_batch_seq(60..70), &[]).unwrap();
+        assert!(merk.get(&seq_key(65)).unwrap().is_some());
+    }
+    #[test]
+    fn write_group_rejects_committed_reads() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..10), &[]).unwrap();
+        let mut other = TempMerk::new().unwrap();
+
+        merk.begin_write_group().unwrap();
+        merk.apply(&make_batch_seq(10..20), &[]).unwrap();
+        let batch = make_batch_seq(20..21);
+        assert!(merk.staged_root_hash(&batch).is_err());
+        assert!(merk.apply_batch_bytes(&batch, &[]).is_err());
+        assert!(merk.delete_prefix_with_proof(&[0]).is_err());
+        assert!(other.merge_from(&merk, false).is_err());
+        merk.end_write_group().unwrap();
+
+        // the rejections leave the group's changes intact
+        assert!(merk.staged_root_hash(&batch).is_ok());
+        other.merge_from(&merk, false).unwrap();
+        assert_eq!(other.root_hash(), merk.root_hash());
+        merk.delete_prefix_with_proof(&[0]).unwrap();
+        assert!(merk.get(&seq_key(15)).unwrap().is_none());
+    }
+}
@@ -1,3 +1,4 @@
+use blake2_rfc::blake2b::Blake2b;
 use failure::bail;
 use rocksdb::DBRawIterator;
 
@@ -6,7 +7,7 @@ use super::{
     Node, Op,
 };
 use crate::error::Result;
-use crate::tree::{Fetch, Hash, RefWalker, Tree};
+use crate::tree::{Fetch, Hash, RefWalker, Tree, HASH_LENGTH};
 
 /// The minimum number of layers the trunk will be guaranteed to have before
 /// splitting into multiple chunks. If the tree's height is less than double
@@ -115,14 +116,93 @@ where
     }
 }
 
+/// Reusable buffers for building chunk proofs, so that producing many chunks
+/// in a tight loop doesn't allocate for every node decoded from RocksDB.
+///
+/// Decoded nodes are read into a single reused `Tree`, and the key and value
+/// buffers of the ops produced for one chunk are returned to a pool when the
+/// scratch is cleared for the next one, so after warming up chunk generation
+/// only allocates when a key or value outgrows every pooled buffer.
+pub struct DecodeScratch {
+    node: Tree,
+    stack: Vec<Vec<u8>>,
+    pub(crate) ops: Vec<Op>,
+    /// Indices into `ops` of the nodes whose values are stored outside of the
+    /// node, which are pushed with an empty value.
+    pub(crate) external: Vec<usize>,
+    pub(crate) bytes: Vec<u8>,
+    free: Vec<Vec<u8>>,
+}
+
+impl DecodeScratch {
+    /// Creates an empty `DecodeScratch`. Its buffers grow as they are used.
+    pub fn new() -> Self {
+        DecodeScratch {
+            node: Tree::new(vec![], vec![]),
+            stack: Vec::with_capacity(32),
+            ops: Vec::with_capacity(512),
+            external: vec![],
+            bytes: vec![],
+            free: vec![],
+        }
+    }
+
+    /// Clears the ops and output bytes of the previous operation, returning
+    /// their key and value buffers to the pool.
+    pub(crate) fn clear(&mut self) {
+        for op in self.ops.drain(..) {
+            if let Op::Push(Node::KV(key, value)) = op {
+                self.free.push(key);
+                self.free.push(value);
+            }
+        }
+        self.free.append(&mut self.stack);
+        self.external.clear();
+        self.bytes.clear();
+    }
+}
+
+impl Default for DecodeScratch {
+    fn default() -> Self {
+        DecodeScratch::new()
+    }
+}
+
+/// Copies `bytes` into a buffer taken from `free`, only allocating if the pool
+/// is empty.
+fn pooled_copy(free: &mut Vec<Vec<u8>>, bytes: &[u8]) -> Vec<u8> {
+    let mut buf = free.pop().unwrap_or_default();
+    buf.clear();
+    buf.extend_from_slice(bytes);
+    buf
+}
+
 /// Builds a chunk proof by iterating over values in a RocksDB, ending the chunk
 /// when a node with key `end_key` is encountered.
 ///
 /// Advances the iterator for all nodes in the chunk and the `end_key` (if any).
 pub(crate) fn get_next_chunk(iter: &mut DBRawIterator, end_key: Option<&[u8]>) -> Result<Vec<Op>> {
-    let mut chunk = Vec::with_capacity(512);
-    let mut stack = Vec::with_capacity(32);
-    let mut node = Tree::new(vec![], vec![]);
+    let mut scratch = DecodeScratch::new();
+    get_next_chunk_into(iter, end_key, &mut scratch)?;
+    Ok(scratch.ops)
+}
+
+/// Like `get_next_chunk`, but clears `scratch` and builds the chunk's ops into
+/// `scratch.ops`, reusing its buffers.
+pub(crate) fn get_next_chunk_into(
+    iter: &mut DBRawIterator,
+    end_key: Option<&[u8]>,
+    scratch: &mut DecodeScratch,
+) -> Result<()> {
+    scratch.clear();
+    let DecodeScratch {
+        node,
+        stack,
+        ops: chunk,
+        external,
+        free,
+        ..
+    } = scratch;
 
     while iter.valid() {
         let key = iter.key().unwrap();
@@ -134,9 +214,12 @@ pub(crate) fn get_next_chunk(iter: &mut DBRawIterator, end_key: Option<&[u8]>) -
         }
 
         let encoded_node = iter.value().unwrap();
-        Tree::decode_into(&mut node, vec![], encoded_node);
+        node.decode_stored_into(vec![], encoded_node);
 
-        let kv = Node::KV(key.to_vec(), node.value().to_vec());
+        if node.value_is_external() {
+            external.push(chunk.len());
+        }
+        let kv = Node::KV(pooled_copy(free, key), pooled_copy(free, node.value()));
         chunk.push(Op::Push(kv));
 
         if node.link(true).is_some() {
@@ -144,13 +227,13 @@ pub(crate) fn get_next_chunk(iter: &mut DBRawIterator, end_key: Option<&[u8]>) -
         }
 
         if let Some(child) = node.link(false) {
-            stack.push(child.key().to_vec());
+            stack.push(pooled_copy(free, child.key()));
         } else {
             while let Some(top_key) = stack.last() {
                 if key < top_key.as_slice() {
                     break;
                 }
-                stack.pop();
+                free.push(stack.pop().unwrap());
                 chunk.push(Op::Child);
             }
         }
@@ -162,7 +245,19 @@ pub(crate) fn get_next_chunk(iter: &mut DBRawIterator, end_key: Option<&[u8]>) -
         iter.next();
     }
 
-    Ok(chunk)
+    Ok(())
+}
+
+/// Hashes the encoded bytes of a chunk, e.g. to list the chunks of a tree in
+/// a manifest so downloads can be checked before they are processed.
+pub fn chunk_hash(bytes: &[u8]) -> Hash {
+    let mut hasher = Blake2b::new(HASH_LENGTH);
+    hasher.update(bytes);
+
+    let res = hasher.finalize();
+    let mut hash: Hash = Default::default();
+    hash.copy_from_slice(res.as_bytes());
+    hash
 }
 
 /// Verifies a leaf chunk proof by executing its operators. Checks that there
@@ -416,20 +511,36 @@ mod tests {
         assert_eq!(counts.kvhash, 0);
         drop(iter);
 
+        #[cfg(not(feature = "hash256"))]
+        let (left_hash, right_hash) = (
+            [
+                10, 147, 175, 167, 145, 38, 181, 73, 116, 253, 95, 138, 110, 222, 254, 197, 189,
+                68, 11, 151,
+            ],
+            [
+                128, 166, 214, 176, 167, 251, 11, 84, 228, 2, 97, 239, 253, 75, 184, 16, 137, 134,
+                72, 154,
+            ],
+        );
+        #[cfg(feature = "hash256")]
+        let (left_hash, right_hash) = (
+            [
+                110, 181, 146, 238, 226, 187, 115, 104, 201, 44, 244, 196, 118, 230, 4, 207, 0,
+                220, 190, 200, 239, 234, 238, 5, 73, 91, 64, 18, 237, 42, 155, 153,
+            ],
+            [
+                124, 4, 224, 5, 188, 28, 251, 202, 240, 104, 220, 99, 138, 28, 57, 168, 238, 225,
+                34, 38, 25, 137, 119, 167, 235, 128, 169, 137, 162, 150, 50, 158,
+            ],
+        );
+
         let mut iter = merk.db.raw_iterator();
         iter.seek_to_first();
 
         // left leaf
         let chunk = get_next_chunk(&mut iter, Some(root_key.as_slice())).unwrap();
         let ops = chunk.into_iter().map(|op| Ok(op));
-        let chunk = verify_leaf(
-            ops,
-            [
-                10, 147, 175, 167, 145, 38, 181, 73, 116, 253, 95, 138, 110, 222, 254, 197, 189,
-                68, 11, 151,
-            ],
-        )
-        .unwrap();
+        let chunk = verify_leaf(ops, left_hash).unwrap();
         let counts = count_node_types(chunk);
         assert_eq!(counts.kv, 15);
         assert_eq!(counts.hash, 0);
@@ -438,14 +549,7 @@ mod tests {
         // right leaf
         let chunk = get_next_chunk(&mut iter, None).unwrap();
         let ops = chunk.into_iter().map(|op| Ok(op));
-        let chunk = verify_leaf(
-            ops,
-            [
-                128, 166, 214, 176, 167, 251, 11, 84, 228, 2, 97, 239, 253, 75, 184, 16, 137, 134,
-                72, 154,
-            ],
-        )
-        .unwrap();
+        let chunk = verify_leaf(ops, right_hash).unwrap();
         let counts = count_node_types(chunk);
         assert_eq!(counts.kv, 15);
         assert_eq!(counts.hash, 0);
@@ -253,4 +253,41 @@ mod test {
         let bytes = [0x88];
         assert!(Op::decode(&bytes[..]).is_err());
     }
+
+    fn all_variants() -> Vec<Op> {
+        vec![
+            Op::Push(Node::Hash([1; HASH_LENGTH])),
+            Op::Push(Node::KVHash([2; HASH_LENGTH])),
+            Op::Push(Node::KV(vec![1, 2, 3], vec![4, 5, 6])),
+            Op::Parent,
+            Op::Child,
+        ]
+    }
+
+    fn encode(op: &Op) -> Vec<u8> {
+        let mut bytes = vec![];
+        op.encode_into(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn ed_roundtrip() {
+        for op in all_variants() {
+            let bytes = encode(&op);
+            assert_eq!(Op::decode(bytes.as_slice()).expect("decode failed"), op);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_roundtrip() {
+        for op in all_variants() {
+            let json = serde_json::to_string(&op).expect("serialize failed");
+            let decoded: Op = serde_json::from_str(&json).expect("deserialize failed");
+            assert_eq!(decoded, op);
+
+            // the binary encoding is unaffected
+            assert_eq!(encode(&decoded), encode(&op));
+        }
+    }
 }
@@ -0,0 +1,76 @@
+//! Encoding of values stored by a `Merk` in history mode (see
+//! `Merk::open_with_history`), along with verification of their proofs.
+
+use byteorder::{BigEndian, ByteOrder};
+use failure::bail;
+
+use super::verify_query;
+use crate::error::Result;
+use crate::tree::Hash;
+
+/// Encodes a value along with its history (newest first), as stored in the
+/// tree in history mode. Each entry is prefixed by its 4-byte big-endian
+/// length, starting with the current value.
+pub(crate) fn encode_history_value(value: &[u8], history: &[Vec<u8>]) -> Vec<u8> {
+    let len = 4 + value.len() + history.iter().map(|entry| 4 + entry.len()).sum::<usize>();
+    let mut bytes = Vec::with_capacity(len);
+    for entry in std::iter::once(value).chain(history.iter().map(Vec::as_slice)) {
+        bytes.extend_from_slice(&(entry.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(entry);
+    }
+    bytes
+}
+
+/// Decodes a value stored in history mode into the current value and its
+/// prior values, newest first.
+pub fn decode_history_value(mut bytes: &[u8]) -> Result<(Vec<u8>, Vec<Vec<u8>>)> {
+    let mut entries = vec![];
+    while !bytes.is_empty() {
+        if bytes.len() < 4 {
+            bail!("History value has a truncated length");
+        }
+        let len = BigEndian::read_u32(&bytes[..4]) as usize;
+        if bytes.len() < 4 + len {
+            bail!("History value has a truncated entry");
+        }
+        entries.push(bytes[4..4 + len].to_vec());
+        bytes = &bytes[4 + len..];
+    }
+
+    if entries.is_empty() {
+        bail!("History value is empty");
+    }
+    let value = entries.remove(0);
+    Ok((value, entries))
+}
+
+/// Verifies a proof created by `Merk::prove_history` against the expected
+/// hash. Returns the current value of `key` along with its prior values
+/// (newest first), or `None` if the key is proven to be absent.
+pub fn verify_history(
+    bytes: &[u8],
+    key: &[u8],
+    expected_hash: Hash,
+) -> Result<Option<(Vec<u8>, Vec<Vec<u8>>)>> {
+    let mut values = verify_query(bytes, &[key.to_vec()], expected_hash)?;
+    values
+        .pop()
+        .unwrap()
+        .map(|value| decode_history_value(&value))
+        .transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_value_roundtrip() {
+        let history = vec![vec![2], vec![], vec![1; 300]];
+        let bytes = encode_history_value(&[3, 3], &history);
+        assert_eq!(decode_history_value(&bytes).unwrap(), (vec![3, 3], history));
+
+        assert!(decode_history_value(&[]).is_err());
+        assert!(decode_history_value(&bytes[..bytes.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,368 @@
+use super::{Node, QueryItem};
+use crate::error::Result;
+use failure::bail;
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+/// `MapBuilder` allows a consumer to construct a `Map` by inserting the nodes
+/// contained in a proof, in key-order.
+pub(crate) struct MapBuilder(Map);
+
+impl MapBuilder {
+    /// Creates a new `MapBuilder` with an empty internal `Map`.
+    pub fn new() -> Self {
+        MapBuilder(Map {
+            entries: Default::default(),
+            right_edge: true,
+        })
+    }
+
+    /// Adds the node's data to the underlying `Map` (if node is type `KV`), or
+    /// makes a note of non-contiguous data (if node is type `KVHash` or
+    /// `Hash`).
+    pub fn insert(&mut self, node: &Node) -> Result<()> {
+        match node {
+            Node::KV(key, value) => {
+                if let Some((prev_key, _)) = self.0.entries.iter().next_back() {
+                    if key <= prev_key {
+                        bail!("Expected nodes to be in increasing key order");
+                    }
+                }
+
+                let value = (self.0.right_edge, value.clone());
+                self.0.entries.insert(key.clone(), value);
+                self.0.right_edge = true;
+            }
+            _ => self.0.right_edge = false,
+        }
+
+        Ok(())
+    }
+
+    /// Consumes the `MapBuilder` and returns its internal `Map`.
+    pub fn build(self) -> Map {
+        self.0
+    }
+}
+
+/// `Map` stores the key/value pairs contained in a verified proof, and keeps
+/// track of which of them are contiguous in the tree so that lookups can tell
+/// proven absences apart from data which was not included in the proof.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Map {
+    // each entry is marked `true` if no data was omitted between it and the
+    // preceding entry (or the left edge of the tree, for the first entry)
+    pub(crate) entries: BTreeMap<Vec<u8>, (bool, Vec<u8>)>,
+    // `true` if no data was omitted after the last entry
+    pub(crate) right_edge: bool,
+}
+
+impl Map {
+    /// Gets the value for a single key. Returns `Ok(None)` if the key is proven
+    /// to not exist in the tree, or an error if the proof does not include
+    /// enough data to know either way.
+    pub fn get<'a>(&'a self, key: &[u8]) -> Result<Option<&'a [u8]>> {
+        if let Some((_, value)) = self.entries.get(key) {
+            return Ok(Some(value.as_slice()));
+        }
+
+        let next = self
+            .entries
+            .range::<[u8], _>((Bound::Excluded(key), Bound::Unbounded))
+            .next();
+        match next {
+            // the following entry has no gap before it
+            Some((_, (true, _))) => Ok(None),
+            // the key is past the last entry, which is at the right edge
+            None if self.right_edge => Ok(None),
+            _ => bail!("Proof is missing data for query"),
+        }
+    }
+
+    /// Returns all the key/value pairs within `bounds`, in key-order. Returns
+    /// an error if the proof does not include enough data to prove there are
+    /// no other keys within the range.
+    pub fn range<'a, R>(&self, bounds: R) -> Result<Vec<(Vec<u8>, Vec<u8>)>>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        let bounds = (bounds.start_bound().cloned(), bounds.end_bound().cloned());
+        let mut output = vec![];
+        let mut first = true;
+
+        let entries = self.entries.range::<[u8], _>((bounds.0, Bound::Unbounded));
+        for (key, (contiguous, value)) in entries {
+            // an entry exactly at an inclusive start bound doesn't need to
+            // prove anything about the keys before it
+            let exact_start = first && bounds.0 == Bound::Included(key.as_slice());
+            first = false;
+
+            if !contiguous && !exact_start {
+                bail!("Proof is missing data for query");
+            }
+
+            if !RangeBounds::<[u8]>::contains(&bounds, key.as_slice()) {
+                // first entry past the end of the range, with no gap before it
+                return Ok(output);
+            }
+
+            output.push((key.clone(), value.clone()));
+
+            if bounds.1 == Bound::Included(key.as_slice()) {
+                return Ok(output);
+            }
+        }
+
+        if !self.right_edge {
+            bail!("Proof is missing data for query");
+        }
+
+        Ok(output)
+    }
+
+    /// Returns all the key/value pairs selected by `item`, in key-order. See
+    /// `Map::range`.
+    pub fn query_item(&self, item: &QueryItem) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+        self.range(item.bounds())
+    }
+
+    /// Returns the sub-ranges of `item` which are proven to contain no keys,
+    /// i.e. the gaps between consecutive results of the item (and between its
+    /// bounds and its first and last results), in key-order. Empty sub-ranges
+    /// (e.g. before a result exactly at the item's start) are omitted.
+    ///
+    /// Returns an error if the proof does not include enough data to prove the
+    /// item's results are complete, as in `Map::query_item`.
+    pub fn gaps(&self, item: &QueryItem) -> Result<Vec<(Bound<Vec<u8>>, Bound<Vec<u8>>)>> {
+        let (start, end) = item.bounds();
+        let results = self.query_item(item)?;
+
+        let mut gaps = vec![];
+        let mut push_gap = |lower: Bound<Vec<u8>>, upper: Bound<Vec<u8>>| {
+            let empty = match (&lower, &upper) {
+                (Bound::Included(a), Bound::Excluded(b)) => a >= b,
+                (Bound::Excluded(a), Bound::Included(b)) => a >= b,
+                (Bound::Excluded(a), Bound::Excluded(b)) => a >= b,
+                (Bound::Included(a), Bound::Included(b)) => a > b,
+                _ => false,
+            };
+            if !empty {
+                gaps.push((lower, upper));
+            }
+        };
+
+        let mut lower = owned_bound(start);
+        for (key, _) in results {
+            push_gap(lower, Bound::Excluded(key.clone()));
+            lower = Bound::Excluded(key);
+        }
+        push_gap(lower, owned_bound(end));
+
+        Ok(gaps)
+    }
+
+    /// Merges the entries of `other` (verified against the same root hash)
+    /// into this map. Errors if both maps contain a key with different
+    /// values.
+    ///
+    /// The gap before each entry of the merged map lies within a single gap of
+    /// each of the two maps, so it is proven empty if it is proven empty by
+    /// either of them.
+    pub(crate) fn merge(&mut self, other: Map) -> Result<()> {
+        // whether `map` proves there are no keys between `key` and the entry
+        // preceding it in the merged map
+        fn covers(map: &Map, key: &[u8]) -> bool {
+            let next = map
+                .entries
+                .range::<[u8], _>((Bound::Included(key), Bound::Unbounded))
+                .next();
+            match next {
+                Some((_, (contiguous, _))) => *contiguous,
+                None => map.right_edge,
+            }
+        }
+
+        for (key, (_, value)) in other.entries.iter() {
+            if let Some((_, existing)) = self.entries.get(key) {
+                if existing != value {
+                    bail!("Maps have different values for key {:?}", key);
+                }
+            }
+        }
+
+        let mut entries = BTreeMap::new();
+        for (key, value) in self.entries.iter().chain(other.entries.iter()) {
+            if entries.contains_key(key) {
+                continue;
+            }
+            let contiguous = covers(self, key) || covers(&other, key);
+            entries.insert(key.clone(), (contiguous, value.1.clone()));
+        }
+
+        self.entries = entries;
+        self.right_edge = self.right_edge || other.right_edge;
+        Ok(())
+    }
+
+    /// Returns the number of key/value pairs contained in the proof.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the proof contains no key/value pairs.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn owned_bound(bound: Bound<&[u8]>) -> Bound<Vec<u8>> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.to_vec()),
+        Bound::Excluded(key) => Bound::Excluded(key.to_vec()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tree::HASH_LENGTH;
+
+    fn build(nodes: Vec<Node>) -> Map {
+        let mut builder = MapBuilder::new();
+        for node in nodes.iter() {
+            builder.insert(node).unwrap();
+        }
+        builder.build()
+    }
+
+    fn kv(key: u8) -> Node {
+        Node::KV(vec![key], vec![key])
+    }
+
+    #[test]
+    fn unordered_insert() {
+        let mut builder = MapBuilder::new();
+        builder.insert(&kv(2)).unwrap();
+        assert!(builder.insert(&kv(1)).is_err());
+    }
+
+    #[test]
+    fn get() {
+        let map = build(vec![
+            Node::Hash([0; HASH_LENGTH]),
+            kv(2),
+            kv(4),
+            Node::KVHash([0; HASH_LENGTH]),
+            kv(6),
+        ]);
+
+        assert_eq!(map.get(&[2]).unwrap(), Some(&[2][..]));
+        assert_eq!(map.get(&[6]).unwrap(), Some(&[6][..]));
+        assert_eq!(map.get(&[3]).unwrap(), None);
+        assert_eq!(map.get(&[7]).unwrap(), None);
+        assert!(map.get(&[1]).is_err());
+        assert!(map.get(&[5]).is_err());
+    }
+
+    #[test]
+    fn range() {
+        let map = build(vec![
+            kv(1),
+            kv(2),
+            kv(4),
+            Node::Hash([0; HASH_LENGTH]),
+            kv(6),
+            kv(7),
+        ]);
+
+        let keys = |entries: Vec<(Vec<u8>, Vec<u8>)>| -> Vec<u8> {
+            entries.into_iter().map(|(key, _)| key[0]).collect()
+        };
+
+        assert_eq!(keys(map.range(&[0][..]..&[4][..]).unwrap()), vec![1, 2]);
+        assert_eq!(keys(map.range(&[0][..]..=&[4][..]).unwrap()), vec![1, 2, 4]);
+        assert_eq!(keys(map.range(&[6][..]..).unwrap()), vec![6, 7]);
+        assert_eq!(keys(map.range(&[3][..]..&[4][..]).unwrap()), vec![]);
+        assert!(map.range(..).is_err());
+        assert!(map.range(&[3][..]..&[6][..]).is_err());
+        assert!(map.range(&[5][..]..).is_err());
+
+        let item = QueryItem::RangeInclusive(vec![2]..=vec![4]);
+        assert_eq!(keys(map.query_item(&item).unwrap()), vec![2, 4]);
+        let item = QueryItem::Key(vec![3]);
+        assert_eq!(keys(map.query_item(&item).unwrap()), vec![]);
+    }
+
+    #[test]
+    fn gaps() {
+        let map = build(vec![
+            kv(1),
+            kv(3),
+            kv(4),
+            Node::Hash([0; HASH_LENGTH]),
+            kv(8),
+        ]);
+        let gap = |lower: Bound<u8>, upper: Bound<u8>| {
+            let lower = owned_bound(lower.as_ref().map(std::slice::from_ref));
+            let upper = owned_bound(upper.as_ref().map(std::slice::from_ref));
+            (lower, upper)
+        };
+
+        let item = QueryItem::Range(vec![0]..vec![4]);
+        let expected = vec![
+            gap(Bound::Included(0), Bound::Excluded(1)),
+            gap(Bound::Excluded(1), Bound::Excluded(3)),
+            gap(Bound::Excluded(3), Bound::Excluded(4)),
+        ];
+        assert_eq!(map.gaps(&item).unwrap(), expected);
+
+        // results exactly at the bounds leave no gap at the edges
+        let item = QueryItem::RangeInclusive(vec![1]..=vec![4]);
+        let expected = vec![
+            gap(Bound::Excluded(1), Bound::Excluded(3)),
+            gap(Bound::Excluded(3), Bound::Excluded(4)),
+        ];
+        assert_eq!(map.gaps(&item).unwrap(), expected);
+
+        let item = QueryItem::Key(vec![2]);
+        let expected = vec![gap(Bound::Included(2), Bound::Included(2))];
+        assert_eq!(map.gaps(&item).unwrap(), expected);
+        assert_eq!(map.gaps(&QueryItem::Key(vec![3])).unwrap(), vec![]);
+
+        let item = QueryItem::RangeFrom(vec![8]..);
+        let expected = vec![gap(Bound::Excluded(8), Bound::Unbounded)];
+        assert_eq!(map.gaps(&item).unwrap(), expected);
+
+        assert!(map.gaps(&QueryItem::Range(vec![3]..vec![8])).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let mut map = build(vec![kv(1), kv(2), Node::Hash([0; HASH_LENGTH]), kv(6)]);
+        let other = build(vec![
+            Node::Hash([0; HASH_LENGTH]),
+            kv(2),
+            kv(4),
+            Node::Hash([0; HASH_LENGTH]),
+        ]);
+        map.merge(other).unwrap();
+
+        assert_eq!(map.len(), 4);
+        assert_eq!(map.get(&[3]).unwrap(), None);
+        assert!(map.get(&[5]).is_err());
+        assert_eq!(map.get(&[7]).unwrap(), None);
+        assert_eq!(map.range(&[1][..]..=&[4][..]).unwrap().len(), 3);
+
+        let mut map = build(vec![kv(1)]);
+        let other = build(vec![Node::KV(vec![1], vec![2])]);
+        assert!(map.merge(other).is_err());
+    }
+
+    #[test]
+    fn range_right_edge() {
+        let map = build(vec![kv(1), kv(2), Node::Hash([0; HASH_LENGTH])]);
+        assert!(map.range(&[2][..]..).is_err());
+        assert_eq!(map.range(&[1][..]..=&[2][..]).unwrap().len(), 2);
+    }
+}
@@ -1,15 +1,29 @@
 pub mod chunk;
 mod encoding;
+mod history;
+mod map;
+mod partial;
 mod query;
 pub mod verify;
 
 use crate::tree::Hash;
 
 pub use encoding::{encode_into, Decoder};
-pub use verify::verify_query;
+pub(crate) use history::encode_history_value;
+pub use history::{decode_history_value, verify_history};
+pub use map::Map;
+pub use partial::{to_partial_merk, PartialMerk};
+pub use query::{Query, QueryItem};
+pub use verify::{
+    encode_versioned_into, results, root_hash, verified_iter, verify, verify_against_any,
+    verify_any_version, verify_filtered_range, verify_gaps, verify_key_projected, verify_nested,
+    verify_path_between, verify_query, verify_query_exact, verify_query_limited, verify_structured,
+    verify_uniform_range, ProofTree, ProvenPath, MIN_PROOF_VERSION, PROOF_VERSION,
+};
 
 /// A proof operator, executed to verify the data in a Merkle proof.
 #[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Op {
     /// Pushes a node on the stack.
     Push(Node),
@@ -28,6 +42,7 @@ pub enum Op {
 /// A selected piece of data about a single tree node, to be contained in a
 /// `Push` operator in a proof.
 #[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Node {
     /// Represents the hash of a tree node.
     Hash(Hash),
@@ -0,0 +1,182 @@
+use super::{encode_into, verify, Decoder, Map, Node, Op};
+use crate::error::Result;
+use crate::tree::{kv_hash, Hash};
+use failure::bail;
+use std::collections::{BTreeSet, HashMap};
+use std::ops::RangeBounds;
+
+/// `PartialMerk` is a read-only view of the part of a Merk tree contained in a
+/// verified proof, which can be queried offline like a cache of the store.
+/// Created with `to_partial_merk`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PartialMerk {
+    map: Map,
+    root_hash: Hash,
+}
+
+impl PartialMerk {
+    /// Gets the value for a key. Returns `Ok(None)` if the key is proven to
+    /// not exist in the tree, or an error if the key is outside the coverage
+    /// of the proof.
+    pub fn get<'a>(&'a self, key: &[u8]) -> Result<Option<&'a [u8]>> {
+        self.map.get(key)
+    }
+
+    /// Returns all the key/value pairs within `bounds`, in key-order. Returns
+    /// an error if the range is not fully covered by the proof.
+    pub fn range<'a, R>(&self, bounds: R) -> Result<Vec<(Vec<u8>, Vec<u8>)>>
+    where
+        R: RangeBounds<&'a [u8]>,
+    {
+        self.map.range(bounds)
+    }
+
+    /// Returns the root hash of the tree the proof was verified against.
+    pub fn root_hash(&self) -> Hash {
+        self.root_hash
+    }
+
+    /// Updates the view to `new_root` with a proof created by
+    /// `Merk::prove_update` for the same query as the proof this view was
+    /// created from. Returns the keys covered by the update which were added,
+    /// changed or removed, in key order.
+    ///
+    /// The update only contains hashes for the entries which are unchanged,
+    /// so they are filled back in from this view's entries before the proof
+    /// is verified against `new_root`. If verification fails, the view is left
+    /// unchanged.
+    pub fn apply_update(&mut self, bytes: &[u8], new_root: Hash) -> Result<Vec<Vec<u8>>> {
+        let held: HashMap<Hash, (&Vec<u8>, &Vec<u8>)> = self
+            .map
+            .entries
+            .iter()
+            .map(|(key, (_, value))| (kv_hash(key, value), (key, value)))
+            .collect();
+
+        let mut ops = vec![];
+        for op in Decoder::new(bytes) {
+            ops.push(match op? {
+                Op::Push(Node::KVHash(hash)) if held.contains_key(&hash) => {
+                    let (key, value) = held[&hash];
+                    Op::Push(Node::KV(key.clone(), value.clone()))
+                }
+                op => op,
+            });
+        }
+        let mut proof = Vec::with_capacity(bytes.len());
+        encode_into(ops.iter(), &mut proof);
+        let map = verify(&proof, new_root)?;
+
+        // a key changed if it is covered by the update and its value differs,
+        // counting proven absences as changes for keys which were held
+        let keys: BTreeSet<&Vec<u8>> = self.map.entries.keys().chain(map.entries.keys()).collect();
+        let mut changed = vec![];
+        for key in keys {
+            let new_value = match map.get(key) {
+                Err(_) => continue, // not covered by the update
+                Ok(value) => value,
+            };
+            if self.map.get(key).ok().flatten() != new_value {
+                changed.push(key.clone());
+            }
+        }
+
+        self.map = map;
+        self.root_hash = new_root;
+        Ok(changed)
+    }
+
+    /// Merges the data proven by `other` into this view, so it can answer
+    /// reads for any key covered by either of them. Both must have been
+    /// verified against the same root hash.
+    ///
+    /// Entries contained in both are only kept once, and ranges proven by the
+    /// two views together (e.g. two overlapping range proofs) can be read from
+    /// the merged view. Errors if the root hashes differ.
+    pub fn merge(&mut self, other: PartialMerk) -> Result<()> {
+        if other.root_hash != self.root_hash {
+            bail!("Cannot merge partial views with different root hashes");
+        }
+        self.map.merge(other.map)
+    }
+
+    /// Returns the underlying map of verified key/value pairs.
+    pub fn map(&self) -> &Map {
+        &self.map
+    }
+}
+
+/// Verifies the encoded proof against the expected hash and materializes the
+/// proven data into a `PartialMerk`, which serves reads for the keys the proof
+/// covers.
+pub fn to_partial_merk(bytes: &[u8], expected_hash: Hash) -> Result<PartialMerk> {
+    let map = verify(bytes, expected_hash)?;
+    Ok(PartialMerk {
+        map,
+        root_hash: expected_hash,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proofs::Query;
+    use crate::test_utils::*;
+    use crate::tree::HASH_LENGTH;
+
+    #[test]
+    fn partial_merk_reads() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(20)..seq_key(30));
+        let proof = merk.prove_query(&query).unwrap();
+
+        let partial = to_partial_merk(&proof, merk.root_hash()).unwrap();
+        assert_eq!(partial.root_hash(), merk.root_hash());
+        assert_eq!(partial.get(&seq_key(25)).unwrap(), Some(&[123; 60][..]));
+        assert_eq!(partial.get(&[0, 0, 0, 0, 0, 0, 0, 25, 1]).unwrap(), None);
+        assert!(partial.get(&seq_key(50)).is_err());
+
+        let entries = partial
+            .range(seq_key(22).as_slice()..seq_key(24).as_slice())
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(partial.range(seq_key(25).as_slice()..).is_err());
+
+        assert!(to_partial_merk(&proof, [0; HASH_LENGTH]).is_err());
+    }
+
+    #[test]
+    fn merge_partials() {
+        let mut merk = TempMerk::new().unwrap();
+        merk.apply(&make_batch_seq(0..100), &[]).unwrap();
+        let root_hash = merk.root_hash();
+
+        let partial = |start: u64, end: u64| {
+            let mut query = Query::new();
+            query.insert_range(seq_key(start)..seq_key(end));
+            to_partial_merk(&merk.prove_query(&query).unwrap(), root_hash).unwrap()
+        };
+
+        let mut merged = partial(20, 30);
+        merged.merge(partial(50, 60)).unwrap();
+        assert_eq!(merged.get(&seq_key(25)).unwrap(), Some(&[123; 60][..]));
+        assert_eq!(merged.get(&seq_key(55)).unwrap(), Some(&[123; 60][..]));
+        assert!(merged.get(&seq_key(40)).is_err());
+
+        // overlapping ranges together cover their union
+        let range = seq_key(20).as_slice()..seq_key(40).as_slice();
+        let mut merged = partial(20, 30);
+        assert!(merged.range(range.clone()).is_err());
+        merged.merge(partial(25, 40)).unwrap();
+        assert_eq!(merged.range(range).unwrap().len(), 20);
+
+        let mut other = TempMerk::new().unwrap();
+        other.apply(&make_batch_seq(0..10), &[]).unwrap();
+        let proof = other.prove(&[seq_key(5)]).unwrap();
+        let other = to_partial_merk(&proof, other.root_hash()).unwrap();
+        assert!(merged.merge(other).is_err());
+    }
+}
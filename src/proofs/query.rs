@@ -2,6 +2,177 @@ use super::{Node, Op};
 use crate::error::Result;
 use crate::tree::{Fetch, Link, RefWalker};
 use std::collections::LinkedList;
+use std::ops::{Bound, Range, RangeFrom, RangeInclusive};
+
+/// A set of keys and key ranges to be proven. Items are kept sorted, and
+/// overlapping items are merged as they are inserted.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Query {
+    items: Vec<QueryItem>,
+}
+
+impl Query {
+    /// Creates an empty `Query`.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Adds a single key to the query.
+    pub fn insert_key(&mut self, key: Vec<u8>) {
+        self.insert_item(QueryItem::Key(key));
+    }
+
+    /// Adds a range of keys (excluding the end key) to the query.
+    pub fn insert_range(&mut self, range: Range<Vec<u8>>) {
+        self.insert_item(QueryItem::Range(range));
+    }
+
+    /// Adds a range of keys (including the end key) to the query.
+    pub fn insert_range_inclusive(&mut self, range: RangeInclusive<Vec<u8>>) {
+        self.insert_item(QueryItem::RangeInclusive(range));
+    }
+
+    /// Adds all the keys greater than or equal to the start key to the query.
+    pub fn insert_range_from(&mut self, range: RangeFrom<Vec<u8>>) {
+        self.insert_item(QueryItem::RangeFrom(range));
+    }
+
+    /// Adds an item to the query, merging it with any items it overlaps.
+    pub fn insert_item(&mut self, mut item: QueryItem) {
+        // items before `start` are entirely less than the new item, following
+        // items are merged into it until we reach one which does not overlap
+        let start = self
+            .items
+            .partition_point(|other| other.is_below(item.lower_bound()));
+        let mut end = start;
+        while end < self.items.len() && !item.is_below(self.items[end].lower_bound()) {
+            item = item.merge(&self.items[end]);
+            end += 1;
+        }
+
+        self.items.drain(start..end);
+        self.items.insert(start, item);
+    }
+
+    /// Returns the sorted, non-overlapping items of the query.
+    pub fn items(&self) -> &[QueryItem] {
+        self.items.as_slice()
+    }
+
+    /// Returns the number of items in the query.
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    /// Returns `true` if the query has no items.
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+}
+
+/// A single item of a `Query`, selecting either one key or a contiguous range
+/// of keys.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum QueryItem {
+    Key(Vec<u8>),
+    Range(Range<Vec<u8>>),
+    RangeInclusive(RangeInclusive<Vec<u8>>),
+    RangeFrom(RangeFrom<Vec<u8>>),
+}
+
+impl QueryItem {
+    /// Creates a `QueryItem` selecting every key which starts with `prefix`.
+    pub fn prefix(prefix: &[u8]) -> QueryItem {
+        // the first key after the prefix range is the prefix with its last
+        // non-0xff byte incremented, and every byte after that removed
+        let mut end = prefix.to_vec();
+        while let Some(last) = end.pop() {
+            if last < u8::MAX {
+                end.push(last + 1);
+                return QueryItem::Range(prefix.to_vec()..end);
+            }
+        }
+
+        QueryItem::RangeFrom(prefix.to_vec()..)
+    }
+
+    /// Returns the lowest key selected by the item (always inclusive).
+    pub fn lower_bound(&self) -> &[u8] {
+        match self {
+            QueryItem::Key(key) => key.as_slice(),
+            QueryItem::Range(range) => range.start.as_slice(),
+            QueryItem::RangeInclusive(range) => range.start().as_slice(),
+            QueryItem::RangeFrom(range) => range.start.as_slice(),
+        }
+    }
+
+    /// Returns the end key of the item and whether or not it is inclusive, or
+    /// `None` if the item is unbounded.
+    pub fn upper_bound(&self) -> Option<(&[u8], bool)> {
+        match self {
+            QueryItem::Key(key) => Some((key.as_slice(), true)),
+            QueryItem::Range(range) => Some((range.end.as_slice(), false)),
+            QueryItem::RangeInclusive(range) => Some((range.end().as_slice(), true)),
+            QueryItem::RangeFrom(_) => None,
+        }
+    }
+
+    /// Returns the bounds of the item, e.g. to look up its results in a
+    /// proven `Map`.
+    pub fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        let end = match self.upper_bound() {
+            None => Bound::Unbounded,
+            Some((end, true)) => Bound::Included(end),
+            Some((end, false)) => Bound::Excluded(end),
+        };
+        (Bound::Included(self.lower_bound()), end)
+    }
+
+    /// Returns `true` if `key` is selected by the item.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        key >= self.lower_bound() && !self.is_below(key)
+    }
+
+    /// Returns `true` if every key selected by the item is less than `key`.
+    pub(crate) fn is_below(&self, key: &[u8]) -> bool {
+        match self.upper_bound() {
+            None => false,
+            Some((end, true)) => end < key,
+            Some((end, false)) => end <= key,
+        }
+    }
+
+    /// Returns `true` if the item may select keys greater than `key`.
+    pub(crate) fn extends_above(&self, key: &[u8]) -> bool {
+        match self.upper_bound() {
+            None => true,
+            Some((end, _)) => end > key,
+        }
+    }
+
+    /// Creates an item covering both `self` and `other`. The two items are
+    /// expected to overlap.
+    fn merge(&self, other: &QueryItem) -> QueryItem {
+        let start = self.lower_bound().min(other.lower_bound()).to_vec();
+        let end = match (self.upper_bound(), other.upper_bound()) {
+            (None, _) | (_, None) => None,
+            (Some((a, a_inclusive)), Some((b, b_inclusive))) => Some(if a == b {
+                (a, a_inclusive || b_inclusive)
+            } else if a > b {
+                (a, a_inclusive)
+            } else {
+                (b, b_inclusive)
+            }),
+        };
+
+        match end {
+            None => QueryItem::RangeFrom(start..),
+            Some((end, true)) if end == start.as_slice() => QueryItem::Key(start),
+            Some((end, true)) => QueryItem::RangeInclusive(start..=end.to_vec()),
+            Some((end, false)) => QueryItem::Range(start..end.to_vec()),
+        }
+    }
+}
 
 impl Link {
     /// Creates a `Node::Hash` from this link. Panics if the link is of variant
@@ -47,27 +218,58 @@ where
         &mut self,
         keys: &[Vec<u8>],
     ) -> Result<(LinkedList<Op>, (bool, bool))> {
-        let search = keys.binary_search_by(|key| key.as_slice().cmp(self.tree().key()));
+        let query: Vec<QueryItem> = keys.iter().cloned().map(QueryItem::Key).collect();
+        self.create_query_proof(query.as_slice())
+    }
 
-        let (left_keys, right_keys) = match search {
-            Ok(index) => (&keys[..index], &keys[index + 1..]),
-            Err(index) => (&keys[..index], &keys[index..]),
+    /// Generates a proof for the sorted, non-overlapping query items. Every
+    /// node selected by an item is included as a `Node::KV`, as well as the
+    /// nodes bounding each item so that the absence of any other keys can be
+    /// proven. Returns a tuple containing the generated proof operators, and a
+    /// tuple representing if any queried items extended past the left edge or
+    /// the right edge, respectively.
+    ///
+    /// Proofs are already as compact as they can soundly be: every other node
+    /// on the traversed paths is included as a `Node::KVHash`, omitting its key
+    /// and value. The keys of the bounding nodes can not be omitted, since the
+    /// verifier needs them to check that no other keys exist in each item.
+    pub(crate) fn create_query_proof(
+        &mut self,
+        query: &[QueryItem],
+    ) -> Result<(LinkedList<Op>, (bool, bool))> {
+        let (found, left_items, right_items) = {
+            let key = self.tree().key();
+
+            // first item which is not entirely to the left of this node's key
+            let index = query.partition_point(|item| item.is_below(key));
+            match query.get(index) {
+                Some(item) if item.contains(key) => {
+                    // the matching item might also continue into either child
+                    let left_end = if item.lower_bound() < key {
+                        index + 1
+                    } else {
+                        index
+                    };
+                    let right_start = if item.extends_above(key) {
+                        index
+                    } else {
+                        index + 1
+                    };
+                    (true, &query[..left_end], &query[right_start..])
+                }
+                _ => (false, &query[..index], &query[index..]),
+            }
         };
 
-        let (mut proof, left_absence) = self.create_child_proof(true, left_keys)?;
-        let (mut right_proof, right_absence) = self.create_child_proof(false, right_keys)?;
+        let (mut proof, left_absence) = self.create_child_proof(true, left_items)?;
+        let (mut right_proof, right_absence) = self.create_child_proof(false, right_items)?;
 
         let (has_left, has_right) = (!proof.is_empty(), !right_proof.is_empty());
 
-        proof.push_back(match search {
-            Ok(_) => Op::Push(self.to_kv_node()),
-            Err(_) => {
-                if left_absence.1 || right_absence.0 {
-                    Op::Push(self.to_kv_node())
-                } else {
-                    Op::Push(self.to_kvhash_node())
-                }
-            }
+        proof.push_back(if found || left_absence.1 || right_absence.0 {
+            Op::Push(self.to_kv_node())
+        } else {
+            Op::Push(self.to_kvhash_node())
         });
 
         if has_left {
@@ -82,16 +284,16 @@ where
         Ok((proof, (left_absence.0, right_absence.1)))
     }
 
-    /// Similar to `create_proof`. Recurses into the child on the given side and
-    /// generates a proof for the queried keys.
+    /// Similar to `create_query_proof`. Recurses into the child on the given
+    /// side and generates a proof for the queried items.
     fn create_child_proof(
         &mut self,
         left: bool,
-        keys: &[Vec<u8>],
+        query: &[QueryItem],
     ) -> Result<(LinkedList<Op>, (bool, bool))> {
-        Ok(if !keys.is_empty() {
+        Ok(if !query.is_empty() {
             if let Some(mut child) = self.walk(left)? {
-                child.create_proof(keys)?
+                child.create_query_proof(query)?
             } else {
                 (LinkedList::new(), (true, true))
             }
@@ -109,22 +311,22 @@ where
 mod test {
     use super::super::encoding::encode_into;
     use super::*;
-    use crate::tree::{PanicSource, RefWalker, Tree};
+    use crate::tree::{PanicSource, RefWalker, Tree, HASH_LENGTH};
 
     fn make_3_node_tree() -> Tree {
         Tree::from_fields(
             vec![5],
             vec![5],
-            [105; 20],
+            [105; HASH_LENGTH],
             Some(Link::Loaded {
                 child_heights: (0, 0),
-                hash: [3; 20],
-                tree: Tree::from_fields(vec![3], vec![3], [103; 20], None, None),
+                hash: [3; HASH_LENGTH],
+                tree: Tree::from_fields(vec![3], vec![3], [103; HASH_LENGTH], None, None),
             }),
             Some(Link::Loaded {
                 child_heights: (0, 0),
-                hash: [7; 20],
-                tree: Tree::from_fields(vec![7], vec![7], [107; 20], None, None),
+                hash: [7; HASH_LENGTH],
+                tree: Tree::from_fields(vec![7], vec![7], [107; HASH_LENGTH], None, None),
             }),
         )
     }
@@ -139,10 +341,13 @@ mod test {
             .expect("create_proof errored");
 
         let mut iter = proof.iter();
-        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([3; 20]))));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::KVHash([105; 20]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([3; HASH_LENGTH]))));
+        assert_eq!(
+            iter.next(),
+            Some(&Op::Push(Node::KVHash([105; HASH_LENGTH])))
+        );
         assert_eq!(iter.next(), Some(&Op::Parent));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([7; 20]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([7; HASH_LENGTH]))));
         assert_eq!(iter.next(), Some(&Op::Child));
         assert!(iter.next().is_none());
         assert_eq!(absence, (false, false));
@@ -158,10 +363,10 @@ mod test {
             .expect("create_proof errored");
 
         let mut iter = proof.iter();
-        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([3; 20]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([3; HASH_LENGTH]))));
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![5], vec![5]))));
         assert_eq!(iter.next(), Some(&Op::Parent));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([7; 20]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([7; HASH_LENGTH]))));
         assert_eq!(iter.next(), Some(&Op::Child));
         assert!(iter.next().is_none());
         assert_eq!(absence, (false, false));
@@ -178,9 +383,12 @@ mod test {
 
         let mut iter = proof.iter();
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![3], vec![3]))));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::KVHash([105; 20]))));
+        assert_eq!(
+            iter.next(),
+            Some(&Op::Push(Node::KVHash([105; HASH_LENGTH])))
+        );
         assert_eq!(iter.next(), Some(&Op::Parent));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([7; 20]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([7; HASH_LENGTH]))));
         assert_eq!(iter.next(), Some(&Op::Child));
         assert!(iter.next().is_none());
         assert_eq!(absence, (false, false));
@@ -197,7 +405,10 @@ mod test {
 
         let mut iter = proof.iter();
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![3], vec![3]))));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::KVHash([105; 20]))));
+        assert_eq!(
+            iter.next(),
+            Some(&Op::Push(Node::KVHash([105; HASH_LENGTH])))
+        );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![7], vec![7]))));
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -234,8 +445,11 @@ mod test {
             .expect("create_proof errored");
 
         let mut iter = proof.iter();
-        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([3; 20]))));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::KVHash([105; 20]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([3; HASH_LENGTH]))));
+        assert_eq!(
+            iter.next(),
+            Some(&Op::Push(Node::KVHash([105; HASH_LENGTH])))
+        );
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![7], vec![7]))));
         assert_eq!(iter.next(), Some(&Op::Child));
@@ -253,7 +467,7 @@ mod test {
             .expect("create_proof errored");
 
         let mut iter = proof.iter();
-        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([3; 20]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([3; HASH_LENGTH]))));
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![5], vec![5]))));
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![7], vec![7]))));
@@ -267,30 +481,36 @@ mod test {
         let mut tree = Tree::from_fields(
             vec![5],
             vec![5],
-            [105; 20],
+            [105; HASH_LENGTH],
             Some(Link::Loaded {
                 child_heights: (0, 0),
-                hash: [2; 20],
+                hash: [2; HASH_LENGTH],
                 tree: Tree::from_fields(
                     vec![2],
                     vec![2],
-                    [102; 20],
+                    [102; HASH_LENGTH],
                     Some(Link::Loaded {
                         child_heights: (0, 0),
-                        hash: [1; 20],
-                        tree: Tree::from_fields(vec![1], vec![1], [101; 20], None, None),
+                        hash: [1; HASH_LENGTH],
+                        tree: Tree::from_fields(vec![1], vec![1], [101; HASH_LENGTH], None, None),
                     }),
                     Some(Link::Loaded {
                         child_heights: (0, 0),
-                        hash: [4; 20],
+                        hash: [4; HASH_LENGTH],
                         tree: Tree::from_fields(
                             vec![4],
                             vec![4],
-                            [104; 20],
+                            [104; HASH_LENGTH],
                             Some(Link::Loaded {
                                 child_heights: (0, 0),
-                                hash: [3; 20],
-                                tree: Tree::from_fields(vec![3], vec![3], [103; 20], None, None),
+                                hash: [3; HASH_LENGTH],
+                                tree: Tree::from_fields(
+                                    vec![3],
+                                    vec![3],
+                                    [103; HASH_LENGTH],
+                                    None,
+                                    None,
+                                ),
                             }),
                             None,
                         ),
@@ -299,41 +519,59 @@ mod test {
             }),
             Some(Link::Loaded {
                 child_heights: (0, 0),
-                hash: [9; 20],
+                hash: [9; HASH_LENGTH],
                 tree: Tree::from_fields(
                     vec![9],
                     vec![9],
-                    [109; 20],
+                    [109; HASH_LENGTH],
                     Some(Link::Loaded {
                         child_heights: (0, 0),
-                        hash: [7; 20],
+                        hash: [7; HASH_LENGTH],
                         tree: Tree::from_fields(
                             vec![7],
                             vec![7],
-                            [107; 20],
+                            [107; HASH_LENGTH],
                             Some(Link::Loaded {
                                 child_heights: (0, 0),
-                                hash: [6; 20],
-                                tree: Tree::from_fields(vec![6], vec![6], [106; 20], None, None),
+                                hash: [6; HASH_LENGTH],
+                                tree: Tree::from_fields(
+                                    vec![6],
+                                    vec![6],
+                                    [106; HASH_LENGTH],
+                                    None,
+                                    None,
+                                ),
                             }),
                             Some(Link::Loaded {
                                 child_heights: (0, 0),
-                                hash: [8; 20],
-                                tree: Tree::from_fields(vec![8], vec![8], [108; 20], None, None),
+                                hash: [8; HASH_LENGTH],
+                                tree: Tree::from_fields(
+                                    vec![8],
+                                    vec![8],
+                                    [108; HASH_LENGTH],
+                                    None,
+                                    None,
+                                ),
                             }),
                         ),
                     }),
                     Some(Link::Loaded {
                         child_heights: (0, 0),
-                        hash: [11; 20],
+                        hash: [11; HASH_LENGTH],
                         tree: Tree::from_fields(
                             vec![11],
                             vec![11],
-                            [111; 20],
+                            [111; HASH_LENGTH],
                             Some(Link::Loaded {
                                 child_heights: (0, 0),
-                                hash: [10; 20],
-                                tree: Tree::from_fields(vec![10], vec![10], [110; 20], None, None),
+                                hash: [10; HASH_LENGTH],
+                                tree: Tree::from_fields(
+                                    vec![10],
+                                    vec![10],
+                                    [110; HASH_LENGTH],
+                                    None,
+                                    None,
+                                ),
                             }),
                             None,
                         ),
@@ -355,23 +593,124 @@ mod test {
         assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![4], vec![4]))));
         assert_eq!(iter.next(), Some(&Op::Parent));
         assert_eq!(iter.next(), Some(&Op::Child));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::KVHash([105; 20]))));
+        assert_eq!(
+            iter.next(),
+            Some(&Op::Push(Node::KVHash([105; HASH_LENGTH])))
+        );
         assert_eq!(iter.next(), Some(&Op::Parent));
-        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([9; 20]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([9; HASH_LENGTH]))));
         assert_eq!(iter.next(), Some(&Op::Child));
         assert!(iter.next().is_none());
         assert_eq!(absence, (false, false));
 
+        #[cfg(not(feature = "hash256"))]
+        let expected = vec![
+            3, 1, 1, 0, 1, 1, 3, 1, 2, 0, 1, 2, 16, 3, 1, 3, 0, 1, 3, 3, 1, 4, 0, 1, 4, 16, 17, 2,
+            105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105,
+            105, 105, 105, 16, 1, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 17,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = vec![
+            3, 1, 1, 0, 1, 1, 3, 1, 2, 0, 1, 2, 16, 3, 1, 3, 0, 1, 3, 3, 1, 4, 0, 1, 4, 16, 17, 2,
+            105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105,
+            105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 16, 1, 9, 9,
+            9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9,
+            9, 17,
+        ];
         let mut bytes = vec![];
         encode_into(proof.iter(), &mut bytes);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn range_proof() {
+        let mut tree = make_3_node_tree();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let (proof, absence) = walker
+            .create_query_proof(vec![QueryItem::Range(vec![4]..vec![8])].as_slice())
+            .expect("create_proof errored");
+
+        let mut iter = proof.iter();
+        assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![3], vec![3]))));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![5], vec![5]))));
+        assert_eq!(iter.next(), Some(&Op::Parent));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![7], vec![7]))));
+        assert_eq!(iter.next(), Some(&Op::Child));
+        assert!(iter.next().is_none());
+        assert_eq!(absence, (false, true));
+    }
+
+    #[test]
+    fn range_proof_inner() {
+        let mut tree = make_3_node_tree();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let (proof, absence) = walker
+            .create_query_proof(vec![QueryItem::RangeInclusive(vec![1]..=vec![3])].as_slice())
+            .expect("create_proof errored");
+
+        let mut iter = proof.iter();
+        assert_eq!(iter.next(), Some(&Op::Push(Node::KV(vec![3], vec![3]))));
         assert_eq!(
-            bytes,
-            vec![
-                3, 1, 1, 0, 1, 1, 3, 1, 2, 0, 1, 2, 16, 3, 1, 3, 0, 1, 3, 3, 1, 4, 0, 1, 4, 16, 17,
-                2, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105, 105,
-                105, 105, 105, 105, 16, 1, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9, 9,
-                9, 17
+            iter.next(),
+            Some(&Op::Push(Node::KVHash([105; HASH_LENGTH])))
+        );
+        assert_eq!(iter.next(), Some(&Op::Parent));
+        assert_eq!(iter.next(), Some(&Op::Push(Node::Hash([7; HASH_LENGTH]))));
+        assert_eq!(iter.next(), Some(&Op::Child));
+        assert!(iter.next().is_none());
+        assert_eq!(absence, (true, false));
+    }
+
+    #[test]
+    fn query_merges_items() {
+        let mut query = Query::new();
+        query.insert_key(vec![5]);
+        query.insert_range(vec![1]..vec![3]);
+        query.insert_range_inclusive(vec![2]..=vec![4]);
+        query.insert_key(vec![4]);
+        assert_eq!(
+            query.items(),
+            &[
+                QueryItem::RangeInclusive(vec![1]..=vec![4]),
+                QueryItem::Key(vec![5])
+            ]
+        );
+
+        query.insert_range_from(vec![5]..);
+        query.insert_key(vec![0]);
+        assert_eq!(
+            query.items(),
+            &[
+                QueryItem::Key(vec![0]),
+                QueryItem::RangeInclusive(vec![1]..=vec![4]),
+                QueryItem::RangeFrom(vec![5]..)
             ]
         );
+        assert_eq!(query.len(), 3);
+    }
+
+    #[test]
+    fn prefix_item() {
+        assert_eq!(
+            QueryItem::prefix(&[1, 2]),
+            QueryItem::Range(vec![1, 2]..vec![1, 3])
+        );
+        assert_eq!(
+            QueryItem::prefix(&[1, 255]),
+            QueryItem::Range(vec![1, 255]..vec![2])
+        );
+        assert_eq!(
+            QueryItem::prefix(&[255, 255]),
+            QueryItem::RangeFrom(vec![255, 255]..)
+        );
+        assert_eq!(QueryItem::prefix(&[]), QueryItem::RangeFrom(vec![]..));
+
+        let item = QueryItem::prefix(&[1]);
+        assert!(item.contains(&[1]));
+        assert!(item.contains(&[1, 255, 255]));
+        assert!(!item.contains(&[0, 255]));
+        assert!(!item.contains(&[2]));
     }
 }
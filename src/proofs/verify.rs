@@ -1,7 +1,10 @@
-use super::{Decoder, Node, Op};
+use super::map::{Map, MapBuilder};
+use super::{encode_into, Decoder, Node, Op, Query, QueryItem};
 use crate::error::Result;
-use crate::tree::{kv_hash, node_hash, Hash, NULL_HASH};
+use crate::tree::{kv_hash, node_hash, Hash, HASH_LENGTH, NULL_HASH};
+use byteorder::{BigEndian, ByteOrder};
 use failure::bail;
+use std::ops::{Bound, Range};
 
 /// Contains a tree's child node and its hash. The hash can always be assumed to
 /// be up-to-date.
@@ -217,6 +220,66 @@ impl<'a> Iterator for LayerIter<'a> {
     }
 }
 
+/// The full structure of a decoded proof, as a binary tree of the nodes it
+/// contains. With the `serde` feature enabled this can be serialized (e.g. to
+/// JSON) for inspecting proofs, while the wire format stays the encoded
+/// operators.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ProofTree {
+    pub node: Node,
+    pub left: Option<Box<ProofTree>>,
+    pub right: Option<Box<ProofTree>>,
+}
+
+impl ProofTree {
+    /// Decodes and executes an encoded proof, returning its structure. This
+    /// does not check the proof against any root hash, see `verify` for that.
+    pub fn decode(bytes: &[u8]) -> Result<Self> {
+        let tree = execute(Decoder::new(bytes), false, |_| Ok(()))?;
+        Ok(tree.into())
+    }
+
+    /// Computes the root hash of the proof.
+    pub fn hash(&self) -> Hash {
+        let child_hash =
+            |child: &Option<Box<ProofTree>>| child.as_ref().map_or(NULL_HASH, |c| c.hash());
+        let kv_hash = match &self.node {
+            Node::Hash(hash) => return *hash,
+            Node::KVHash(kv_hash) => *kv_hash,
+            Node::KV(key, value) => kv_hash(key.as_slice(), value.as_slice()),
+        };
+        node_hash(&kv_hash, &child_hash(&self.left), &child_hash(&self.right))
+    }
+
+    /// Encodes the proof back into its operators, appending them to `output`.
+    pub fn encode_into(&self, output: &mut Vec<u8>) {
+        if let Some(left) = &self.left {
+            left.encode_into(output);
+            Op::Push(self.node.clone()).encode_into(output).unwrap();
+            Op::Parent.encode_into(output).unwrap();
+        } else {
+            Op::Push(self.node.clone()).encode_into(output).unwrap();
+        }
+
+        if let Some(right) = &self.right {
+            right.encode_into(output);
+            Op::Child.encode_into(output).unwrap();
+        }
+    }
+}
+
+impl From<Tree> for ProofTree {
+    fn from(tree: Tree) -> Self {
+        let child = |child: Option<Child>| child.map(|c| Box::new((*c.tree).into()));
+        ProofTree {
+            node: tree.node,
+            left: child(tree.left),
+            right: child(tree.right),
+        }
+    }
+}
+
 /// Executes a proof by stepping through its operators, modifying the
 /// verification stack as it goes. The resulting stack item is returned.
 ///
@@ -283,6 +346,476 @@ where
     Ok(stack.pop().unwrap())
 }
 
+/// Verifies the encoded proof against the expected hash, returning a `Map` of
+/// the key/value pairs it contains.
+///
+/// The `Map` can then be used to look up the results for the queried keys and
+/// ranges, erroring for any lookups which the proof does not contain enough
+/// data to answer.
+///
+/// The empty tree has no nodes to include in a proof, so an empty proof is
+/// accepted against `NULL_HASH`, proving the absence of every key.
+pub fn verify(bytes: &[u8], expected_hash: Hash) -> Result<Map> {
+    if bytes.is_empty() && expected_hash == NULL_HASH {
+        return Ok(MapBuilder::new().build());
+    }
+
+    let ops = Decoder::new(bytes);
+    let mut map_builder = MapBuilder::new();
+
+    let root = execute(ops, true, |node| map_builder.insert(node))?;
+
+    if root.hash() != expected_hash {
+        bail!(
+            "Proof did not match expected hash\n\tExpected: {:?}\n\tActual: {:?}",
+            expected_hash,
+            root.hash()
+        );
+    }
+
+    Ok(map_builder.build())
+}
+
+/// The newest proof format version, written by `encode_versioned_into`.
+///
+/// Version 0 proofs are the bare sequence of encoded operators, with no
+/// version byte, and every operator variant byte is below `0x80`. Version 1
+/// proofs start with the byte `0x80 | version`, followed by the same
+/// operators, so the version of any proof can be read from its first byte.
+pub const PROOF_VERSION: u8 = 1;
+
+/// The oldest proof format version accepted by `verify_any_version`.
+///
+/// During a migration to a new format, this stays at the previous version
+/// (keeping its decoder path available) until every producer has upgraded,
+/// so old and new proofs are both accepted.
+pub const MIN_PROOF_VERSION: u8 = 0;
+
+/// Returns the format version of an encoded proof, as read from its first
+/// byte (see `PROOF_VERSION`).
+fn proof_version(bytes: &[u8]) -> u8 {
+    match bytes.first() {
+        Some(byte) if byte & 0x80 != 0 => byte & 0x7f,
+        _ => 0,
+    }
+}
+
+/// Encodes the proof operators in `ops` into `output` in the newest format
+/// (see `PROOF_VERSION`), prefixed by its version byte.
+pub fn encode_versioned_into<'a, T: Iterator<Item = &'a Op>>(ops: T, output: &mut Vec<u8>) {
+    output.push(0x80 | PROOF_VERSION);
+    encode_into(ops, output);
+}
+
+/// Verifies an encoded proof of any format version from `MIN_PROOF_VERSION`
+/// to `PROOF_VERSION` against the expected hash, like `verify`, dispatching
+/// on the version read from the proof. Errors for proofs with versions
+/// outside of that window.
+///
+/// This currently accepts both unprefixed version 0 proofs (as returned by
+/// `Merk::prove`) and version 1 proofs.
+pub fn verify_any_version(bytes: &[u8], expected_hash: Hash) -> Result<Map> {
+    let version = proof_version(bytes);
+    if !(MIN_PROOF_VERSION..=PROOF_VERSION).contains(&version) {
+        bail!(
+            "Unsupported proof version {}, expected versions {} to {}",
+            version,
+            MIN_PROOF_VERSION,
+            PROOF_VERSION
+        );
+    }
+
+    match version {
+        0 => verify(bytes, expected_hash),
+        1 => verify(&bytes[1..], expected_hash),
+        _ => bail!("Unsupported proof version {}", version),
+    }
+}
+
+/// Verifies a nested proof (as created by `Merk::prove_nested`) against the
+/// parent tree's expected hash, returning the value of `child_key` within the
+/// child tree stored at `parent_key`, or `None` if it is proven to be absent.
+///
+/// Errors if the parent proof does not contain `parent_key`, if its value is
+/// not a root hash, or if the child proof does not match that root hash.
+pub fn verify_nested(
+    bytes: &[u8],
+    parent_key: &[u8],
+    child_key: &[u8],
+    expected_hash: Hash,
+) -> Result<Option<Vec<u8>>> {
+    let (parent_proof, child_proof) = split_nested_proof(bytes)?;
+
+    let child_root = match verify_query(parent_proof, &[parent_key.to_vec()], expected_hash)?
+        .pop()
+        .unwrap()
+    {
+        None => bail!("Parent key is not present in parent tree"),
+        Some(value) => child_root_from_value(value.as_slice())?,
+    };
+
+    if child_root == NULL_HASH {
+        if !child_proof.is_empty() {
+            bail!("Expected empty proof for empty child tree");
+        }
+        return Ok(None);
+    }
+
+    Ok(
+        verify_query(child_proof, &[child_key.to_vec()], child_root)?
+            .pop()
+            .unwrap(),
+    )
+}
+
+/// Splits a nested proof into its parent and child proofs.
+fn split_nested_proof(bytes: &[u8]) -> Result<(&[u8], &[u8])> {
+    if bytes.len() < 4 {
+        bail!("Unexpected end of nested proof");
+    }
+    let parent_len = BigEndian::read_u32(bytes) as usize;
+    if bytes.len() - 4 < parent_len {
+        bail!("Unexpected end of nested proof");
+    }
+
+    Ok(bytes[4..].split_at(parent_len))
+}
+
+/// Checks that a value stored in a parent Merk is a valid child root hash.
+fn child_root_from_value(value: &[u8]) -> Result<Hash> {
+    if value.len() != HASH_LENGTH {
+        bail!("Parent value is not a child root hash");
+    }
+    let mut hash = NULL_HASH;
+    hash.copy_from_slice(value);
+    Ok(hash)
+}
+
+/// Verifies the encoded proof for `query` against the expected hash, returning
+/// the results grouped by query item.
+///
+/// Each entry pairs one of the query's items (in the query's order, after
+/// overlapping items have been merged) with the key/value pairs it selects.
+/// Errors if the proof does not contain enough data to answer every item.
+pub fn verify_structured(
+    bytes: &[u8],
+    query: &Query,
+    expected_hash: Hash,
+) -> Result<Vec<(QueryItem, Vec<(Vec<u8>, Vec<u8>)>)>> {
+    let map = verify(bytes, expected_hash)?;
+
+    query
+        .items()
+        .iter()
+        .map(|item| Ok((item.clone(), map.query_item(item)?)))
+        .collect()
+}
+
+/// Verifies the encoded proof for `query` against the expected hash, returning
+/// the sub-ranges of the query which are proven to contain no keys (see
+/// `Map::gaps`), in key-order.
+///
+/// Range proofs always prove that no keys were omitted between their
+/// results, so this does not require any extra data in the proof - it makes
+/// that guarantee explicit so clients can check the results are complete.
+pub fn verify_gaps(
+    bytes: &[u8],
+    query: &Query,
+    expected_hash: Hash,
+) -> Result<Vec<(Bound<Vec<u8>>, Bound<Vec<u8>>)>> {
+    let map = verify(bytes, expected_hash)?;
+
+    let mut gaps = vec![];
+    for item in query.items() {
+        gaps.extend(map.gaps(item)?);
+    }
+    Ok(gaps)
+}
+
+/// Verifies a proof created by `Merk::prove_uniform_range` against the expected
+/// hash, checking that every key within `range` maps to `value`. Returns the
+/// number of keys in the range.
+///
+/// Errors if the proof does not cover the whole range (including the gaps
+/// between its keys), or if any key in the range has a different value.
+pub fn verify_uniform_range(
+    bytes: &[u8],
+    range: &QueryItem,
+    value: &[u8],
+    expected_hash: Hash,
+) -> Result<usize> {
+    let map = verify(bytes, expected_hash)?;
+
+    let entries = map.query_item(range)?;
+    for (key, entry_value) in entries.iter() {
+        if entry_value.as_slice() != value {
+            bail!("Key {:?} in range does not have the uniform value", key);
+        }
+    }
+
+    Ok(entries.len())
+}
+
+/// Verifies a proof created by `Merk::prove_filtered_range` against the
+/// expected hash, returning the entries within `range` which match
+/// `predicate`.
+///
+/// The predicate is applied by the verifier to every entry in the range, so
+/// this errors if the proof does not cover the whole range (including the
+/// gaps between its keys) rather than trusting the prover's filtering.
+pub fn verify_filtered_range(
+    bytes: &[u8],
+    range: &QueryItem,
+    predicate: impl Fn(&[u8], &[u8]) -> bool,
+    expected_hash: Hash,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let map = verify(bytes, expected_hash)?;
+
+    Ok(map
+        .query_item(range)?
+        .into_iter()
+        .filter(|(key, value)| predicate(key, value))
+        .collect())
+}
+
+/// The two keys proven by `Merk::prove_path_between`, along with their
+/// positions in the tree.
+#[derive(Debug, PartialEq)]
+pub struct ProvenPath {
+    /// The value of the first key.
+    pub a_value: Vec<u8>,
+    /// The value of the second key.
+    pub b_value: Vec<u8>,
+    /// The depth of the first key's node (the number of hops from the root).
+    pub a_depth: usize,
+    /// The depth of the second key's node.
+    pub b_depth: usize,
+    /// The depth of the lowest common ancestor of the two nodes. If one node
+    /// is an ancestor of the other (or the keys are equal), this is the depth
+    /// of that node.
+    pub ancestor_depth: usize,
+}
+
+/// Verifies a proof created by `Merk::prove_path_between` against the
+/// expected hash, returning the values of `a` and `b` and their positions in
+/// the tree.
+///
+/// The positions are taken from the shape of the proof, which is bound to the
+/// shape of the tree by the root hash. Errors if either key is not contained
+/// in the proof.
+pub fn verify_path_between(
+    bytes: &[u8],
+    a: &[u8],
+    b: &[u8],
+    expected_hash: Hash,
+) -> Result<ProvenPath> {
+    // finds the node for `key`, recording the side taken at each level
+    fn find(tree: &Tree, key: &[u8], path: &mut Vec<bool>) -> Option<Vec<u8>> {
+        if let Node::KV(node_key, value) = &tree.node {
+            if node_key.as_slice() == key {
+                return Some(value.clone());
+            }
+        }
+        for left in [true, false].iter() {
+            if let Some(child) = tree.child(*left) {
+                path.push(*left);
+                if let Some(value) = find(&child.tree, key, path) {
+                    return Some(value);
+                }
+                path.pop();
+            }
+        }
+        None
+    }
+
+    let root = execute(Decoder::new(bytes), false, |_| Ok(()))?;
+    if root.hash() != expected_hash {
+        bail!(
+            "Proof did not match expected hash\n\tExpected: {:?}\n\tActual: {:?}",
+            expected_hash,
+            root.hash()
+        );
+    }
+
+    let mut a_path = vec![];
+    let a_value = match find(&root, a, &mut a_path) {
+        None => bail!("Key {:?} is not contained in the proof", a),
+        Some(value) => value,
+    };
+    let mut b_path = vec![];
+    let b_value = match find(&root, b, &mut b_path) {
+        None => bail!("Key {:?} is not contained in the proof", b),
+        Some(value) => value,
+    };
+
+    let ancestor_depth = a_path
+        .iter()
+        .zip(b_path.iter())
+        .take_while(|(a_side, b_side)| a_side == b_side)
+        .count();
+
+    Ok(ProvenPath {
+        a_value,
+        b_value,
+        a_depth: a_path.len(),
+        b_depth: b_path.len(),
+        ancestor_depth,
+    })
+}
+
+/// Verifies the encoded proof for `query` against a set of allowed root
+/// hashes, e.g. the recent roots of the chains on either side of a fork.
+/// Returns the index in `allowed_hashes` of the root the proof matched, along
+/// with the verified data.
+///
+/// The proof is only decoded and executed once. Errors if the reconstructed
+/// root matches none of the allowed hashes, or if the proof does not contain
+/// enough data to answer every item of `query`.
+pub fn verify_against_any(
+    bytes: &[u8],
+    query: &Query,
+    allowed_hashes: &[Hash],
+) -> Result<(usize, Map)> {
+    let ops = Decoder::new(bytes);
+    let mut map_builder = MapBuilder::new();
+
+    let root = execute(ops, true, |node| map_builder.insert(node))?;
+    let root_hash = root.hash();
+
+    let index = match allowed_hashes.iter().position(|hash| *hash == root_hash) {
+        None => bail!(
+            "Proof did not match any allowed hash\n\tActual: {:?}",
+            root_hash
+        ),
+        Some(index) => index,
+    };
+
+    let map = map_builder.build();
+    for item in query.items() {
+        map.query_item(item)?;
+    }
+
+    Ok((index, map))
+}
+
+/// Verifies the encoded proof against the expected hash, and checks that it
+/// answers every item of `requested` in full. Returns all the key/value pairs
+/// selected by the requested query, in key-order.
+///
+/// A server could otherwise answer a narrower query than was asked, producing
+/// a valid proof for a subset of the requested ranges. Here, every requested
+/// range must be fully covered by the proof: each result must be contiguous
+/// with its neighbors, and where a range has no results, the proof must show
+/// the keys surrounding it to prove that it is empty. Entries in the proof
+/// outside of the requested ranges (e.g. boundary keys) are not returned.
+pub fn verify_query_exact(
+    bytes: &[u8],
+    requested: &Query,
+    expected_hash: Hash,
+) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let map = verify(bytes, expected_hash)?;
+
+    let mut output = vec![];
+    for item in requested.items() {
+        match map.query_item(item) {
+            Ok(entries) => output.extend(entries),
+            Err(_) => bail!("Proof does not cover requested query item {:?}", item),
+        }
+    }
+
+    Ok(output)
+}
+
+/// Verifies the encoded proof against the expected hash, then returns an
+/// iterator which lazily yields the key/value pairs it contains, in ascending
+/// key order.
+///
+/// The whole proof is checked before this returns, so the iterator never
+/// yields entries from a proof which does not match `expected_hash`. Unlike
+/// `verify`, it does not tell proven absences apart from omitted data - it
+/// only yields the entries which are present.
+pub fn verified_iter(
+    bytes: &[u8],
+    expected_hash: Hash,
+) -> Result<impl Iterator<Item = (Vec<u8>, Vec<u8>)> + '_> {
+    let root = execute(Decoder::new(bytes), true, |_| Ok(()))?;
+
+    if root.hash() != expected_hash {
+        bail!(
+            "Proof did not match expected hash\n\tExpected: {:?}\n\tActual: {:?}",
+            expected_hash,
+            root.hash()
+        );
+    }
+
+    // the proof already decoded successfully above, and `execute` ensured the
+    // KV pushes are in increasing key order
+    Ok(Decoder::new(bytes).filter_map(|op| match op {
+        Ok(Op::Push(Node::KV(key, value))) => Some((key, value)),
+        _ => None,
+    }))
+}
+
+/// Executes the encoded proof and returns the root hash it reconstructs,
+/// without checking it against an expected hash.
+///
+/// This does not authenticate anything on its own - any well-formed proof
+/// reconstructs some root hash. The result is only meaningful when compared
+/// against a root hash obtained from a trusted source.
+pub fn root_hash(bytes: &[u8]) -> Result<Hash> {
+    let root = execute(Decoder::new(bytes), true, |_| Ok(()))?;
+    Ok(root.hash())
+}
+
+/// Executes the encoded proof and returns the key/value pairs it contains, in
+/// ascending key order, without checking it against an expected hash. The
+/// proof must still decode and be internally consistent (e.g. its entries must
+/// be in key order).
+///
+/// This is a testing convenience, e.g. for asserting that a proof yields
+/// exactly some expected entries. It does NOT authenticate the results against
+/// a trusted root hash, so it must not be used for security decisions - use
+/// `verify` or `verify_query` instead.
+pub fn results(bytes: &[u8]) -> Result<Vec<(Vec<u8>, Vec<u8>)>> {
+    let mut entries = vec![];
+    execute(Decoder::new(bytes), true, |node| {
+        if let Node::KV(key, value) = node {
+            entries.push((key.clone(), value.clone()));
+        }
+        Ok(())
+    })?;
+    Ok(entries)
+}
+
+/// Verifies the encoded proof for a single key against the expected hash, then
+/// returns only the bytes of the value within `range` (e.g. one field of a
+/// fixed-layout value). Returns `None` if the key is proven to be absent.
+///
+/// The whole value is still included in the proof and hashed during
+/// verification; this only narrows what is returned. Errors if `range` does
+/// not fit within the value.
+pub fn verify_key_projected(
+    bytes: &[u8],
+    key: &[u8],
+    expected_hash: Hash,
+    range: Range<usize>,
+) -> Result<Option<Vec<u8>>> {
+    let mut values = verify_query(bytes, &[key.to_vec()], expected_hash)?;
+    let value = match values.pop().unwrap() {
+        None => return Ok(None),
+        Some(value) => value,
+    };
+
+    match value.get(range.clone()) {
+        Some(field) => Ok(Some(field.to_vec())),
+        None => bail!(
+            "Projection {:?} is out of bounds for value of length {}",
+            range,
+            value.len()
+        ),
+    }
+}
+
 /// Verifies the encoded proof with the given query and expected hash.
 ///
 /// Every key in `keys` is checked to either have a key/value pair in the proof,
@@ -297,15 +830,40 @@ pub fn verify_query(
     bytes: &[u8],
     keys: &[Vec<u8>],
     expected_hash: Hash,
+) -> Result<Vec<Option<Vec<u8>>>> {
+    verify_query_limited(bytes, keys, expected_hash, usize::MAX)
+}
+
+/// Verifies the encoded proof with the given query and expected hash, like
+/// `verify_query`, but fails if the proof contains more than `max_results`
+/// key/value pairs (including those only used to prove absences).
+///
+/// The limit is checked as the proof is decoded, so a proof which is too large
+/// is rejected as soon as it exceeds the limit rather than after all of its
+/// entries have been allocated.
+pub fn verify_query_limited(
+    bytes: &[u8],
+    keys: &[Vec<u8>],
+    expected_hash: Hash,
+    max_results: usize,
 ) -> Result<Vec<Option<Vec<u8>>>> {
     let mut key_index = 0;
     let mut last_push = None;
     let mut output = Vec::with_capacity(keys.len());
+    let mut kv_count = 0;
 
     let ops = Decoder::new(bytes);
 
     let root = execute(ops, true, |node| {
         if let Node::KV(key, value) = node {
+            kv_count += 1;
+            if kv_count > max_results {
+                bail!(
+                    "Proof contains more than the maximum of {} results",
+                    max_results
+                );
+            }
+
             loop {
                 if key_index >= keys.len() || *key < keys[key_index] {
                     // TODO: should we error if proof includes unused keys?
@@ -389,6 +947,39 @@ mod test {
         tree
     }
 
+    #[test]
+    fn proof_tree_roundtrip() {
+        let mut tree = make_3_node_tree();
+        let expected_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let (proof, _) = walker
+            .create_proof(vec![vec![3], vec![7]].as_slice())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let proof_tree = ProofTree::decode(bytes.as_slice()).unwrap();
+        assert_eq!(proof_tree.hash(), expected_hash);
+        assert_eq!(proof_tree.node, Node::KVHash(*tree.kv_hash()));
+        assert_eq!(
+            proof_tree.left.as_ref().unwrap().node,
+            Node::KV(vec![3], vec![3])
+        );
+
+        let mut encoded = vec![];
+        proof_tree.encode_into(&mut encoded);
+        assert_eq!(encoded, bytes);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&proof_tree).expect("serialize failed");
+            let decoded: ProofTree = serde_json::from_str(&json).expect("deserialize failed");
+            assert_eq!(decoded, proof_tree);
+            assert_eq!(decoded.hash(), expected_hash);
+        }
+    }
+
     fn verify_test(keys: Vec<Vec<u8>>, expected_result: Vec<Option<Vec<u8>>>) {
         let mut tree = make_3_node_tree();
         let mut walker = RefWalker::new(&mut tree, PanicSource {});
@@ -399,14 +990,114 @@ mod test {
         let mut bytes = vec![];
         encode_into(proof.iter(), &mut bytes);
 
+        #[cfg(not(feature = "hash256"))]
         let expected_hash = [
             65, 23, 96, 10, 165, 42, 240, 100, 206, 125, 192, 81, 44, 89, 119, 39, 35, 215, 211, 24,
         ];
+        #[cfg(feature = "hash256")]
+        let expected_hash = [
+            183, 241, 105, 164, 170, 191, 240, 70, 240, 64, 255, 45, 100, 111, 247, 122, 53, 153,
+            216, 173, 32, 35, 98, 96, 71, 252, 140, 233, 161, 103, 113, 59,
+        ];
         let result =
             verify_query(bytes.as_slice(), keys.as_slice(), expected_hash).expect("verify failed");
         assert_eq!(result, expected_result);
     }
 
+    #[test]
+    fn verify_query_limit() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+
+        let keys: Vec<_> = (10..20).map(seq_key).collect();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_proof(keys.as_slice())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let kv_count = verify(bytes.as_slice(), expected_hash).unwrap().len();
+        assert!(kv_count >= 10);
+
+        let result =
+            verify_query_limited(bytes.as_slice(), keys.as_slice(), expected_hash, kv_count)
+                .expect("verify failed");
+        assert_eq!(result.len(), 10);
+
+        let limit = kv_count - 1;
+        let err = verify_query_limited(bytes.as_slice(), keys.as_slice(), expected_hash, limit)
+            .unwrap_err();
+        assert!(err.to_string().contains("more than the maximum"));
+    }
+
+    #[test]
+    fn rejects_other_hash_length() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+        use ed::Encode;
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+
+        let keys: Vec<_> = (10..20).map(seq_key).collect();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_proof(keys.as_slice())
+            .expect("failed to create proof");
+
+        // encode the proof as a build using the other hash length would
+        let other_length = if HASH_LENGTH == 20 { 32 } else { 20 };
+        let mut bytes = vec![];
+        for op in proof.iter() {
+            let (variant, hash) = match op {
+                Op::Push(Node::Hash(hash)) => (0x01, hash),
+                Op::Push(Node::KVHash(hash)) => (0x02, hash),
+                op => {
+                    op.encode_into(&mut bytes).unwrap();
+                    continue;
+                }
+            };
+            let mut other_hash = hash.to_vec();
+            other_hash.resize(other_length, 0);
+            bytes.push(variant);
+            bytes.extend_from_slice(&other_hash);
+        }
+
+        assert!(verify(bytes.as_slice(), expected_hash).is_err());
+        assert!(verify_query(bytes.as_slice(), keys.as_slice(), expected_hash).is_err());
+    }
+
+    #[test]
+    fn verify_any_version() {
+        let mut tree = make_3_node_tree();
+        let expected_hash = tree.hash();
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+
+        let (proof, _) = walker
+            .create_proof(vec![vec![5]].as_slice())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        assert_eq!(proof_version(&bytes), 0);
+        let map = super::verify_any_version(bytes.as_slice(), expected_hash).unwrap();
+        assert_eq!(map.get(&[5]).unwrap(), Some(&[5][..]));
+
+        let mut versioned = vec![];
+        encode_versioned_into(proof.iter(), &mut versioned);
+        assert_eq!(proof_version(&versioned), PROOF_VERSION);
+        assert_eq!(&versioned[1..], bytes.as_slice());
+        let map = super::verify_any_version(versioned.as_slice(), expected_hash).unwrap();
+        assert_eq!(map.get(&[5]).unwrap(), Some(&[5][..]));
+
+        let mut newer = vec![0x80 | (PROOF_VERSION + 1)];
+        newer.extend_from_slice(&bytes);
+        let err = super::verify_any_version(newer.as_slice(), expected_hash).unwrap_err();
+        assert!(err.to_string().starts_with("Unsupported proof version 2"));
+    }
+
     #[test]
     fn root_verify() {
         verify_test(vec![vec![5]], vec![Some(vec![5])]);
@@ -455,6 +1146,352 @@ mod test {
         verify_test(vec![vec![5], vec![6]], vec![Some(vec![5]), None]);
     }
 
+    #[test]
+    fn structured_verify() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(60)..seq_key(63));
+        query.insert_key(seq_key(10));
+        query.insert_key(vec![0, 0, 0, 0, 0, 0, 0, 20, 1]);
+        query.insert_range_inclusive(seq_key(62)..=seq_key(64));
+
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_query_proof(query.items())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let results =
+            verify_structured(bytes.as_slice(), &query, expected_hash).expect("verify failed");
+        let results: Vec<_> = results
+            .into_iter()
+            .map(|(item, entries)| {
+                let keys: Vec<_> = entries.into_iter().map(|(key, _)| key).collect();
+                (item, keys)
+            })
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                (QueryItem::Key(seq_key(10)), vec![seq_key(10)]),
+                (QueryItem::Key(vec![0, 0, 0, 0, 0, 0, 0, 20, 1]), vec![]),
+                (
+                    QueryItem::RangeInclusive(seq_key(60)..=seq_key(64)),
+                    (60..=64).map(seq_key).collect()
+                ),
+            ]
+        );
+
+        assert!(verify_structured(bytes.as_slice(), &query, [0; HASH_LENGTH]).is_err());
+    }
+
+    #[test]
+    fn verify_gaps_sparse() {
+        use crate::test_utils::{apply_to_memonly, put_entry, seq_key};
+
+        let batch: Vec<_> = (0..30).map(|i| put_entry(i * 10)).collect();
+        let mut tree = apply_to_memonly(None, &batch).unwrap();
+        let expected_hash = tree.hash();
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(95)..seq_key(125));
+        query.insert_key(seq_key(200));
+        query.insert_key(seq_key(205));
+
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_query_proof(query.items())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let gaps = verify_gaps(bytes.as_slice(), &query, expected_hash).expect("verify failed");
+        assert_eq!(
+            gaps,
+            vec![
+                (Bound::Included(seq_key(95)), Bound::Excluded(seq_key(100))),
+                (Bound::Excluded(seq_key(100)), Bound::Excluded(seq_key(110))),
+                (Bound::Excluded(seq_key(110)), Bound::Excluded(seq_key(120))),
+                (Bound::Excluded(seq_key(120)), Bound::Excluded(seq_key(125))),
+                (Bound::Included(seq_key(205)), Bound::Included(seq_key(205))),
+            ]
+        );
+
+        // every key within a gap is proven absent
+        let map = verify(bytes.as_slice(), expected_hash).unwrap();
+        assert_eq!(map.get(&seq_key(105)).unwrap(), None);
+        assert_eq!(map.get(&seq_key(205)).unwrap(), None);
+
+        assert!(verify_gaps(bytes.as_slice(), &query, [0; HASH_LENGTH]).is_err());
+    }
+
+    #[test]
+    fn verify_query_exact_under_coverage() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+
+        let prove = |root: &mut tree::Tree, query: &Query| {
+            let mut walker = RefWalker::new(root, PanicSource {});
+            let (proof, _) = walker
+                .create_query_proof(query.items())
+                .expect("failed to create proof");
+            let mut bytes = vec![];
+            encode_into(proof.iter(), &mut bytes);
+            bytes
+        };
+
+        let mut requested = Query::new();
+        requested.insert_range(seq_key(20)..seq_key(30));
+        requested.insert_key(seq_key(50));
+        requested.insert_key(vec![0, 0, 0, 0, 0, 0, 0, 60, 1]);
+
+        let bytes = prove(&mut tree, &requested);
+        let entries =
+            verify_query_exact(bytes.as_slice(), &requested, expected_hash).expect("verify failed");
+        let keys: Vec<_> = entries.into_iter().map(|(key, _)| key).collect();
+        let mut expected_keys: Vec<_> = (20..30).map(seq_key).collect();
+        expected_keys.push(seq_key(50));
+        assert_eq!(keys, expected_keys);
+
+        // a proof for a narrower range is valid, but not for the request
+        let mut narrower = Query::new();
+        narrower.insert_range(seq_key(20)..seq_key(25));
+        narrower.insert_key(seq_key(50));
+        narrower.insert_key(vec![0, 0, 0, 0, 0, 0, 0, 60, 1]);
+        let bytes = prove(&mut tree, &narrower);
+        assert!(verify(bytes.as_slice(), expected_hash).is_ok());
+        assert!(verify_query_exact(bytes.as_slice(), &requested, expected_hash).is_err());
+
+        // omitting the absence proof for a requested key is caught
+        let mut missing_absence = Query::new();
+        missing_absence.insert_range(seq_key(20)..seq_key(30));
+        missing_absence.insert_key(seq_key(50));
+        let bytes = prove(&mut tree, &missing_absence);
+        assert!(verify_query_exact(bytes.as_slice(), &requested, expected_hash).is_err());
+    }
+
+    #[test]
+    fn proof_keys_only_where_needed() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(20)..seq_key(30));
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_query_proof(query.items())
+            .expect("failed to create proof");
+
+        // nodes outside the range are only included as KV nodes if they bound
+        // it, every other node's key is omitted
+        let kv_keys: Vec<_> = proof
+            .iter()
+            .filter_map(|op| match op {
+                Op::Push(Node::KV(key, _)) => Some(key.clone()),
+                _ => None,
+            })
+            .collect();
+        let mut expected_keys = vec![seq_key(19)];
+        expected_keys.extend((20..30).map(seq_key));
+        expected_keys.push(seq_key(30));
+        assert_eq!(kv_keys, expected_keys);
+
+        // omitting the boundary keys as well still hashes to the root, but the
+        // range can no longer be proven complete
+        let stripped: Vec<_> = proof
+            .into_iter()
+            .map(|op| match op {
+                Op::Push(Node::KV(key, value)) if !query.items()[0].contains(&key) => {
+                    Op::Push(Node::KVHash(kv_hash(&key, &value)))
+                }
+                op => op,
+            })
+            .collect();
+        let mut bytes = vec![];
+        encode_into(stripped.iter(), &mut bytes);
+        assert!(verify(bytes.as_slice(), expected_hash).is_ok());
+        assert!(verify_query_exact(bytes.as_slice(), &query, expected_hash).is_err());
+    }
+
+    #[test]
+    fn verify_against_any_roots() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+
+        let mut query = Query::new();
+        query.insert_range(seq_key(20)..seq_key(25));
+
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_query_proof(query.items())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let allowed = [[1; HASH_LENGTH], expected_hash, [2; HASH_LENGTH]];
+        let (index, map) =
+            verify_against_any(bytes.as_slice(), &query, &allowed).expect("verify failed");
+        assert_eq!(index, 1);
+        assert_eq!(map.query_item(&query.items()[0]).unwrap().len(), 5);
+
+        assert!(verify_against_any(
+            bytes.as_slice(),
+            &query,
+            &[[1; HASH_LENGTH], [2; HASH_LENGTH]]
+        )
+        .is_err());
+        assert!(verify_against_any(bytes.as_slice(), &query, &[]).is_err());
+
+        // the proof must also cover the query
+        let mut wider = Query::new();
+        wider.insert_range(seq_key(20)..seq_key(30));
+        assert!(verify_against_any(bytes.as_slice(), &wider, &allowed).is_err());
+    }
+
+    #[test]
+    fn verified_iter_yields_entries() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let items = vec![QueryItem::Range(seq_key(30)..seq_key(40))];
+        let (proof, _) = walker
+            .create_query_proof(items.as_slice())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let keys: Vec<_> = verified_iter(bytes.as_slice(), expected_hash)
+            .expect("verify failed")
+            .map(|(key, _)| key)
+            .filter(|key| items[0].contains(key))
+            .collect();
+        let expected: Vec<_> = (30..40).map(seq_key).collect();
+        assert_eq!(keys, expected);
+
+        assert!(verified_iter(bytes.as_slice(), [0; HASH_LENGTH]).is_err());
+    }
+
+    #[test]
+    fn results_and_root_hash() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let items = vec![QueryItem::Range(seq_key(30)..seq_key(40))];
+        let (proof, _) = walker
+            .create_query_proof(items.as_slice())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        assert_eq!(root_hash(bytes.as_slice()).unwrap(), expected_hash);
+        let entries = results(bytes.as_slice()).unwrap();
+        let expected: Vec<_> = verified_iter(bytes.as_slice(), expected_hash)
+            .unwrap()
+            .collect();
+        assert_eq!(entries, expected);
+        assert!(entries.iter().any(|(key, _)| *key == seq_key(35)));
+
+        assert!(results(&bytes[..bytes.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn key_projected() {
+        let mut tree = tree::Tree::new(vec![5], (0..16).collect());
+        tree.commit(&mut NoopCommit {}).expect("commit failed");
+        let expected_hash = tree.hash();
+
+        let prove = |tree: &mut tree::Tree, key: u8| {
+            let mut walker = RefWalker::new(tree, PanicSource {});
+            let (proof, _) = walker
+                .create_proof(&[vec![key]])
+                .expect("failed to create proof");
+            let mut bytes = vec![];
+            encode_into(proof.iter(), &mut bytes);
+            bytes
+        };
+
+        let bytes = prove(&mut tree, 5);
+        let field = verify_key_projected(bytes.as_slice(), &[5], expected_hash, 8..16)
+            .expect("verify failed");
+        assert_eq!(field, Some((8..16).collect::<Vec<u8>>()));
+        assert!(verify_key_projected(bytes.as_slice(), &[5], expected_hash, 8..17).is_err());
+        assert!(verify_key_projected(bytes.as_slice(), &[5], [0; HASH_LENGTH], 8..16).is_err());
+
+        let bytes = prove(&mut tree, 6);
+        let field = verify_key_projected(bytes.as_slice(), &[6], expected_hash, 8..16)
+            .expect("verify failed");
+        assert_eq!(field, None);
+    }
+
+    #[test]
+    fn range_verify() {
+        use crate::test_utils::{make_tree_seq, seq_key};
+
+        let mut tree = make_tree_seq(100);
+        let expected_hash = tree.hash();
+        let keys: Vec<Vec<u8>> = tree.iter().map(|(key, _)| key).collect();
+
+        let items = vec![
+            QueryItem::Range(seq_key(10)..seq_key(20)),
+            QueryItem::RangeInclusive(seq_key(20)..=seq_key(25)),
+            QueryItem::Key(seq_key(40)),
+            QueryItem::Key(vec![0, 0, 0, 0, 0, 0, 0, 50, 1]),
+            QueryItem::RangeFrom(seq_key(95)..),
+        ];
+        let mut query = Query::new();
+        for item in items.iter() {
+            query.insert_item(item.clone());
+        }
+
+        let mut walker = RefWalker::new(&mut tree, PanicSource {});
+        let (proof, _) = walker
+            .create_query_proof(query.items())
+            .expect("failed to create proof");
+        let mut bytes = vec![];
+        encode_into(proof.iter(), &mut bytes);
+
+        let map = verify(bytes.as_slice(), expected_hash).expect("verify failed");
+        for item in items.iter() {
+            let expected: Vec<Vec<u8>> = keys
+                .iter()
+                .filter(|key| item.contains(key))
+                .cloned()
+                .collect();
+            let actual: Vec<Vec<u8>> = map
+                .query_item(item)
+                .expect("lookup failed")
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect();
+            assert_eq!(actual, expected);
+        }
+
+        // ranges not covered by the proof
+        assert!(map.query_item(&QueryItem::Key(seq_key(60))).is_err());
+        assert!(map
+            .query_item(&QueryItem::Range(seq_key(5)..seq_key(15)))
+            .is_err());
+
+        assert!(verify(bytes.as_slice(), [0; HASH_LENGTH]).is_err());
+    }
+
     #[test]
     fn height_counting() {
         fn recurse(tree: &super::Tree, expected_height: usize) {
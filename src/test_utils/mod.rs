@@ -2,6 +2,7 @@ mod crash_merk;
 mod temp_merk;
 
 use crate::tree::{Batch, BatchEntry, NoopCommit, Op, PanicSource, Tree, Walker};
+use crate::Merk;
 use byteorder::{BigEndian, WriteBytesExt};
 use rand::prelude::*;
 use std::convert::TryInto;
@@ -153,3 +154,18 @@ pub fn make_tree_seq(node_count: u64) -> Tree {
 
     tree
 }
+
+/// Asserts that `a` and `b` produce identical chunks, which is a stronger check
+/// than comparing root hashes when debugging restores. Panics with the index
+/// of the first differing chunk.
+pub fn assert_chunks_equal(a: &Merk, b: &Merk) {
+    let a_chunks = a.chunks().expect("failed to create chunk producer");
+    let b_chunks = b.chunks().expect("failed to create chunk producer");
+    assert_eq!(a_chunks.len(), b_chunks.len(), "chunk counts differ");
+
+    for (index, (a_chunk, b_chunk)) in a_chunks.into_iter().zip(b_chunks).enumerate() {
+        let a_chunk = a_chunk.expect("failed to produce chunk");
+        let b_chunk = b_chunk.expect("failed to produce chunk");
+        assert!(a_chunk == b_chunk, "chunks differ at index {}", index);
+    }
+}
@@ -15,6 +15,12 @@ pub trait Commit {
     fn prune(&self, _tree: &Tree) -> (bool, bool) {
         (true, true)
     }
+
+    /// Called once per node after writing it, returning whether `write` stored
+    /// the node's value outside of the encoded node.
+    fn stores_value_externally(&self, _tree: &Tree) -> bool {
+        false
+    }
 }
 
 /// A `Commit` implementation which does not write to a store and does not prune
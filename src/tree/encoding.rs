@@ -1,6 +1,11 @@
 use super::Tree;
 use ed::{Decode, Encode};
 
+/// Prefixes the stored encoding of a node whose value is stored outside of it.
+/// A plain encoding starts with the `Option` tag of the left link (0 or 1), so
+/// the two can't be confused.
+pub(crate) const EXTERNAL_VALUE_MARKER: u8 = 0xff;
+
 impl Tree {
     #[inline]
     pub fn encode(&self) -> Vec<u8> {
@@ -34,24 +39,71 @@ impl Tree {
         tree.inner.kv.key = key;
         tree
     }
+
+    /// Encodes the tree for storage with its value stored separately: the
+    /// marker byte followed by the encoding of the tree with an empty value.
+    pub(crate) fn encode_external_into(&self, dest: &mut Vec<u8>) {
+        dest.push(EXTERNAL_VALUE_MARKER);
+        self.encode_into(dest);
+        // the value is the last field of the encoding
+        dest.truncate(dest.len() - self.value().len());
+    }
+
+    /// Decodes a node written by `encode_into` or `encode_external_into`. If
+    /// its value is stored separately, the decoded value is empty and
+    /// `value_is_external` returns `true`.
+    pub(crate) fn decode_stored_into(&mut self, key: Vec<u8>, input: &[u8]) {
+        let (external, input) = strip_external_marker(input);
+        self.decode_into(key, input);
+        self.inner.kv.external = external;
+    }
+
+    /// Like `decode_stored_into`, but returns a new `Tree`.
+    pub(crate) fn decode_stored(key: Vec<u8>, input: &[u8]) -> Tree {
+        let (external, input) = strip_external_marker(input);
+        let mut tree = Tree::decode(key, input);
+        tree.inner.kv.external = external;
+        tree
+    }
+
+    /// Like `decode_stored`, but fails on an invalid encoding instead of
+    /// panicking and leaves the key empty.
+    pub(crate) fn try_decode_stored(input: &[u8]) -> ed::Result<Tree> {
+        let (external, input) = strip_external_marker(input);
+        let mut tree: Tree = Decode::decode(input)?;
+        tree.inner.kv.external = external;
+        Ok(tree)
+    }
+}
+
+/// Returns whether a stored node is marked as having its value stored
+/// separately, along with the node's encoding without the marker.
+fn strip_external_marker(input: &[u8]) -> (bool, &[u8]) {
+    match input.split_first() {
+        Some((&EXTERNAL_VALUE_MARKER, rest)) => (true, rest),
+        _ => (false, input),
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::Link;
+    use super::super::{Link, HASH_LENGTH};
     use super::*;
 
     #[test]
     fn encode_leaf_tree() {
-        let tree = Tree::from_fields(vec![0], vec![1], [55; 20], None, None);
-        assert_eq!(tree.encoding_length(), 23);
-        assert_eq!(
-            tree.encode(),
-            vec![
-                0, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
-                55, 1,
-            ]
-        );
+        let tree = Tree::from_fields(vec![0], vec![1], [55; HASH_LENGTH], None, None);
+        #[cfg(not(feature = "hash256"))]
+        let expected = vec![
+            0, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 1,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = vec![
+            0, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 1,
+        ];
+        assert_eq!(tree.encoding_length(), expected.len());
+        assert_eq!(tree.encode(), expected);
     }
 
     #[test]
@@ -60,7 +112,7 @@ mod tests {
         let tree = Tree::from_fields(
             vec![0],
             vec![1],
-            [55; 20],
+            [55; HASH_LENGTH],
             Some(Link::Modified {
                 pending_writes: 1,
                 child_heights: (123, 124),
@@ -76,22 +128,28 @@ mod tests {
         let tree = Tree::from_fields(
             vec![0],
             vec![1],
-            [55; 20],
+            [55; HASH_LENGTH],
             Some(Link::Loaded {
-                hash: [66; 20],
+                hash: [66; HASH_LENGTH],
                 child_heights: (123, 124),
                 tree: Tree::new(vec![2], vec![3]),
             }),
             None,
         );
-        assert_eq!(
-            tree.encode(),
-            vec![
-                1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
-                66, 66, 123, 124, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
-                55, 55, 55, 55, 55, 1
-            ]
-        );
+        #[cfg(not(feature = "hash256"))]
+        let expected = vec![
+            1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
+            66, 123, 124, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 1,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = vec![
+            1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
+            66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 123, 124, 0, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 1,
+        ];
+        assert_eq!(tree.encode(), expected);
     }
 
     #[test]
@@ -99,22 +157,28 @@ mod tests {
         let tree = Tree::from_fields(
             vec![0],
             vec![1],
-            [55; 20],
+            [55; HASH_LENGTH],
             Some(Link::Uncommitted {
-                hash: [66; 20],
+                hash: [66; HASH_LENGTH],
                 child_heights: (123, 124),
                 tree: Tree::new(vec![2], vec![3]),
             }),
             None,
         );
-        assert_eq!(
-            tree.encode(),
-            vec![
-                1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
-                66, 66, 123, 124, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
-                55, 55, 55, 55, 55, 1
-            ]
-        );
+        #[cfg(not(feature = "hash256"))]
+        let expected = vec![
+            1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
+            66, 123, 124, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 1,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = vec![
+            1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
+            66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 123, 124, 0, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 1,
+        ];
+        assert_eq!(tree.encode(), expected);
     }
 
     #[test]
@@ -122,30 +186,42 @@ mod tests {
         let tree = Tree::from_fields(
             vec![0],
             vec![1],
-            [55; 20],
+            [55; HASH_LENGTH],
             Some(Link::Reference {
-                hash: [66; 20],
+                hash: [66; HASH_LENGTH],
                 child_heights: (123, 124),
                 key: vec![2],
             }),
             None,
         );
-        assert_eq!(tree.encoding_length(), 47);
-        assert_eq!(
-            tree.encode(),
-            vec![
-                1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
-                66, 66, 123, 124, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
-                55, 55, 55, 55, 55, 1
-            ]
-        );
+        #[cfg(not(feature = "hash256"))]
+        let expected = vec![
+            1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
+            66, 123, 124, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 1,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = vec![
+            1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
+            66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 123, 124, 0, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 1,
+        ];
+        assert_eq!(tree.encoding_length(), expected.len());
+        assert_eq!(tree.encode(), expected);
     }
 
     #[test]
     fn decode_leaf_tree() {
+        #[cfg(not(feature = "hash256"))]
         let bytes = vec![
             0, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 1,
         ];
+        #[cfg(feature = "hash256")]
+        let bytes = vec![
+            0, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 1,
+        ];
         let tree = Tree::decode(vec![0], bytes.as_slice());
         assert_eq!(tree.key(), &[0]);
         assert_eq!(tree.value(), &[1]);
@@ -153,11 +229,19 @@ mod tests {
 
     #[test]
     fn decode_reference_tree() {
+        #[cfg(not(feature = "hash256"))]
         let bytes = vec![
             1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
             66, 123, 124, 0, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
             55, 55, 55, 1,
         ];
+        #[cfg(feature = "hash256")]
+        let bytes = vec![
+            1, 1, 2, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66,
+            66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 66, 123, 124, 0, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 1,
+        ];
         let tree = Tree::decode(vec![0], bytes.as_slice());
         assert_eq!(tree.key(), &[0]);
         assert_eq!(tree.value(), &[1]);
@@ -169,9 +253,27 @@ mod tests {
         {
             assert_eq!(*key, [2]);
             assert_eq!(*child_heights, (123 as u8, 124 as u8));
-            assert_eq!(*hash, [66 as u8; 20]);
+            assert_eq!(*hash, [66 as u8; HASH_LENGTH]);
         } else {
             panic!("Expected Link::Reference");
         }
     }
+
+    #[test]
+    fn external_value_roundtrip() {
+        let tree = Tree::new(vec![0], vec![1, 2, 3]);
+        let mut bytes = vec![];
+        tree.encode_external_into(&mut bytes);
+        assert_eq!(bytes[0], EXTERNAL_VALUE_MARKER);
+        assert_eq!(bytes.len(), tree.encoding_length() + 1 - tree.value().len());
+
+        let decoded = Tree::decode_stored(vec![0], bytes.as_slice());
+        assert!(decoded.value_is_external());
+        assert!(decoded.value().is_empty());
+        assert_eq!(decoded.kv_hash(), tree.kv_hash());
+
+        let decoded = Tree::decode_stored(vec![0], tree.encode().as_slice());
+        assert!(!decoded.value_is_external());
+        assert_eq!(decoded.value(), &[1, 2, 3]);
+    }
 }
@@ -2,9 +2,27 @@ use std::convert::TryFrom;
 
 use blake2_rfc::blake2b::Blake2b;
 
-/// The length of a `Hash` (in bytes).
+#[cfg(all(feature = "hash160", feature = "hash256"))]
+compile_error!("features `hash160` and `hash256` are mutually exclusive");
+
+/// The length of a `Hash` (in bytes), selected by the `hash160` (the default)
+/// or `hash256` feature.
+///
+/// 20-byte hashes keep nodes and proofs small, but only provide about 80 bits
+/// of collision resistance, so an attacker with enough compute could find two
+/// different trees with the same root hash. 32-byte hashes provide about 128
+/// bits, at the cost of 12 extra bytes for every hash stored in a node or
+/// included in a proof. The length is part of the encoding of nodes, proofs
+/// and chunks, so stores and proofs created with one length can not be read
+/// or verified by a build using the other.
+#[cfg(not(feature = "hash256"))]
 pub const HASH_LENGTH: usize = 20;
 
+/// The length of a `Hash` (in bytes), selected by the `hash160` (the default)
+/// or `hash256` feature. See the `hash160` variant for the tradeoff.
+#[cfg(feature = "hash256")]
+pub const HASH_LENGTH: usize = 32;
+
 /// A zero-filled `Hash`.
 pub const NULL_HASH: Hash = [0; HASH_LENGTH];
 
@@ -37,6 +55,16 @@ pub fn kv_hash(key: &[u8], value: &[u8]) -> Hash {
     hash
 }
 
+/// Computes the hash the tree stores for a key/value pair, exactly as it is
+/// computed when the pair is applied. This can be used to derive identifiers
+/// consistent with the tree's hashing without applying the pair.
+///
+/// **NOTE:** This will panic if the key is longer than 255 bytes, or the value
+/// is longer than 65,535 bytes.
+pub fn leaf_hash(key: &[u8], value: &[u8]) -> Hash {
+    kv_hash(key, value)
+}
+
 /// Hashes a node based on the hash of its key/value pair, the hash of its left
 /// child (if any), and the hash of its right child (if any).
 pub fn node_hash(kv: &Hash, left: &Hash, right: &Hash) -> Hash {
@@ -53,3 +81,25 @@ pub fn node_hash(kv: &Hash, left: &Hash, right: &Hash) -> Hash {
     hash.copy_from_slice(res.as_bytes());
     hash
 }
+
+/// Hashes the shape of a node based on its key, its height, and the structure
+/// hashes of its left and right children (if any). Values are not included.
+///
+/// This is only meant as a diagnostic fingerprint for comparing the shape of
+/// two trees, and is **not** a security primitive - nothing commits to it, so
+/// it should never be used in place of the node hash.
+pub(crate) fn structure_hash(key: &[u8], height: u8, left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Blake2b::new(HASH_LENGTH);
+
+    let key_length = u8::try_from(key.len()).expect("key must be less than 256 bytes");
+    hasher.update(&key_length.to_be_bytes());
+    hasher.update(key);
+    hasher.update(&height.to_be_bytes());
+    hasher.update(left);
+    hasher.update(right);
+
+    let res = hasher.finalize();
+    let mut hash: Hash = Default::default();
+    hash.copy_from_slice(res.as_bytes());
+    hash
+}
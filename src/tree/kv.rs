@@ -12,6 +12,12 @@ pub struct KV {
     pub(super) key: Vec<u8>,
     pub(super) value: Vec<u8>,
     pub(super) hash: Hash,
+    /// Whether the value was stored outside of the encoded node when the `KV`
+    /// was last read from or written to a store. Not part of the encoding.
+    pub(super) external: bool,
+    /// Whether the value was replaced since the `KV` was last read from or
+    /// written to a store. Not part of the encoding.
+    pub(super) value_modified: bool,
 }
 
 impl KV {
@@ -20,14 +26,20 @@ impl KV {
     pub fn new(key: Vec<u8>, value: Vec<u8>) -> Self {
         // TODO: length checks?
         let hash = kv_hash(key.as_slice(), value.as_slice());
-        KV { key, value, hash }
+        KV::from_fields(key, value, hash)
     }
 
     /// Creates a new `KV` with the given key, value, and hash. The hash is not
     /// checked to be correct for the given key/value.
     #[inline]
     pub fn from_fields(key: Vec<u8>, value: Vec<u8>, hash: Hash) -> Self {
-        KV { key, value, hash }
+        KV {
+            key,
+            value,
+            hash,
+            external: false,
+            value_modified: false,
+        }
     }
 
     /// Replaces the `KV`'s value with the given value, updates the hash, and
@@ -37,6 +49,7 @@ impl KV {
         // TODO: length check?
         self.value = value;
         self.hash = kv_hash(self.key(), self.value());
+        self.value_modified = true;
         self
     }
 
@@ -83,11 +96,7 @@ impl Encode for KV {
 impl Decode for KV {
     #[inline]
     fn decode<R: Read>(input: R) -> Result<Self> {
-        let mut kv = KV {
-            key: Vec::with_capacity(0),
-            value: Vec::with_capacity(128),
-            hash: NULL_HASH,
-        };
+        let mut kv = KV::from_fields(Vec::with_capacity(0), Vec::with_capacity(128), NULL_HASH);
         KV::decode_into(&mut kv, input)?;
         Ok(kv)
     }
@@ -101,6 +110,9 @@ impl Decode for KV {
         self.value.clear();
         input.read_to_end(self.value.as_mut())?;
 
+        self.external = false;
+        self.value_modified = false;
+
         Ok(())
     }
 }
@@ -125,5 +137,6 @@ mod test {
         assert_eq!(kv.key(), &[1, 2, 3]);
         assert_eq!(kv.value(), &[7, 8, 9]);
         assert_ne!(kv.hash(), &super::super::hash::NULL_HASH);
+        assert!(kv.value_modified);
     }
 }
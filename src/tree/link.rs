@@ -3,7 +3,7 @@ use std::io::{Read, Write};
 
 use ed::{Decode, Encode, Result, Terminated};
 
-use super::hash::Hash;
+use super::hash::{Hash, HASH_LENGTH};
 use super::Tree;
 
 // TODO: optimize memory footprint
@@ -249,10 +249,10 @@ impl Encode for Link {
         debug_assert!(self.key().len() < 256, "Key length must be less than 256");
 
         Ok(match self {
-            Link::Reference { key, .. } => 1 + key.len() + 20 + 2,
+            Link::Reference { key, .. } => 1 + key.len() + HASH_LENGTH + 2,
             Link::Modified { .. } => panic!("No encoding for Link::Modified"),
-            Link::Uncommitted { tree, .. } => 1 + tree.key().len() + 20 + 2,
-            Link::Loaded { tree, .. } => 1 + tree.key().len() + 20 + 2,
+            Link::Uncommitted { tree, .. } => 1 + tree.key().len() + HASH_LENGTH + 2,
+            Link::Loaded { tree, .. } => 1 + tree.key().len() + HASH_LENGTH + 2,
         })
     }
 }
@@ -318,7 +318,7 @@ fn read_u8<R: Read>(mut input: R) -> Result<u8> {
 
 #[cfg(test)]
 mod test {
-    use super::super::hash::NULL_HASH;
+    use super::super::hash::{HASH_LENGTH, NULL_HASH};
     use super::super::Tree;
     use super::*;
 
@@ -380,7 +380,7 @@ mod test {
         assert!(!reference.is_uncommitted());
         assert!(!reference.is_stored());
         assert!(reference.tree().is_none());
-        assert_eq!(reference.hash(), &[0; 20]);
+        assert_eq!(reference.hash(), &[0; HASH_LENGTH]);
         assert_eq!(reference.height(), 1);
         assert!(reference.into_reference().is_reference());
 
@@ -396,7 +396,7 @@ mod test {
         assert!(uncommitted.is_uncommitted());
         assert!(!uncommitted.is_stored());
         assert!(uncommitted.tree().is_some());
-        assert_eq!(uncommitted.hash(), &[0; 20]);
+        assert_eq!(uncommitted.hash(), &[0; HASH_LENGTH]);
         assert_eq!(uncommitted.height(), 1);
 
         assert!(!loaded.is_reference());
@@ -404,7 +404,7 @@ mod test {
         assert!(!loaded.is_uncommitted());
         assert!(loaded.is_stored());
         assert!(loaded.tree().is_some());
-        assert_eq!(loaded.hash(), &[0; 20]);
+        assert_eq!(loaded.hash(), &[0; HASH_LENGTH]);
         assert_eq!(loaded.height(), 1);
         assert!(loaded.into_reference().is_reference());
     }
@@ -435,7 +435,7 @@ mod test {
     #[should_panic]
     fn uncommitted_into_reference() {
         Link::Uncommitted {
-            hash: [1; 20],
+            hash: [1; HASH_LENGTH],
             child_heights: (1, 1),
             tree: Tree::new(vec![0], vec![1]),
         }
@@ -447,19 +447,23 @@ mod test {
         let link = Link::Reference {
             key: vec![1, 2, 3],
             child_heights: (123, 124),
-            hash: [55; 20],
+            hash: [55; HASH_LENGTH],
         };
-        assert_eq!(link.encoding_length().unwrap(), 26);
+        #[cfg(not(feature = "hash256"))]
+        let expected = vec![
+            3, 1, 2, 3, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 123, 124,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = vec![
+            3, 1, 2, 3, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
+            55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 123, 124,
+        ];
+        assert_eq!(link.encoding_length().unwrap(), expected.len());
 
         let mut bytes = vec![];
         link.encode_into(&mut bytes).unwrap();
-        assert_eq!(
-            bytes,
-            vec![
-                3, 1, 2, 3, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55, 55,
-                55, 55, 123, 124
-            ]
-        );
+        assert_eq!(bytes, expected);
     }
 
     #[test]
@@ -468,7 +472,7 @@ mod test {
         let link = Link::Reference {
             key: vec![123; 300],
             child_heights: (123, 124),
-            hash: [55; 20],
+            hash: [55; HASH_LENGTH],
         };
         let mut bytes = vec![];
         link.encode_into(&mut bytes).unwrap();
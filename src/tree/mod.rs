@@ -15,7 +15,8 @@ use ed::{Decode, Encode};
 
 use super::error::Result;
 pub use commit::{Commit, NoopCommit};
-pub use hash::{kv_hash, node_hash, Hash, HASH_LENGTH, NULL_HASH};
+pub(crate) use hash::structure_hash;
+pub use hash::{kv_hash, leaf_hash, node_hash, Hash, HASH_LENGTH, NULL_HASH};
 use kv::KV;
 pub use link::Link;
 pub use ops::{Batch, BatchEntry, Op, PanicSource};
@@ -311,6 +312,28 @@ impl Tree {
         self
     }
 
+    /// Replaces the root node's value without recomputing the kv hash. Used
+    /// when the value is stored separately from the encoded node, so the
+    /// stored hash is already correct for it.
+    #[inline]
+    pub(crate) fn set_value_unchecked(&mut self, value: Vec<u8>) {
+        self.inner.kv.value = value;
+    }
+
+    /// Returns whether the root node's value was stored outside of the encoded
+    /// node when it was last read from or written to a store.
+    #[inline]
+    pub(crate) fn value_is_external(&self) -> bool {
+        self.inner.kv.external
+    }
+
+    /// Returns whether the root node's value was replaced since it was last
+    /// read from or written to a store.
+    #[inline]
+    pub(crate) fn value_modified(&self) -> bool {
+        self.inner.kv.value_modified
+    }
+
     // TODO: add compute_hashes method
 
     /// Called to finalize modifications to a tree, recompute its hashes, and
@@ -362,6 +385,8 @@ impl Tree {
         }
 
         c.write(&self)?;
+        self.inner.kv.external = c.stores_value_externally(&self);
+        self.inner.kv.value_modified = false;
 
         let (prune_left, prune_right) = c.prune(&self);
         if prune_left {
@@ -499,26 +524,34 @@ mod test {
     fn child_hash() {
         let mut tree = Tree::new(vec![0], vec![1]).attach(true, Some(Tree::new(vec![2], vec![3])));
         tree.commit(&mut NoopCommit {}).expect("commit failed");
-        assert_eq!(
-            tree.child_hash(true),
-            &[
-                23, 66, 77, 65, 141, 140, 245, 11, 53, 36, 157, 248, 208, 6, 160, 222, 213, 143,
-                249, 85
-            ]
-        );
+        #[cfg(not(feature = "hash256"))]
+        let expected = [
+            23, 66, 77, 65, 141, 140, 245, 11, 53, 36, 157, 248, 208, 6, 160, 222, 213, 143, 249,
+            85,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = [
+            100, 181, 174, 190, 126, 127, 236, 85, 67, 214, 206, 186, 5, 68, 54, 37, 112, 63, 40,
+            198, 136, 199, 172, 163, 222, 177, 113, 157, 153, 76, 92, 167,
+        ];
+        assert_eq!(tree.child_hash(true), &expected);
         assert_eq!(tree.child_hash(false), &NULL_HASH);
     }
 
     #[test]
     fn hash() {
         let tree = Tree::new(vec![0], vec![1]);
-        assert_eq!(
-            tree.hash(),
-            [
-                9, 242, 41, 142, 47, 227, 251, 242, 27, 29, 140, 24, 184, 111, 118, 188, 20, 58,
-                223, 197
-            ]
-        );
+        #[cfg(not(feature = "hash256"))]
+        let expected = [
+            9, 242, 41, 142, 47, 227, 251, 242, 27, 29, 140, 24, 184, 111, 118, 188, 20, 58, 223,
+            197,
+        ];
+        #[cfg(feature = "hash256")]
+        let expected = [
+            20, 148, 172, 120, 209, 29, 44, 201, 154, 20, 73, 142, 220, 58, 202, 191, 113, 49, 8,
+            111, 151, 66, 137, 183, 57, 129, 60, 236, 111, 205, 110, 73,
+        ];
+        assert_eq!(tree.hash(), expected);
     }
 
     #[test]
@@ -52,13 +52,24 @@ where
     pub fn apply_to(
         maybe_tree: Option<Self>,
         batch: &Batch,
+    ) -> Result<(Option<Tree>, LinkedList<Vec<u8>>)> {
+        Self::apply_to_tracked(maybe_tree, batch, &mut vec![])
+    }
+
+    /// Like `apply_to`, but also adds the keys of deleted nodes whose values
+    /// were stored outside of the node (see `Tree::value_is_external`) to
+    /// `external_deletes`.
+    pub(crate) fn apply_to_tracked(
+        maybe_tree: Option<Self>,
+        batch: &Batch,
+        external_deletes: &mut Vec<Vec<u8>>,
     ) -> Result<(Option<Tree>, LinkedList<Vec<u8>>)> {
         let (maybe_walker, deleted_keys) = if batch.is_empty() {
             (maybe_tree, LinkedList::default())
         } else {
             match maybe_tree {
                 None => return Ok((Self::build(batch)?, LinkedList::default())),
-                Some(tree) => tree.apply(batch)?,
+                Some(tree) => tree.apply(batch, external_deletes)?,
             }
         };
 
@@ -95,7 +106,11 @@ where
     /// `Walker<S>::apply`_to, but requires a populated tree.
     ///
     /// Keys in batch must be sorted and unique.
-    fn apply(self, batch: &Batch) -> Result<(Option<Self>, LinkedList<Vec<u8>>)> {
+    fn apply(
+        self,
+        batch: &Batch,
+        external_deletes: &mut Vec<Vec<u8>>,
+    ) -> Result<(Option<Self>, LinkedList<Vec<u8>>)> {
         // binary search to see if this node's key is in the batch, and to split
         // into left and right batches
         let search = batch.binary_search_by(|(key, _op)| key.as_slice().cmp(self.tree().key()));
@@ -111,14 +126,20 @@ where
                         maybe_tree.map(|tree| Self::new(tree, source.clone()))
                     };
                     let key = self.tree().key().to_vec();
+                    if self.tree().value_is_external() {
+                        external_deletes.push(key.clone());
+                    }
                     let maybe_tree = self.remove()?;
 
                     let (maybe_tree, mut deleted_keys) =
-                        Self::apply_to(maybe_tree, &batch[..index])?;
+                        Self::apply_to_tracked(maybe_tree, &batch[..index], external_deletes)?;
                     let maybe_walker = wrap(maybe_tree);
 
-                    let (maybe_tree, mut deleted_keys_right) =
-                        Self::apply_to(maybe_walker, &batch[index + 1..])?;
+                    let (maybe_tree, mut deleted_keys_right) = Self::apply_to_tracked(
+                        maybe_walker,
+                        &batch[index + 1..],
+                        external_deletes,
+                    )?;
                     let maybe_walker = wrap(maybe_tree);
 
                     deleted_keys.append(&mut deleted_keys_right);
@@ -136,7 +157,7 @@ where
             Err(index) => (index, false),
         };
 
-        tree.recurse(batch, mid, exclusive)
+        tree.recurse(batch, mid, exclusive, external_deletes)
     }
 
     /// Recursively applies operations to the tree's children (if there are any
@@ -149,6 +170,7 @@ where
         batch: &Batch,
         mid: usize,
         exclusive: bool,
+        external_deletes: &mut Vec<Vec<u8>>,
     ) -> Result<(Option<Self>, LinkedList<Vec<u8>>)> {
         let left_batch = &batch[..mid];
         let right_batch = if exclusive {
@@ -161,7 +183,8 @@ where
 
         let tree = if !left_batch.is_empty() {
             self.walk(true, |maybe_left| {
-                let (maybe_left, mut deleted_keys_left) = Self::apply_to(maybe_left, left_batch)?;
+                let (maybe_left, mut deleted_keys_left) =
+                    Self::apply_to_tracked(maybe_left, left_batch, external_deletes)?;
                 deleted_keys.append(&mut deleted_keys_left);
                 Ok(maybe_left)
             })?
@@ -172,7 +195,7 @@ where
         let tree = if !right_batch.is_empty() {
             tree.walk(false, |maybe_right| {
                 let (maybe_right, mut deleted_keys_right) =
-                    Self::apply_to(maybe_right, right_batch)?;
+                    Self::apply_to_tracked(maybe_right, right_batch, external_deletes)?;
                 deleted_keys.append(&mut deleted_keys_right);
                 Ok(maybe_right)
             })?
@@ -323,10 +346,10 @@ mod test {
         let tree = Tree::from_fields(
             b"foo".to_vec(),
             b"bar".to_vec(),
-            [123; 20],
+            [123; HASH_LENGTH],
             None,
             Some(Link::Loaded {
-                hash: [123; 20],
+                hash: [123; HASH_LENGTH],
                 child_heights: (0, 0),
                 tree: Tree::new(b"foo2".to_vec(), b"bar2".to_vec()),
             }),